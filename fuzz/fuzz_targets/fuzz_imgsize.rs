@@ -3,5 +3,9 @@
 use libfuzzer_sys::fuzz_target;
 
 fuzz_target!(|data: &[u8]| {
-    let _ = imgsize::read_bytes(data);
+    // Reading never panics, and the borrowed and owned readers agree.
+    let owned = imgsize::read_bytes(data);
+    let borrowed = imgsize::read_bytes_ref(data).map(imgsize::ImageMetadata::from);
+    assert_eq!(owned, borrowed);
+    let _ = imgsize::validate(data);
 });
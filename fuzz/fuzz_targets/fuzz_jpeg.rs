@@ -0,0 +1,81 @@
+#![no_main]
+
+//! Builds JPEG data out of segments, so that the fuzzer spends its time
+//! inside them rather than failing to get past the SOI marker.
+
+use arbitrary::Arbitrary;
+use imgsize::jpeg::{self, Segments};
+use imgsize::{ReadOptions, Strictness};
+use libfuzzer_sys::fuzz_target;
+
+#[derive(Debug, Arbitrary)]
+struct Input {
+    segments: Vec<Segment>,
+    scan: Vec<u8>,
+}
+
+#[derive(Debug, Arbitrary)]
+struct Segment {
+    marker: u8,
+    identifier: Identifier,
+    data: Vec<u8>,
+}
+
+/// The identifiers at the start of the APPn segments that the reader decodes.
+#[derive(Debug, Arbitrary)]
+enum Identifier {
+    None,
+    Jfif,
+    Jfxx,
+    Exif,
+    Xmp,
+    Icc,
+    Mpf,
+    Adobe,
+}
+
+impl Identifier {
+    fn bytes(&self) -> &'static [u8] {
+        match self {
+            Identifier::None => b"",
+            Identifier::Jfif => b"JFIF\0",
+            Identifier::Jfxx => b"JFXX\0",
+            Identifier::Exif => b"Exif\0\0",
+            Identifier::Xmp => b"http://ns.adobe.com/xap/1.0/\0",
+            Identifier::Icc => b"ICC_PROFILE\0",
+            Identifier::Mpf => b"MPF\0",
+            Identifier::Adobe => b"Adobe",
+        }
+    }
+}
+
+fuzz_target!(|input: Input| {
+    let mut buf = vec![0xff, 0xd8];
+    for segment in &input.segments {
+        let data = [segment.identifier.bytes(), &segment.data].concat();
+        let Ok(len) = u16::try_from(data.len() + 2) else {
+            continue;
+        };
+        buf.extend_from_slice(&[0xff, segment.marker]);
+        buf.extend_from_slice(&len.to_be_bytes());
+        buf.extend_from_slice(&data);
+    }
+    buf.extend_from_slice(&input.scan);
+
+    for strictness in [Strictness::Strict, Strictness::Lenient] {
+        let options = ReadOptions::new()
+            .exif(true)
+            .xmp(true)
+            .icc_profile(true)
+            .scan_for_dnl(true)
+            .strictness(strictness);
+        let _ = imgsize::read_bytes_with(&options, &buf);
+    }
+    let _ = imgsize::validate(&buf);
+    let _ = jpeg::read_thumbnails(&buf);
+    for segment in Segments::new(&buf).through_scans(true) {
+        if segment.is_err() {
+            break;
+        }
+    }
+});
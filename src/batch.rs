@@ -0,0 +1,486 @@
+//! Reading many images at once, and finding the images in a directory tree.
+//!
+//! With the `rayon` feature, the images are read in parallel on rayon's
+//! global thread pool; without it, they are read one after another.
+//! [`read_dir_recursive_iter`] reads them one at a time as it finds them,
+//! instead of finding them all first.
+
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::{read_file_with, Error, ImageMetadata, ReadOptions};
+
+/// The file extensions that [`DirOptions`] looks for unless told otherwise.
+const DEFAULT_EXTENSIONS: &[&str] = &["jpg", "jpeg", "jpe", "png"];
+
+/// Which files [`read_dir_recursive`] reads, and how.
+///
+/// # Examples
+///
+/// ```
+/// use pb_imgsize::{DirOptions, ReadOptions};
+///
+/// // Only PNG files whose names start with "icon-", following symlinks.
+/// let options = DirOptions::new()
+///     .extensions(["png"])
+///     .pattern("icon-*")
+///     .follow_symlinks(true)
+///     .read_options(ReadOptions::dimensions_only());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirOptions {
+    pub(crate) extensions: Vec<String>,
+    pub(crate) pattern: Option<String>,
+    pub(crate) follow_symlinks: bool,
+    pub(crate) read_options: ReadOptions,
+}
+
+impl Default for DirOptions {
+    fn default() -> Self {
+        DirOptions {
+            extensions: DEFAULT_EXTENSIONS.iter().map(|e| e.to_string()).collect(),
+            pattern: None,
+            follow_symlinks: false,
+            read_options: ReadOptions::default(),
+        }
+    }
+}
+
+impl DirOptions {
+    /// Options that read every file ending in `.jpg`, `.jpeg`, `.jpe` or
+    /// `.png`, without following symlinks.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The file extensions to read, without the dot. Case doesn't matter.
+    /// An empty list reads every file, whatever its extension.
+    pub fn extensions<S: Into<String>>(mut self, extensions: impl IntoIterator<Item = S>) -> Self {
+        self.extensions = extensions.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// A pattern that file names must match as well, where `*` matches any
+    /// run of characters and `?` any single character. It is matched against
+    /// the name only, not the directories above it. In a name that isn't
+    /// valid Unicode, each invalid sequence counts as one character.
+    pub fn pattern(mut self, pattern: impl Into<String>) -> Self {
+        self.pattern = Some(pattern.into());
+        self
+    }
+
+    /// Whether to follow symlinks to files and directories. A directory that
+    /// has already been visited, through a symlink loop or otherwise, is not
+    /// visited again.
+    pub fn follow_symlinks(mut self, follow_symlinks: bool) -> Self {
+        self.follow_symlinks = follow_symlinks;
+        self
+    }
+
+    /// The options to read each image with.
+    pub fn read_options(mut self, read_options: ReadOptions) -> Self {
+        self.read_options = read_options;
+        self
+    }
+
+    /// Whether a file with this name is wanted.
+    fn matches(&self, name: &OsStr) -> bool {
+        let name = name.to_string_lossy();
+        let extension_matches = self.extensions.is_empty()
+            || name.rsplit_once('.').is_some_and(|(_, extension)| {
+                self.extensions
+                    .iter()
+                    .any(|e| e.eq_ignore_ascii_case(extension))
+            });
+        let pattern_matches = self
+            .pattern
+            .as_ref()
+            .is_none_or(|pattern| glob_match(pattern, &name));
+        extension_matches && pattern_matches
+    }
+}
+
+/// Reads the metadata of many images, in parallel with the `rayon` feature.
+/// The results are in the same order as the paths.
+///
+/// # Examples
+///
+/// ```
+/// let results = pb_imgsize::read_many(&["src/buttercups.jpg", "src/missing.png"]);
+/// assert_eq!(results[0].as_ref().unwrap().width, 512);
+/// assert!(results[1].is_err());
+/// ```
+pub fn read_many<P: AsRef<Path> + Sync>(paths: &[P]) -> Vec<Result<ImageMetadata, Error>> {
+    read_many_with(&ReadOptions::default(), paths)
+}
+
+/// Reads the metadata of many images using the given options, in parallel
+/// with the `rayon` feature. The results are in the same order as the paths.
+pub fn read_many_with<P: AsRef<Path> + Sync>(
+    options: &ReadOptions,
+    paths: &[P],
+) -> Vec<Result<ImageMetadata, Error>> {
+    #[cfg(feature = "rayon")]
+    {
+        use rayon::prelude::*;
+        paths
+            .par_iter()
+            .map(|path| read_file_with(options, path))
+            .collect()
+    }
+    #[cfg(not(feature = "rayon"))]
+    {
+        paths
+            .iter()
+            .map(|path| read_file_with(options, path))
+            .collect()
+    }
+}
+
+/// Finds the images in a directory and everything below it, as chosen by the
+/// options, and reads their metadata, in parallel with the `rayon` feature.
+///
+/// Each file's result is paired with its path, sorted by path. A
+/// subdirectory that can't be listed is reported as an error for that
+/// directory, and the rest of the tree is still read; only a top-level
+/// directory that can't be listed fails the whole call.
+///
+/// # Examples
+///
+/// ```
+/// use pb_imgsize::{read_dir_recursive, DirOptions};
+///
+/// let results = read_dir_recursive("src", &DirOptions::new())?;
+/// let names: Vec<_> = results.iter().map(|(path, _)| path.file_name().unwrap()).collect();
+/// assert_eq!(names, ["buttercups.jpg", "invalid01.png", "watercolors.png"]);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn read_dir_recursive(
+    dir: impl AsRef<Path>,
+    options: &DirOptions,
+) -> std::io::Result<Vec<(PathBuf, Result<ImageMetadata, Error>)>> {
+    let mut files = Vec::new();
+    let mut errors = Vec::new();
+    for found in Walk::new(dir.as_ref(), options)? {
+        match found {
+            Ok(path) => files.push(path),
+            Err((path, e)) => errors.push((path, Err(e))),
+        }
+    }
+    files.sort();
+    let results = read_many_with(&options.read_options, &files);
+    let mut results: Vec<_> = files.into_iter().zip(results).chain(errors).collect();
+    results.sort_by(|(a, _), (b, _)| a.cmp(b));
+    Ok(results)
+}
+
+/// Finds the images in a directory and everything below it, as chosen by the
+/// options, and reads each one's metadata as it is found.
+///
+/// Unlike [`read_dir_recursive`], this holds on to nothing but the
+/// directories it is part way through, so it suits trees too big to list
+/// first, and lets you stop part way. The images are read one at a time, in
+/// the order the directories list them, and subdirectories that can't be
+/// listed are reported in the same way.
+///
+/// # Examples
+///
+/// ```
+/// use pb_imgsize::{read_dir_recursive_iter, DirOptions};
+///
+/// let options = DirOptions::new().extensions(["jpg"]);
+/// for (path, result) in read_dir_recursive_iter("src", &options)? {
+///     assert_eq!(path.file_name().unwrap(), "buttercups.jpg");
+///     assert_eq!(result?.width, 512);
+/// }
+/// # Ok::<(), pb_imgsize::Error>(())
+/// ```
+pub fn read_dir_recursive_iter(
+    dir: impl AsRef<Path>,
+    options: &DirOptions,
+) -> std::io::Result<ReadDirRecursive<'_>> {
+    Ok(ReadDirRecursive {
+        walk: Walk::new(dir.as_ref(), options)?,
+    })
+}
+
+/// An iterator over the images in a directory tree, and their metadata,
+/// returned by [`read_dir_recursive_iter`].
+#[derive(Debug)]
+pub struct ReadDirRecursive<'a> {
+    walk: Walk<'a>,
+}
+
+impl Iterator for ReadDirRecursive<'_> {
+    type Item = (PathBuf, Result<ImageMetadata, Error>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(match self.walk.next()? {
+            Ok(path) => {
+                let result = read_file_with(&self.walk.options.read_options, &path);
+                (path, result)
+            }
+            Err((path, e)) => (path, Err(e)),
+        })
+    }
+}
+
+impl std::iter::FusedIterator for ReadDirRecursive<'_> {}
+
+/// A walk through a directory tree, which finds the files that the options
+/// ask for, and the directories that can't be listed.
+#[derive(Debug)]
+struct Walk<'a> {
+    options: &'a DirOptions,
+    /// The canonical paths of the directories visited so far.
+    visited: HashSet<PathBuf>,
+    /// The directories being listed, innermost last.
+    stack: Vec<(PathBuf, fs::ReadDir)>,
+}
+
+impl<'a> Walk<'a> {
+    fn new(dir: &Path, options: &'a DirOptions) -> std::io::Result<Self> {
+        let entries = fs::read_dir(dir)?;
+        let mut walk = Walk {
+            options,
+            visited: HashSet::new(),
+            stack: Vec::new(),
+        };
+        walk.enter(dir.to_path_buf(), entries);
+        Ok(walk)
+    }
+
+    /// Start listing a directory, unless it has been visited already.
+    fn enter(&mut self, dir: PathBuf, entries: fs::ReadDir) {
+        if let Ok(canonical) = fs::canonicalize(&dir) {
+            if !self.visited.insert(canonical) {
+                return;
+            }
+        }
+        self.stack.push((dir, entries));
+    }
+}
+
+impl Iterator for Walk<'_> {
+    type Item = Result<PathBuf, (PathBuf, Error)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (dir, entries) = self.stack.last_mut()?;
+            let entry = match entries.next() {
+                Some(Ok(entry)) => entry,
+                Some(Err(e)) => return Some(Err((dir.clone(), e.into()))),
+                None => {
+                    self.stack.pop();
+                    continue;
+                }
+            };
+            let path = entry.path();
+            let Ok(mut file_type) = entry.file_type() else {
+                continue;
+            };
+            if file_type.is_symlink() {
+                if !self.options.follow_symlinks {
+                    continue;
+                }
+                // A broken symlink is skipped.
+                let Ok(metadata) = fs::metadata(&path) else {
+                    continue;
+                };
+                file_type = metadata.file_type();
+            }
+            if file_type.is_dir() {
+                match fs::read_dir(&path) {
+                    Ok(entries) => self.enter(path, entries),
+                    Err(e) => return Some(Err((path, e.into()))),
+                }
+            } else if file_type.is_file() && self.options.matches(&entry.file_name()) {
+                return Some(Ok(path));
+            }
+        }
+    }
+}
+
+/// Match a name against a pattern of literal characters, `*` and `?`.
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Where the last `*` was, and where in the name it started matching.
+    let mut star = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                // Let the `*` match one more character, and try again.
+                Some((star_p, star_n)) => {
+                    star = Some((star_p, star_n + 1));
+                    p = star_p + 1;
+                    n = star_n + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh directory holding the sample images, a text file and a
+    /// subdirectory with a copy of the PNG.
+    fn sample_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("imgsize-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::copy("src/buttercups.jpg", dir.join("buttercups.JPG")).unwrap();
+        fs::copy("src/watercolors.png", dir.join("watercolors.png")).unwrap();
+        fs::copy("src/watercolors.png", dir.join("sub/copy.png")).unwrap();
+        fs::write(dir.join("notes.txt"), "not an image").unwrap();
+        dir
+    }
+
+    fn names(dir: &Path, results: &[(PathBuf, Result<ImageMetadata, Error>)]) -> Vec<String> {
+        results
+            .iter()
+            .map(|(path, _)| {
+                let path = path.strip_prefix(dir).unwrap();
+                path.to_string_lossy().replace('\\', "/")
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_read_many() {
+        let results = read_many(&[
+            "src/watercolors.png",
+            "src/missing.png",
+            "src/buttercups.jpg",
+        ]);
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].as_ref().unwrap().width, 400);
+        assert!(matches!(results[1], Err(Error::Io(_))));
+        assert_eq!(results[2].as_ref().unwrap().width, 512);
+    }
+
+    #[test]
+    fn test_read_dir_recursive() {
+        let dir = sample_dir("read-dir");
+        let results = read_dir_recursive(&dir, &DirOptions::new()).unwrap();
+        assert_eq!(
+            names(&dir, &results),
+            ["buttercups.JPG", "sub/copy.png", "watercolors.png"]
+        );
+        assert!(results.iter().all(|(_, result)| result.is_ok()));
+
+        let options = DirOptions::new().extensions(["png"]).pattern("w*s.png");
+        let results = read_dir_recursive(&dir, &options).unwrap();
+        assert_eq!(names(&dir, &results), ["watercolors.png"]);
+
+        // Without an extension filter, the text file is read and fails.
+        let options = DirOptions::new().extensions([] as [&str; 0]);
+        let results = read_dir_recursive(&dir, &options).unwrap();
+        assert_eq!(results.len(), 4);
+        assert!(results[1].1.is_err());
+
+        assert!(read_dir_recursive(dir.join("missing"), &options).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlink_loop() {
+        let dir = sample_dir("symlink-loop");
+        std::os::unix::fs::symlink(&dir, dir.join("sub/loop")).unwrap();
+        std::os::unix::fs::symlink("watercolors.png", dir.join("link.png")).unwrap();
+
+        let results = read_dir_recursive(&dir, &DirOptions::new()).unwrap();
+        assert_eq!(results.len(), 3);
+
+        let options = DirOptions::new().follow_symlinks(true);
+        let results = read_dir_recursive(&dir, &options).unwrap();
+        assert_eq!(
+            names(&dir, &results),
+            [
+                "buttercups.JPG",
+                "link.png",
+                "sub/copy.png",
+                "watercolors.png"
+            ]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_read_dir_recursive_iter() {
+        let dir = sample_dir("read-dir-iter");
+        let mut results: Vec<_> = read_dir_recursive_iter(&dir, &DirOptions::new())
+            .unwrap()
+            .collect();
+        results.sort_by(|(a, _), (b, _)| a.cmp(b));
+        let expected = read_dir_recursive(&dir, &DirOptions::new()).unwrap();
+        assert_eq!(names(&dir, &results), names(&dir, &expected));
+        assert!(results.iter().all(|(_, result)| result.is_ok()));
+
+        // The images are read one at a time, as they are found, so spoiling
+        // the others after the first has been read spoils their results.
+        let options = DirOptions::new();
+        let mut iter = read_dir_recursive_iter(&dir, &options).unwrap();
+        let (first, result) = iter.next().unwrap();
+        assert!(result.is_ok());
+        for (path, _) in &expected {
+            if *path != first {
+                fs::write(path, b"not an image").unwrap();
+            }
+        }
+        let rest: Vec<_> = iter.by_ref().collect();
+        assert_eq!(rest.len(), 2);
+        assert!(rest.iter().all(|(_, result)| result.is_err()));
+        assert!(iter.next().is_none());
+        assert!(read_dir_recursive_iter(dir.join("missing"), &DirOptions::new()).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_non_unicode_names() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = sample_dir("non-unicode");
+        let path = dir.join(OsStr::from_bytes(b"caf\xe9.png"));
+        fs::copy("src/watercolors.png", &path).unwrap();
+        let results = read_dir_recursive(&dir, &DirOptions::new()).unwrap();
+        assert!(results
+            .iter()
+            .any(|(p, result)| *p == path && result.is_ok()));
+
+        let options = DirOptions::new().pattern("caf?.png");
+        let results = read_dir_recursive(&dir, &options).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, path);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*", ""));
+        assert!(glob_match("*.png", "image.png"));
+        assert!(glob_match("img-??.jpg", "img-01.jpg"));
+        assert!(!glob_match("img-??.jpg", "img-1.jpg"));
+        assert!(glob_match("a*b*c", "aXbYbZc"));
+        assert!(!glob_match("a*b*c", "aXbYbZ"));
+        assert!(glob_match("photo", "photo"));
+        assert!(!glob_match("photo", "photos"));
+    }
+}
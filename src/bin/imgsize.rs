@@ -0,0 +1,353 @@
+//! Prints the dimensions of JPEG and PNG images, for scripts and the shell.
+//!
+//! Each image is printed as `path width height format`, or as JSON with
+//! `--json` (one array) or `--ndjson` (one object per line). A path of `-`
+//! reads an image from standard input.
+
+use std::borrow::Cow;
+use std::ffi::OsString;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use pb_imgsize::{read_bytes, read_file, Error, ImageMetadata};
+
+const USAGE: &str = "\
+Usage: imgsize [OPTIONS] FILE...
+
+Prints `path width height format` for each image. Use `-` to read an image
+from standard input. A path with spaces, quotes or control characters in it
+is printed as a JSON string, in double quotes.
+
+Options:
+  --json      Print a JSON array of objects
+  --ndjson    Print one JSON object per line
+  --comments  Include the images' comments
+  -h, --help  Print this help
+  --version   Print the version
+
+Exit status is 0 if every image could be read, 1 if any could not, and 2 if
+the arguments were wrong.";
+
+/// How to print the results.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Output {
+    Text,
+    Json,
+    Ndjson,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Args {
+    output: Output,
+    comments: bool,
+    paths: Vec<PathBuf>,
+}
+
+/// What the arguments asked for.
+#[derive(Debug, PartialEq, Eq)]
+enum Command {
+    Read(Args),
+    Help,
+    Version,
+}
+
+fn parse_args(args: impl IntoIterator<Item = OsString>) -> Result<Command, String> {
+    let mut parsed = Args {
+        output: Output::Text,
+        comments: false,
+        paths: Vec::new(),
+    };
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        // Paths needn't be valid Unicode, so only options are compared as
+        // strings.
+        match arg.to_str() {
+            Some("--json") => parsed.output = Output::Json,
+            Some("--ndjson") => parsed.output = Output::Ndjson,
+            Some("--comments") => parsed.comments = true,
+            Some("-h" | "--help") => return Ok(Command::Help),
+            Some("--version") => return Ok(Command::Version),
+            Some("--") => parsed.paths.extend(args.by_ref().map(PathBuf::from)),
+            Some("-") => parsed.paths.push(arg.into()),
+            _ if arg.as_encoded_bytes().starts_with(b"-") => {
+                return Err(format!("unknown option: {}", arg.to_string_lossy()))
+            }
+            _ => parsed.paths.push(arg.into()),
+        }
+    }
+    if parsed.paths.is_empty() {
+        return Err("no files given".to_string());
+    }
+    Ok(Command::Read(parsed))
+}
+
+fn read(path: &Path) -> Result<ImageMetadata, Error> {
+    if path == Path::new("-") {
+        let mut data = Vec::new();
+        io::stdin().lock().read_to_end(&mut data)?;
+        Ok(read_bytes(&data)?)
+    } else {
+        read_file(path)
+    }
+}
+
+/// A path as text, with anything that isn't Unicode replaced.
+fn lossy(path: &Path) -> Cow<'_, str> {
+    path.to_string_lossy()
+}
+
+/// The result for one image as a line of text, with its comments on the
+/// lines after it, indented by a tab. A path that would be hard to split
+/// from the rest of the line is quoted as a JSON string.
+fn text(path: &Path, metadata: &ImageMetadata, comments: bool) -> String {
+    let path = lossy(path);
+    let needs_quotes = path.is_empty()
+        || path.starts_with('"')
+        || path.chars().any(|c| c.is_whitespace() || c.is_control());
+    let path = if needs_quotes {
+        Cow::Owned(json_string(&path))
+    } else {
+        path
+    };
+    let mut line = format!(
+        "{} {} {} {}",
+        path,
+        metadata.width,
+        metadata.height,
+        format_name(metadata)
+    );
+    if comments {
+        for comment in &metadata.comments {
+            let comment = String::from_utf8_lossy(comment);
+            line.push_str(&format!("\n\t{}", comment.escape_debug()));
+        }
+    }
+    line
+}
+
+/// The name of the image's format, such as "JPEG".
+fn format_name(metadata: &ImageMetadata) -> String {
+    match metadata.format() {
+        Some(format) => format.to_string(),
+        None => "unknown".to_string(),
+    }
+}
+
+/// The result for one image as a JSON object.
+fn json(path: &Path, result: &Result<ImageMetadata, Error>, comments: bool) -> String {
+    let path = json_string(&lossy(path));
+    match result {
+        Ok(metadata) => {
+            let mut object = format!(
+                r#"{{"path":{},"width":{},"height":{},"format":"{}""#,
+                path,
+                metadata.width,
+                metadata.height,
+                format_name(metadata)
+            );
+            if comments {
+                let comments: Vec<_> = metadata
+                    .comments
+                    .iter()
+                    .map(|comment| json_string(&String::from_utf8_lossy(comment)))
+                    .collect();
+                object.push_str(&format!(r#","comments":[{}]"#, comments.join(",")));
+            }
+            object.push('}');
+            object
+        }
+        Err(e) => format!(
+            r#"{{"path":{},"error":{},"kind":"{}"}}"#,
+            path,
+            json_string(&e.to_string()),
+            e.kind()
+        ),
+    }
+}
+
+/// Quote a string for JSON.
+fn json_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c < ' ' => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Read each image and print the results, returning whether every image
+/// could be read.
+fn run(args: &Args, out: &mut impl Write) -> io::Result<bool> {
+    let mut ok = true;
+    if args.output == Output::Json {
+        write!(out, "[")?;
+    }
+    for (i, path) in args.paths.iter().enumerate() {
+        let result = read(path);
+        ok &= result.is_ok();
+        match (args.output, &result) {
+            (Output::Text, Ok(metadata)) => {
+                writeln!(out, "{}", text(path, metadata, args.comments))?
+            }
+            (Output::Text, Err(e)) => eprintln!("imgsize: {}: {}", lossy(path), e),
+            (Output::Json, _) => {
+                let separator = if i == 0 { "" } else { "," };
+                write!(out, "{}\n{}", separator, json(path, &result, args.comments))?;
+            }
+            (Output::Ndjson, _) => writeln!(out, "{}", json(path, &result, args.comments))?,
+        }
+    }
+    if args.output == Output::Json {
+        writeln!(out, "\n]")?;
+    }
+    out.flush()?;
+    Ok(ok)
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args(std::env::args_os().skip(1)) {
+        Ok(Command::Read(args)) => args,
+        Ok(Command::Help) => {
+            println!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Ok(Command::Version) => {
+            println!("imgsize {}", env!("CARGO_PKG_VERSION"));
+            return ExitCode::SUCCESS;
+        }
+        Err(message) => {
+            eprintln!("imgsize: {}\n\n{}", message, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+    let mut out = BufWriter::new(io::stdout().lock());
+    match run(&args, &mut out) {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        // Most likely the output was piped into something that has exited.
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("imgsize: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Result<Command, String> {
+        parse_args(args.iter().map(OsString::from))
+    }
+
+    #[test]
+    fn test_parse_args() {
+        assert_eq!(
+            args(&["--ndjson", "--comments", "a.jpg", "-", "--", "--json"]),
+            Ok(Command::Read(Args {
+                output: Output::Ndjson,
+                comments: true,
+                paths: vec!["a.jpg".into(), "-".into(), "--json".into()],
+            }))
+        );
+        assert_eq!(args(&["a.png", "--help"]), Ok(Command::Help));
+        assert_eq!(args(&["--json"]), Err("no files given".to_string()));
+        assert_eq!(
+            args(&["-x", "a.png"]),
+            Err("unknown option: -x".to_string())
+        );
+    }
+
+    #[test]
+    fn test_run() {
+        let args = Args {
+            output: Output::Text,
+            comments: true,
+            paths: vec!["src/buttercups.jpg".into(), "src/watercolors.png".into()],
+        };
+        let mut out = Vec::new();
+        assert!(run(&args, &mut out).unwrap());
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "src/buttercups.jpg 512 341 JPEG\n\tButtercups\n\
+             src/watercolors.png 400 224 PNG\n\tAbstract watercolors\n"
+        );
+    }
+
+    #[test]
+    fn test_json() {
+        let args = Args {
+            output: Output::Json,
+            comments: false,
+            paths: vec!["src/buttercups.jpg".into(), "src/missing.png".into()],
+        };
+        let mut out = Vec::new();
+        assert!(!run(&args, &mut out).unwrap());
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<_> = out.lines().collect();
+        assert_eq!(lines[0], "[");
+        assert_eq!(
+            lines[1],
+            r#"{"path":"src/buttercups.jpg","width":512,"height":341,"format":"JPEG"},"#
+        );
+        assert!(lines[2].starts_with(r#"{"path":"src/missing.png","error":"IO error: "#));
+        assert!(lines[2].ends_with(r#","kind":"I/O error"}"#));
+        assert_eq!(lines[3], "]");
+    }
+
+    #[test]
+    fn test_text() {
+        let metadata = read_bytes(include_bytes!("../watercolors.png")).unwrap();
+        let line = |path: &str| text(Path::new(path), &metadata, false);
+        assert_eq!(line("a.png"), "a.png 400 224 PNG");
+        assert_eq!(line("my photo.png"), r#""my photo.png" 400 224 PNG"#);
+        assert_eq!(line("\"a\".png"), r#""\"a\".png" 400 224 PNG"#);
+        assert_eq!(line("a\tb\n.png"), r#""a\tb\n.png" 400 224 PNG"#);
+        assert_eq!(line("b\"c.png"), r#"b"c.png 400 224 PNG"#);
+
+        // Metadata that wasn't read from an image has no format.
+        let metadata = ImageMetadata::new(4, 3, vec![]);
+        assert_eq!(text(Path::new("a"), &metadata, false), "a 4 3 unknown");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_non_unicode_paths() {
+        use std::os::unix::ffi::OsStringExt;
+
+        let path = OsString::from_vec(b"caf\xe9.jpg".to_vec());
+        let parsed = parse_args([path.clone()]).unwrap();
+        let Command::Read(args) = parsed else {
+            panic!("expected a path, got {:?}", parsed);
+        };
+        assert_eq!(args.paths, vec![PathBuf::from(path)]);
+
+        let mut out = Vec::new();
+        assert!(!run(
+            &Args {
+                output: Output::Ndjson,
+                ..args
+            },
+            &mut out
+        )
+        .unwrap());
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("{\"path\":\"caf\u{fffd}.jpg\",\"error\":"));
+    }
+
+    #[test]
+    fn test_json_string() {
+        assert_eq!(json_string("a \"b\"\\\n\u{1}é"), r#""a \"b\"\\\n\u0001é""#);
+    }
+}
@@ -0,0 +1,331 @@
+//! Editing EXIF data in place.
+//!
+//! Nothing is ever moved, so that the offsets within the data, and the
+//! positions of everything after it in the file, stay valid. Removed entries
+//! leave unused bytes behind, which are zeroed.
+
+use std::collections::HashSet;
+use std::ops::Range;
+
+use crate::limits::LimitExceeded;
+use crate::tiff::{type_size, Entry, Tiff, SHORT};
+use crate::{LimitKind, Limits};
+
+/// The tag of the orientation.
+const ORIENTATION: u16 = 0x0112;
+/// The tag of the pointer to the Exif IFD.
+const EXIF_IFD: u16 = 0x8769;
+/// The tag of the pointer to the GPS IFD.
+const GPS_IFD: u16 = 0x8825;
+/// Tags that identify the camera or lens: BodySerialNumber, LensSerialNumber
+/// and, from DNG, CameraSerialNumber.
+const SERIAL_NUMBER_TAGS: &[u16] = &[0xa431, 0xa435, 0xc62f];
+
+/// Walk the chain of IFDs from IFD0, and the Exif and GPS IFDs they point to,
+/// checking each one's depth against the limit. A chain that loops back on
+/// itself ends where it would repeat.
+pub(crate) fn check_ifd_depth(data: &[u8], limits: &Limits) -> Result<(), LimitExceeded> {
+    let Some(tiff) = Tiff::new(data) else {
+        return Ok(());
+    };
+    let mut seen = HashSet::new();
+    let mut next = tiff.first_ifd();
+    while let Some(ifd) = next {
+        if !seen.insert(ifd.offset()) {
+            break;
+        }
+        limits.check(LimitKind::IfdDepth, ifd.depth() as u64)?;
+        for tag in [EXIF_IFD, GPS_IFD] {
+            if let Some(sub_ifd) = ifd.sub_ifd(tag) {
+                limits.check(LimitKind::IfdDepth, sub_ifd.depth() as u64)?;
+            }
+        }
+        next = ifd.next();
+    }
+    Ok(())
+}
+
+/// Remove the GPS IFD and the serial number tags from EXIF data (starting
+/// with the TIFF header), leaving everything else, such as the orientation,
+/// as it is. Data that can't be read is left alone.
+pub(crate) fn remove_private_tags(data: &mut [u8]) {
+    let Some(tiff) = Tiff::new(data) else {
+        return;
+    };
+    let Some(ifd0) = tiff.first_ifd() else {
+        return;
+    };
+    // The ranges to zero, and the IFDs to remove entries from.
+    let mut cleared = Vec::new();
+    let mut ifds = vec![(ifd0.offset(), [SERIAL_NUMBER_TAGS, &[GPS_IFD]].concat())];
+    if let Some(gps) = ifd0.sub_ifd(GPS_IFD) {
+        cleared.extend(
+            gps.entries()
+                .filter_map(|entry| entry.value_range(&tiff, type_size(entry.kind)?)),
+        );
+        cleared.push(gps.range());
+    }
+    if let Some(exif) = ifd0.sub_ifd(EXIF_IFD) {
+        ifds.push((exif.offset(), SERIAL_NUMBER_TAGS.to_vec()));
+    }
+    for &(offset, ref tags) in &ifds {
+        let Some(ifd) = tiff.ifd(offset) else {
+            continue;
+        };
+        cleared.extend(
+            ifd.entries()
+                .filter(|entry| tags.contains(&entry.tag))
+                .filter_map(|entry| entry.value_range(&tiff, type_size(entry.kind)?)),
+        );
+    }
+
+    // A value can point anywhere, even into an IFD we keep, which zeroing it
+    // would wreck.
+    let kept: Vec<_> = ifds
+        .iter()
+        .filter_map(|&(offset, _)| tiff.ifd(offset))
+        .chain(ifd0.next())
+        .map(|ifd| ifd.range())
+        .collect();
+    let overlaps_kept = |range: &Range<usize>| {
+        kept.iter()
+            .any(|ifd| range.start < ifd.end && ifd.start < range.end)
+    };
+    cleared.retain(|range| !overlaps_kept(range));
+
+    for range in cleared {
+        if let Some(bytes) = data.get_mut(range) {
+            bytes.fill(0);
+        }
+    }
+    for (offset, tags) in ifds {
+        remove_entries(data, offset, &tags);
+    }
+}
+
+/// Set the orientation tag in IFD0, and in IFD1 (the thumbnail's) if it has
+/// one too. Returns false, leaving the data alone, if IFD0 has no orientation
+/// tag holding a single SHORT.
+pub(crate) fn set_orientation(data: &mut [u8], orientation: u16) -> bool {
+    let Some(tiff) = Tiff::new(data) else {
+        return false;
+    };
+    let Some(ifd0) = tiff.first_ifd() else {
+        return false;
+    };
+    let single_short = |entry: &Entry| (entry.kind, entry.count) == (SHORT, 1);
+    let Some(entry) = ifd0.find(ORIENTATION).filter(single_short) else {
+        return false;
+    };
+    let thumbnail = ifd0.next().and_then(|ifd| ifd.find(ORIENTATION));
+    let entries = [Some(entry), thumbnail.filter(single_short)];
+    let bytes = tiff.u16_bytes(orientation);
+    for entry in entries.into_iter().flatten() {
+        let offset = entry.value_offset();
+        data[offset..offset + 2].copy_from_slice(&bytes);
+    }
+    true
+}
+
+/// Remove the entries with the given tags from the IFD at `offset`, moving
+/// the rest up to fill the gap.
+fn remove_entries(data: &mut [u8], offset: usize, tags: &[u16]) {
+    let Some(tiff) = Tiff::new(data) else {
+        return;
+    };
+    let Some(ifd) = tiff.ifd(offset) else {
+        return;
+    };
+    let range = ifd.range();
+    let kept: Vec<_> = ifd
+        .entries()
+        .filter(|entry| !tags.contains(&entry.tag))
+        .collect();
+    let mut rewritten = Vec::with_capacity(range.len());
+    rewritten.extend_from_slice(&tiff.u16_bytes(kept.len() as u16));
+    for entry in kept {
+        rewritten.extend_from_slice(&data[entry.offset..entry.offset + 12]);
+    }
+    // The offset of the next IFD.
+    rewritten.extend_from_slice(&data[range.end - 4..range.end]);
+    rewritten.resize(range.len(), 0);
+    data[range].copy_from_slice(&rewritten);
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// EXIF data with an orientation, a body serial number in the Exif IFD,
+    /// and a GPS IFD with a latitude.
+    pub(crate) fn exif(big_endian: bool) -> Vec<u8> {
+        let mut data = Vec::new();
+        let u16 = |data: &mut Vec<u8>, value: u16| {
+            data.extend(if big_endian {
+                value.to_be_bytes()
+            } else {
+                value.to_le_bytes()
+            })
+        };
+        let u32 = |data: &mut Vec<u8>, value: u32| {
+            data.extend(if big_endian {
+                value.to_be_bytes()
+            } else {
+                value.to_le_bytes()
+            })
+        };
+        data.extend(if big_endian { b"MM\0*" } else { b"II*\0" });
+        u32(&mut data, 8);
+        // IFD0 at 8: orientation, and pointers to the Exif and GPS IFDs.
+        u16(&mut data, 3);
+        for (tag, kind, value) in [(0x0112, 3, 6), (EXIF_IFD, 4, 50), (GPS_IFD, 4, 80)] {
+            u16(&mut data, tag);
+            u16(&mut data, kind);
+            u32(&mut data, 1);
+            if kind == 3 {
+                u16(&mut data, value as u16);
+                u16(&mut data, 0);
+            } else {
+                u32(&mut data, value);
+            }
+        }
+        u32(&mut data, 0);
+        // The Exif IFD at 50: a serial number, stored at 68.
+        u16(&mut data, 1);
+        u16(&mut data, 0xa431);
+        u16(&mut data, 2);
+        u32(&mut data, 10);
+        u32(&mut data, 68);
+        u32(&mut data, 0);
+        data.extend(b"SN-1234567\0\0");
+        // The GPS IFD at 80: "N", and the latitude, stored at 110.
+        u16(&mut data, 2);
+        u16(&mut data, 0x0001);
+        u16(&mut data, 2);
+        u32(&mut data, 2);
+        data.extend(b"N\0\0\0");
+        u16(&mut data, 0x0002);
+        u16(&mut data, 5);
+        u32(&mut data, 3);
+        u32(&mut data, 110);
+        u32(&mut data, 0);
+        for value in [51, 1, 30, 1, 0, 1] {
+            u32(&mut data, value);
+        }
+        data
+    }
+
+    #[test]
+    fn test_remove_private_tags() {
+        for big_endian in [false, true] {
+            let mut data = exif(big_endian);
+            let len = data.len();
+            assert_eq!(len, 134);
+            remove_private_tags(&mut data);
+            assert_eq!(data.len(), len);
+
+            let tiff = Tiff::new(&data).unwrap();
+            let ifd0 = tiff.first_ifd().unwrap();
+            assert_eq!(ifd0.find_u32(0x0112), Some(6));
+            assert_eq!(ifd0.find(GPS_IFD), None);
+            assert_eq!(ifd0.entries().count(), 2);
+            let exif = tiff.ifd(ifd0.find_u32(EXIF_IFD).unwrap() as usize);
+            assert_eq!(exif.unwrap().entries().count(), 0);
+            // The serial number and everything in the GPS IFD are gone.
+            assert!(data[50..].iter().all(|&b| b == 0));
+        }
+    }
+
+    #[test]
+    fn test_value_in_kept_ifd() {
+        // The GPS latitude's value points at IFD0, which mustn't be zeroed
+        // along with the rest of the GPS data.
+        let mut data = exif(false);
+        data[102..106].copy_from_slice(&8u32.to_le_bytes());
+        remove_private_tags(&mut data);
+        let tiff = Tiff::new(&data).unwrap();
+        let ifd0 = tiff.first_ifd().unwrap();
+        assert_eq!(ifd0.find_u32(0x0112), Some(6));
+        assert_eq!(ifd0.entries().count(), 2);
+        // The GPS IFD itself is still cleared.
+        assert!(data[80..110].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn test_set_orientation() {
+        for big_endian in [false, true] {
+            let mut data = exif(big_endian);
+            let original = data.clone();
+            assert!(set_orientation(&mut data, 1));
+            let tiff = Tiff::new(&data).unwrap();
+            assert_eq!(tiff.first_ifd().unwrap().find_u32(ORIENTATION), Some(1));
+            // Only the value has changed.
+            let changed: Vec<_> = (0..data.len())
+                .filter(|&i| data[i] != original[i])
+                .collect();
+            assert_eq!(changed, [if big_endian { 19 } else { 18 }]);
+        }
+
+        // A thumbnail's orientation is changed too.
+        let mut data = [
+            b"II*\0\x08\0\0\0".as_slice(),
+            b"\x01\0\x12\x01\x03\0\x01\0\0\0\x06\0\0\0\x1a\0\0\0",
+            b"\x01\0\x12\x01\x03\0\x01\0\0\0\x06\0\0\0\0\0\0\0",
+        ]
+        .concat();
+        assert!(set_orientation(&mut data, 3));
+        let ifd0 = Tiff::new(&data).unwrap().first_ifd().unwrap();
+        assert_eq!(ifd0.find_u32(ORIENTATION), Some(3));
+        assert_eq!(ifd0.next().unwrap().find_u32(ORIENTATION), Some(3));
+    }
+
+    #[test]
+    fn test_no_orientation() {
+        // Only the thumbnail has an orientation, which isn't enough.
+        let mut data = [
+            b"II*\0\x08\0\0\0".as_slice(),
+            b"\x01\0\x0f\x01\x02\0\x01\0\0\0\0\0\0\0\x1a\0\0\0",
+            b"\x01\0\x12\x01\x03\0\x01\0\0\0\x06\0\0\0\0\0\0\0",
+        ]
+        .concat();
+        let original = data.clone();
+        assert!(!set_orientation(&mut data, 1));
+        assert_eq!(data, original);
+        assert!(!set_orientation(&mut [], 1));
+    }
+
+    #[test]
+    fn test_check_ifd_depth() {
+        // The Exif and GPS IFDs are at depth 2.
+        let data = exif(false);
+        let limits = |depth| Limits::new().max_ifd_depth(Some(depth));
+        assert_eq!(check_ifd_depth(&data, &limits(2)), Ok(()));
+        let err = check_ifd_depth(&data, &limits(1)).unwrap_err();
+        assert_eq!(
+            (err.kind, err.value, err.limit),
+            (LimitKind::IfdDepth, 2, 1)
+        );
+
+        // A chain of three IFDs, the last of which points back to the first.
+        let mut data = b"II*\0\x08\0\0\0".to_vec();
+        for next in [14u32, 20, 8] {
+            data.extend_from_slice(&[0, 0]);
+            data.extend_from_slice(&next.to_le_bytes());
+        }
+        assert_eq!(check_ifd_depth(&data, &Limits::default()), Ok(()));
+        assert_eq!(check_ifd_depth(&data, &limits(3)), Ok(()));
+        let err = check_ifd_depth(&data, &limits(2)).unwrap_err();
+        assert_eq!(
+            (err.kind, err.value, err.limit),
+            (LimitKind::IfdDepth, 3, 2)
+        );
+    }
+
+    #[test]
+    fn test_unreadable_data() {
+        let mut data = b"II*\0\xff\0\0\0".to_vec();
+        remove_private_tags(&mut data);
+        assert_eq!(data, b"II*\0\xff\0\0\0");
+        remove_private_tags(&mut []);
+    }
+}
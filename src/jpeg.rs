@@ -1,24 +1,160 @@
+//! Reading metadata from JPEG images.
+
+mod app;
+mod embedded;
+mod gainmap;
+mod mpf;
+mod segments;
+mod strip;
+mod thumbnail;
+mod validate;
+mod write;
+
+use std::borrow::Cow;
 use std::fmt::{self, Debug, Display};
+use std::ops::Range;
 
-use super::ImageMetadata;
+pub use app::{Adobe, AdobeTransform, DensityUnits, Jfif, Jfxx, JfxxFormat};
+pub use gainmap::GainMap;
+pub use mpf::{MpEntry, MpImageType, Mpf};
+pub use segments::{Marker, Segment, Segments};
+pub(crate) use strip::strip_jpeg;
+pub use thumbnail::{
+    read_thumbnails, read_thumbnails_with, Thumbnail, ThumbnailFormat, ThumbnailSource,
+};
+pub(crate) use validate::validate_jpeg;
+pub(crate) use write::{set_jpeg_orientation, write_jpeg_comments};
+
+use super::limits::LimitExceeded;
+use super::warnings::{Diagnostics, Warning, WarningKind};
+use super::{
+    ErrorKind, Flow, ImageMetadata, ImageMetadataRef, LimitKind, MetadataVisitor, ReadOptions,
+    StopAt, Strictness,
+};
+use embedded::IccChunk;
 
 /// An error that occurred while decoding a JPEG image.
+///
+/// Positions are from the start of the data. A segment's position is where
+/// its marker starts.
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum JpegDecodingError {
     NoSoiMarker,
     NoSofMarker {
         position: usize,
         comments: Vec<Vec<u8>>,
     },
+    /// The SOF segment's data is only `length` bytes, too short for the
+    /// dimensions.
     SofDataTooShort {
         position: usize,
+        length: usize,
+    },
+    DnlDataTooShort {
+        position: usize,
+        length: usize,
     },
     InvalidFrameMarker {
         word: u16,
         position: usize,
     },
-    InvalidSegmentLength(usize),
-    UnexpectedEndOfData(usize),
+    /// The segment's length field is less than 2, the size of the field
+    /// itself.
+    InvalidSegmentLength {
+        marker: Marker,
+        position: usize,
+        length: usize,
+    },
+    /// The segment's length runs past the end of the data, which only has
+    /// `available` bytes from the length field on.
+    TruncatedSegment {
+        marker: Marker,
+        position: usize,
+        length: usize,
+        available: usize,
+    },
+    /// The data ends partway through the marker at `position`.
+    UnexpectedEndOfData {
+        position: usize,
+    },
+    /// There are `count` bytes starting at `position` that aren't part of
+    /// any segment.
+    ExtraneousBytes {
+        position: usize,
+        count: usize,
+    },
+    /// The data ends at `position` without an EOI marker.
+    MissingEoi {
+        position: usize,
+    },
+    RestartOutOfSequence {
+        position: usize,
+        expected: u8,
+        found: u8,
+    },
+    InvalidScanHeader {
+        position: usize,
+    },
+    InvalidScanComponent {
+        position: usize,
+        component: u8,
+    },
+    /// A comment to be written is too long to fit in a COM segment.
+    CommentTooLong {
+        length: usize,
+    },
+    LimitExceeded {
+        kind: LimitKind,
+        value: u64,
+        limit: u64,
+    },
+}
+
+impl JpegDecodingError {
+    /// What kind of error this is.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            JpegDecodingError::NoSoiMarker | JpegDecodingError::NoSofMarker { .. } => {
+                ErrorKind::Missing
+            }
+            JpegDecodingError::TruncatedSegment { .. }
+            | JpegDecodingError::UnexpectedEndOfData { .. }
+            | JpegDecodingError::MissingEoi { .. } => ErrorKind::Truncated,
+            JpegDecodingError::RestartOutOfSequence { .. } => ErrorKind::OutOfOrder,
+            JpegDecodingError::SofDataTooShort { .. }
+            | JpegDecodingError::DnlDataTooShort { .. }
+            | JpegDecodingError::InvalidFrameMarker { .. }
+            | JpegDecodingError::InvalidSegmentLength { .. }
+            | JpegDecodingError::ExtraneousBytes { .. }
+            | JpegDecodingError::InvalidScanHeader { .. }
+            | JpegDecodingError::InvalidScanComponent { .. }
+            | JpegDecodingError::CommentTooLong { .. } => ErrorKind::InvalidData,
+            JpegDecodingError::LimitExceeded { .. } => ErrorKind::LimitExceeded,
+        }
+    }
+
+    /// Where in the data the error is, if it is anywhere in particular.
+    pub fn offset(&self) -> Option<usize> {
+        match *self {
+            JpegDecodingError::NoSoiMarker => Some(0),
+            JpegDecodingError::NoSofMarker { position, .. }
+            | JpegDecodingError::SofDataTooShort { position, .. }
+            | JpegDecodingError::DnlDataTooShort { position, .. }
+            | JpegDecodingError::InvalidFrameMarker { position, .. }
+            | JpegDecodingError::InvalidSegmentLength { position, .. }
+            | JpegDecodingError::TruncatedSegment { position, .. }
+            | JpegDecodingError::UnexpectedEndOfData { position }
+            | JpegDecodingError::ExtraneousBytes { position, .. }
+            | JpegDecodingError::MissingEoi { position }
+            | JpegDecodingError::RestartOutOfSequence { position, .. }
+            | JpegDecodingError::InvalidScanHeader { position }
+            | JpegDecodingError::InvalidScanComponent { position, .. } => Some(position),
+            JpegDecodingError::CommentTooLong { .. } | JpegDecodingError::LimitExceeded { .. } => {
+                None
+            }
+        }
+    }
 }
 
 impl Display for JpegDecodingError {
@@ -30,38 +166,239 @@ impl Display for JpegDecodingError {
                 "No SOF marker found (position: {}, comments: {:?})",
                 position, comments
             ),
-            JpegDecodingError::SofDataTooShort { position } => {
-                write!(f, "SOF data is too short (position: {})", position)
-            }
+            JpegDecodingError::SofDataTooShort { position, length } => write!(
+                f,
+                "SOF segment at position {} is too short: {} bytes",
+                position, length
+            ),
+            JpegDecodingError::DnlDataTooShort { position, length } => write!(
+                f,
+                "DNL segment at position {} is too short: {} bytes",
+                position, length
+            ),
             JpegDecodingError::InvalidFrameMarker { word, position } => write!(
                 f,
                 "Invalid frame marker: 0x{:04x} at position {} (0x{:04x})",
                 word, position, position
             ),
-            JpegDecodingError::InvalidSegmentLength(len) => {
-                write!(f, "Invalid JPEG segment length: {}", len)
-            }
-            JpegDecodingError::UnexpectedEndOfData(position) => {
+            JpegDecodingError::InvalidSegmentLength {
+                marker,
+                position,
+                length,
+            } => write!(
+                f,
+                "Invalid {} segment length {} at position {}",
+                marker, length, position
+            ),
+            JpegDecodingError::TruncatedSegment {
+                marker,
+                position,
+                length,
+                available,
+            } => write!(
+                f,
+                "{} segment at position {} has length {}, but only {} bytes are left",
+                marker, position, length, available
+            ),
+            JpegDecodingError::UnexpectedEndOfData { position } => {
                 write!(f, "Unexpected end of data at position {}", position)
             }
+            JpegDecodingError::ExtraneousBytes { position, count } => {
+                write!(f, "{} extraneous bytes at position {}", count, position)
+            }
+            JpegDecodingError::MissingEoi { position } => write!(
+                f,
+                "No EOI marker found before the end of the data at position {}",
+                position
+            ),
+            JpegDecodingError::RestartOutOfSequence {
+                position,
+                expected,
+                found,
+            } => write!(
+                f,
+                "RST{} marker out of sequence at position {} (expected RST{})",
+                found, position, expected
+            ),
+            JpegDecodingError::InvalidScanHeader { position } => {
+                write!(f, "Invalid SOS segment at position {}", position)
+            }
+            JpegDecodingError::InvalidScanComponent {
+                position,
+                component,
+            } => write!(
+                f,
+                "SOS segment at position {} refers to unknown component {}",
+                position, component
+            ),
+            JpegDecodingError::CommentTooLong { length } => write!(
+                f,
+                "Comment of {} bytes is too long for a COM segment",
+                length
+            ),
+            JpegDecodingError::LimitExceeded { kind, value, limit } => {
+                write!(
+                    f,
+                    "Image {} of {} exceeds the limit of {}",
+                    kind, value, limit
+                )
+            }
         }
     }
 }
 
 impl std::error::Error for JpegDecodingError {}
 
+impl From<LimitExceeded> for JpegDecodingError {
+    fn from(e: LimitExceeded) -> Self {
+        JpegDecodingError::LimitExceeded {
+            kind: e.kind,
+            value: e.value,
+            limit: e.limit,
+        }
+    }
+}
+
+/// Options controlling how JPEG data is read.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct JpegOptions {
+    pub(crate) scan_for_dnl: bool,
+}
+
+impl JpegOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// If the SOF segment gives a height of 0, scan through the entropy-coded
+    /// data of the first scan looking for a DNL (Define Number of Lines)
+    /// segment, and take the height from that instead.
+    ///
+    /// This is off by default, because it means reading the whole first scan
+    /// rather than stopping at the SOS marker.
+    pub fn scan_for_dnl(mut self, scan_for_dnl: bool) -> Self {
+        self.scan_for_dnl = scan_for_dnl;
+        self
+    }
+}
+
+/// JPEG-specific metadata.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct JpegMetadata {
+    /// The number of color components, from the SOF segment.
+    pub components: u8,
+    /// The JFIF APP0 segment, if present.
+    pub jfif: Option<Jfif>,
+    /// The JFXX (JFIF extension) APP0 segment, if present.
+    pub jfxx: Option<Jfxx>,
+    /// The Adobe APP14 segment, if present.
+    pub adobe: Option<Adobe>,
+    /// The color model, worked out from the component count and the JFIF and
+    /// Adobe segments.
+    pub color_model: ColorModel,
+    /// The Multi-Picture Format APP2 segment, if present. This lists any
+    /// further images stored after the primary image.
+    pub mpf: Option<Mpf>,
+    /// The Ultra HDR gain map image, if one of the images listed in the MPF
+    /// segment is a gain map. It is only looked for when the XMP was asked
+    /// for with [`ReadOptions::xmp`], or the whole file with [`StopAt::End`].
+    pub gain_map: Option<GainMap>,
+}
+
+/// The color model of a JPEG image's components.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ColorModel {
+    Grayscale,
+    YCbCr,
+    Rgb,
+    Cmyk,
+    Ycck,
+    /// A component count that doesn't correspond to any of the above.
+    Unknown,
+}
+
+impl ColorModel {
+    /// Work out the color model the same way libjpeg does.
+    ///
+    /// With 3 components, the image is YCbCr unless there is an Adobe segment
+    /// saying it wasn't transformed, or there is neither a JFIF nor an Adobe
+    /// segment and the component IDs spell out "RGB". With 4 components, the
+    /// image is CMYK unless an Adobe segment says it is YCCK.
+    fn resolve(component_ids: &[u8], jfif: Option<&Jfif>, adobe: Option<&Adobe>) -> Self {
+        match component_ids.len() {
+            1 => ColorModel::Grayscale,
+            3 => match (jfif, adobe) {
+                (_, Some(adobe)) if adobe.transform == AdobeTransform::None => ColorModel::Rgb,
+                (None, None) if component_ids == b"RGB" => ColorModel::Rgb,
+                _ => ColorModel::YCbCr,
+            },
+            4 => match adobe {
+                Some(adobe) if adobe.transform == AdobeTransform::Ycck => ColorModel::Ycck,
+                _ => ColorModel::Cmyk,
+            },
+            _ => ColorModel::Unknown,
+        }
+    }
+}
+
 /// Read JPEG data, and return its dimensions and any comments found.
 pub fn read_jpeg_data(buf: &[u8]) -> Result<ImageMetadata, JpegDecodingError> {
-    let mut context = JpegContext {
-        buf,
-        position: 2, // The first 2 bytes are the SOI marker, which we have already looked at.
-        comments: vec![],
-        dimensions: None,
+    read_jpeg_data_with(buf, &JpegOptions::default())
+}
+
+/// Read JPEG data using the given options, and return its dimensions and any
+/// comments found.
+pub fn read_jpeg_data_with(
+    buf: &[u8],
+    options: &JpegOptions,
+) -> Result<ImageMetadata, JpegDecodingError> {
+    read_jpeg_data_with_visitor(buf, options, &mut ())
+}
+
+/// Read JPEG data using the given options, calling the visitor for each
+/// segment, and return its dimensions and any comments found.
+pub fn read_jpeg_data_with_visitor(
+    buf: &[u8],
+    options: &JpegOptions,
+    visitor: &mut impl MetadataVisitor,
+) -> Result<ImageMetadata, JpegDecodingError> {
+    let options = ReadOptions {
+        jpeg: options.clone(),
+        ..ReadOptions::default()
     };
+    read_jpeg_data_ref(buf, &options, visitor).map(ImageMetadata::from)
+}
+
+/// Read JPEG data, returning metadata that borrows from it.
+pub(crate) fn read_jpeg_data_ref<'a>(
+    buf: &'a [u8],
+    options: &ReadOptions,
+    visitor: &mut impl MetadataVisitor,
+) -> Result<ImageMetadataRef<'a>, JpegDecodingError> {
+    // The first 2 bytes are the SOI marker, which we have already looked at.
+    let limits = &options.limits;
+    let mut context = JpegContext::new(buf, 2);
+    let mut diagnostics = Diagnostics::new(options.strictness);
+    let mut segment_count = 0;
+    let mut comment_bytes = 0;
+    let mut stopped = false;
 
     // Loop over the segments in the JPEG data.
     while let Some(segment) = context.read_segment()? {
         let marker = segment.marker;
+        if let Some(skipped) = context.skipped.take() {
+            let error = JpegDecodingError::ExtraneousBytes {
+                position: skipped.start,
+                count: skipped.len(),
+            };
+            let kind = WarningKind::ExtraneousBytes;
+            diagnostics.report(error, kind, skipped.start, Strictness::Normal)?;
+        }
+        segment_count += 1;
+        limits.check(LimitKind::Segments, segment_count)?;
 
         // What we do next depends on the marker.
         // - It might be an invalid marker, in which case we return an error.
@@ -74,10 +411,24 @@ pub fn read_jpeg_data(buf: &[u8]) -> Result<ImageMetadata, JpegDecodingError> {
         if marker < 0xff01 || marker == 0xffff {
             return Err(JpegDecodingError::InvalidFrameMarker {
                 word: marker,
-                position: context.position,
+                position: segment.position,
             });
         }
 
+        // A visitor that stops us means we don't look for the EOI marker
+        // either, even if this is the last segment we would have read anyway.
+        stopped = visitor.visit_jpeg_segment(&segment.to_segment()) == Flow::Stop;
+
+        // A height of 0 in the SOF means the real height is given by a DNL
+        // segment at the end of the first scan.
+        if marker == 0xffda && options.jpeg.scan_for_dnl && context.needs_dnl() {
+            context.read_dnl()?;
+            if let Some((w, h)) = context.dimensions {
+                limits.check_dimensions(w, h)?;
+            }
+            break;
+        }
+
         // End of metadata?
         if marker == 0xffd9 || marker == 0xffda {
             // 0xffd9 = EOI (end marker)
@@ -89,23 +440,134 @@ pub fn read_jpeg_data(buf: &[u8]) -> Result<ImageMetadata, JpegDecodingError> {
         if segment.is_sof() {
             // SOFx marker: read the dimensions and add them to the context.
             let (w, h) = segment.read_sof()?;
+            limits.check_dimensions(w.into(), h.into())?;
             context.dimensions.replace((w.into(), h.into()));
+            context.component_ids = read_sof_component_ids(segment.data);
         } else if segment.is_com() {
-            // COM marker: read the comment and add it to the list.
-            let comment = segment.into_data();
-            context.comments.push(comment);
+            // COM marker: add the comment to the list.
+            if options.comments {
+                comment_bytes += segment.data.len() as u64;
+                limits.check(LimitKind::CommentBytes, comment_bytes)?;
+                context.comments.push(segment.data);
+            }
+        } else if segment.is_app(0) {
+            // APP0 marker: this is either a JFIF header or a JFXX extension.
+            if let Some(jfif) = app::read_jfif(segment.data) {
+                context.jfif.get_or_insert(jfif);
+            } else if let Some(jfxx) = app::read_jfxx(segment.data) {
+                context.jfxx.get_or_insert(jfxx);
+            }
+        } else if segment.is_app(1) {
+            // APP1 marker: this might be EXIF data or an XMP packet.
+            if options.exif && context.exif.is_none() {
+                context.exif = embedded::read_exif(segment.data);
+                if let Some(exif) = context.exif {
+                    crate::exif::check_ifd_depth(exif, limits)?;
+                }
+            }
+            if options.xmp && context.xmp.is_none() {
+                context.xmp = embedded::read_xmp(segment.data);
+            }
+        } else if segment.is_app(2) {
+            // APP2 marker: this might be an MPF segment, or part of an ICC
+            // profile.
+            if context.mpf.is_none() {
+                let data_offset = segment.data_offset();
+                let read =
+                    mpf::read_mpf(buf, data_offset, segment.data, options, &mut segment_count);
+                if let Some((mpf, gain_map)) = read? {
+                    context.mpf = Some(mpf);
+                    context.gain_map = gain_map;
+                }
+            }
+            if options.icc_profile {
+                context
+                    .icc_chunks
+                    .extend(embedded::read_icc_chunk(segment.data));
+            }
+        } else if segment.is_app(14) {
+            // APP14 marker: this might be an Adobe segment.
+            if let Some(adobe) = app::read_adobe(segment.data) {
+                context.adobe.get_or_insert(adobe);
+            }
+        }
+
+        if stopped || context.has_dimensions(options) {
+            stopped = true;
+            break;
+        }
+    }
+
+    if options.stop_at == StopAt::End && !stopped {
+        match context.find_end() {
+            Some(end) if end < buf.len() => context.trailing_bytes = Some(end..buf.len()),
+            Some(_) => {}
+            None => {
+                context.truncated = true;
+                let error = JpegDecodingError::MissingEoi {
+                    position: buf.len(),
+                };
+                diagnostics.report(error, WarningKind::Truncated, buf.len(), Strictness::Normal)?;
+            }
         }
     }
 
-    // We're done. Try to convert the context into an ImageMetadata. (This will
+    // We're done. Try to convert the context into an ImageMetadataRef. (This will
     // fail if we didn't find a SOF marker.)
+    context.warnings = diagnostics.warnings;
     context.try_into()
 }
+/// Read just the dimensions of JPEG data, ignoring every segment but the
+/// SOF. This is used for embedded images such as thumbnails, which could
+/// themselves embed further images.
+fn read_dimensions(buf: &[u8]) -> Option<(u32, u32)> {
+    if !buf.starts_with(b"\xff\xd8") {
+        return None;
+    }
+    let mut context = JpegContext::new(buf, 2);
+    while let Some(segment) = context.read_segment().ok()? {
+        if segment.marker == 0xffd9 || segment.marker == 0xffda {
+            break;
+        }
+        if segment.is_sof() {
+            let (w, h) = segment.read_sof().ok()?;
+            return Some((w.into(), h.into()));
+        }
+    }
+    None
+}
+
+/// Read the component IDs from the data of a SOF (Start Of Frame) segment. If
+/// the segment is cut short, only the components that fit are returned.
+fn read_sof_component_ids(data: &[u8]) -> Vec<u8> {
+    let count = data.get(5).copied().unwrap_or(0);
+    data.get(6..)
+        .unwrap_or_default()
+        .chunks_exact(3)
+        .take(count.into())
+        .map(|component| component[0])
+        .collect()
+}
+
 struct JpegContext<'a> {
     buf: &'a [u8],
     position: usize,
-    comments: Vec<Vec<u8>>,
+    comments: Vec<&'a [u8]>,
     dimensions: Option<(u32, u32)>,
+    component_ids: Vec<u8>,
+    jfif: Option<Jfif>,
+    jfxx: Option<Jfxx>,
+    adobe: Option<Adobe>,
+    mpf: Option<Mpf>,
+    gain_map: Option<GainMap>,
+    exif: Option<&'a [u8]>,
+    xmp: Option<&'a [u8]>,
+    icc_chunks: Vec<IccChunk<'a>>,
+    /// The bytes skipped over to find the last segment read, if any.
+    skipped: Option<Range<usize>>,
+    warnings: Vec<Warning>,
+    truncated: bool,
+    trailing_bytes: Option<Range<usize>>,
 }
 
 struct JpegSegment<'a> {
@@ -124,34 +586,86 @@ impl<'a> Debug for JpegSegment<'a> {
     }
 }
 
-impl<'a> TryFrom<JpegContext<'a>> for ImageMetadata {
+impl<'a> TryFrom<JpegContext<'a>> for ImageMetadataRef<'a> {
     type Error = JpegDecodingError;
 
-    fn try_from(jpeg: JpegContext) -> Result<Self, JpegDecodingError> {
+    fn try_from(jpeg: JpegContext<'a>) -> Result<Self, JpegDecodingError> {
         if let Some((width, height)) = jpeg.dimensions {
-            Ok(ImageMetadata {
+            let color_model =
+                ColorModel::resolve(&jpeg.component_ids, jpeg.jfif.as_ref(), jpeg.adobe.as_ref());
+            Ok(ImageMetadataRef {
                 width,
                 height,
                 comments: jpeg.comments,
+                exif: jpeg.exif,
+                xmp: jpeg.xmp.map(Cow::Borrowed),
+                icc_profile: embedded::assemble_icc_profile(&jpeg.icc_chunks),
+                warnings: jpeg.warnings,
+                truncated: jpeg.truncated,
+                trailing_bytes: jpeg.trailing_bytes,
+                jpeg: Some(JpegMetadata {
+                    components: jpeg.component_ids.len() as u8,
+                    jfif: jpeg.jfif,
+                    jfxx: jpeg.jfxx,
+                    adobe: jpeg.adobe,
+                    color_model,
+                    mpf: jpeg.mpf,
+                    gain_map: jpeg.gain_map,
+                }),
+                png: None,
             })
         } else {
             Err(JpegDecodingError::NoSofMarker {
                 position: jpeg.position,
-                comments: jpeg.comments,
+                comments: jpeg.comments.iter().map(|c| c.to_vec()).collect(),
             })
         }
     }
 }
 
 impl<'a> JpegContext<'a> {
+    fn new(buf: &'a [u8], position: usize) -> Self {
+        JpegContext {
+            buf,
+            position,
+            comments: vec![],
+            dimensions: None,
+            component_ids: vec![],
+            jfif: None,
+            jfxx: None,
+            adobe: None,
+            mpf: None,
+            gain_map: None,
+            exif: None,
+            xmp: None,
+            icc_chunks: vec![],
+            skipped: None,
+            warnings: vec![],
+            truncated: false,
+            trailing_bytes: None,
+        }
+    }
+
+    /// Returns true if we've read as far as the options ask for. When only
+    /// the dimensions are wanted, that's as soon as we have them, unless the
+    /// height is to come from a DNL segment.
+    fn has_dimensions(&self, options: &ReadOptions) -> bool {
+        options.stop_at == StopAt::Dimensions
+            && self.dimensions.is_some()
+            && !(options.jpeg.scan_for_dnl && self.needs_dnl())
+    }
+
     /// Read a segment from the JPEG data. Returns the marker, and the data
     /// following the marker.
     ///
     /// Returns `None` if the end of the JPEG data has been reached.
-    pub fn read_segment(&mut self) -> Result<Option<JpegSegment>, JpegDecodingError> {
+    pub fn read_segment(&mut self) -> Result<Option<JpegSegment<'a>>, JpegDecodingError> {
         // If the current byte is not 0xff, resync to the next marker.
+        self.skipped = None;
         if self.buf.get(self.position) != Some(&0xff) {
+            let start = self.position;
             self.resync();
+            self.skipped = Some(start..self.position);
         }
         // If the current byte is still not 0xff, we've reached the end of the data.
         if self.buf.get(self.position) != Some(&0xff) {
@@ -170,8 +684,20 @@ impl<'a> JpegContext<'a> {
         let original_position = self.position;
         let (marker, len) = self.read_marker()?;
         // Check that the length is valid.
-        if len < 2 || self.position + len > self.buf.len() {
-            return Err(JpegDecodingError::InvalidSegmentLength(len));
+        let available = self.buf.len() - (self.position - 2);
+        if len < 2 {
+            return Err(JpegDecodingError::InvalidSegmentLength {
+                marker: Marker::from_code(marker as u8),
+                position: original_position,
+                length: len,
+            });
+        } else if len > available {
+            return Err(JpegDecodingError::TruncatedSegment {
+                marker: Marker::from_code(marker as u8),
+                position: original_position,
+                length: len,
+                available,
+            });
         }
 
         // Extract the data from the segment.
@@ -194,13 +720,68 @@ impl<'a> JpegContext<'a> {
             return Ok((marker, 0));
         }
         if self.position + 4 > self.buf.len() {
-            return Err(JpegDecodingError::UnexpectedEndOfData(self.position));
+            return Err(JpegDecodingError::UnexpectedEndOfData {
+                position: self.position,
+            });
         }
         let len = u16::from_be_bytes([self.buf[self.position + 2], self.buf[self.position + 3]]);
         self.position += 4;
         Ok((marker, len.into()))
     }
 
+    /// Returns true if we have seen a SOF marker whose height was 0.
+    fn needs_dnl(&self) -> bool {
+        matches!(self.dimensions, Some((_, 0)))
+    }
+
+    /// Skip over the entropy-coded data following a SOS segment, and if the
+    /// next segment is a DNL (Define Number of Lines), use it to fill in the
+    /// height.
+    fn read_dnl(&mut self) -> Result<(), JpegDecodingError> {
+        self.skip_scan_data(false);
+        if let Some(segment) = self.read_segment()? {
+            if segment.is_dnl() {
+                let height = segment.read_dnl()?;
+                if let Some((_, h)) = self.dimensions.as_mut() {
+                    *h = height.into();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Skip over entropy-coded data, stopping at the next marker that isn't
+    /// a stuffed byte (0xff00), a restart marker (0xffd0-0xffd7) or fill
+    /// bytes (0xffff). With `stop_at_restart`, stop at restart markers too.
+    fn skip_scan_data(&mut self, stop_at_restart: bool) {
+        while let Some(pos) = memchr::memchr(0xff, &self.buf[self.position..]) {
+            self.position += pos;
+            match self.buf.get(self.position + 1) {
+                Some(0xd0..=0xd7) if stop_at_restart => return,
+                Some(0x00) | Some(0xd0..=0xd7) => self.position += 2,
+                Some(0xff) => self.position += 1,
+                _ => return,
+            }
+        }
+        self.position = self.buf.len();
+    }
+
+    /// Skip through the rest of the image, over any further scans, to the EOI
+    /// marker. Returns the position after it, or `None` if the data ends
+    /// first.
+    fn find_end(&mut self) -> Option<usize> {
+        loop {
+            self.skip_scan_data(false);
+            match self.read_segment() {
+                Ok(Some(_)) => {}
+                Ok(None) => break,
+                Err(_) => return None,
+            }
+        }
+        let position = self.position;
+        (self.buf.get(position..position + 2) == Some(b"\xff\xd9")).then_some(position + 2)
+    }
+
     /// Resync to the next marker.
     /// This is used to recover from errors in the JPEG data.
     fn resync(&mut self) {
@@ -221,6 +802,15 @@ impl<'a> JpegContext<'a> {
 }
 
 impl<'a> JpegSegment<'a> {
+    /// Convert to the public form of a segment.
+    fn to_segment(&self) -> Segment<'a> {
+        Segment {
+            marker: Marker::from_code(self.marker as u8),
+            offset: self.position,
+            data: self.data,
+        }
+    }
+
     /// Returns true if this segment is a SOF (Start Of Frame) marker.
     fn is_sof(&self) -> bool {
         self.marker >= 0xffc0
@@ -233,11 +823,27 @@ impl<'a> JpegSegment<'a> {
         self.marker == 0xfffe
     }
 
+    /// The position of the segment's data within the JPEG data.
+    fn data_offset(&self) -> usize {
+        self.position + 4
+    }
+
+    /// Returns true if this segment is an APPn marker.
+    fn is_app(&self, n: u8) -> bool {
+        self.marker == 0xffe0 + u16::from(n)
+    }
+
+    /// Returns true if this segment is a DNL (Define Number of Lines) marker.
+    fn is_dnl(&self) -> bool {
+        self.marker == 0xffdc
+    }
+
     /// Read the dimensions from a SOF (Start Of Frame) marker.
     fn read_sof(&self) -> Result<(u16, u16), JpegDecodingError> {
         if self.data.len() < 5 {
             return Err(JpegDecodingError::SofDataTooShort {
                 position: self.position,
+                length: self.data.len(),
             });
         }
         let height = u16::from_be_bytes([self.data[1], self.data[2]]);
@@ -245,15 +851,24 @@ impl<'a> JpegSegment<'a> {
         Ok((width, height))
     }
 
-    fn into_data(self) -> Vec<u8> {
-        self.data.into()
+    /// Read the number of lines from a DNL (Define Number of Lines) marker.
+    fn read_dnl(&self) -> Result<u16, JpegDecodingError> {
+        if self.data.len() < 2 {
+            return Err(JpegDecodingError::DnlDataTooShort {
+                position: self.position,
+                length: self.data.len(),
+            });
+        }
+        Ok(u16::from_be_bytes([self.data[0], self.data[1]]))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{Limits, Warning};
     use assert_matches::assert_matches;
+    use proptest::prelude::*;
 
     #[test]
     fn test_is_sof_marker() {
@@ -315,12 +930,7 @@ mod tests {
         // 0x1101: component 2 is 17x17 pixels
         // 0x03: component 3 uses table 3
 
-        let mut context = JpegContext {
-            buf: &data,
-            position: 0xc4,
-            comments: vec![],
-            dimensions: None,
-        };
+        let mut context = JpegContext::new(&data, 0xc4);
 
         let segment = context.read_segment().unwrap().unwrap();
         assert!(segment.is_sof());
@@ -343,16 +953,11 @@ mod tests {
         // 00000000: ffd8 ffe0 0010 4a46 4946 0001 0100 0000  ......JFIF......
         // 00000010: 0000 0000 fffe 000c 4275 7474 6572 6375  ........Buttercu
         // 00000020: 7073 ffe1 0016 4578 6966 0000 4d4d 002a  ps....Exif..MM.*
-        let mut context = JpegContext {
-            buf: &buf,
-            position: 0x14,
-            comments: vec![],
-            dimensions: None,
-        };
+        let mut context = JpegContext::new(&buf, 0x14);
         let segment = context.read_segment().unwrap().unwrap();
         assert_eq!(segment.marker, 0xfffe);
         assert!(segment.is_com());
-        assert_eq!(segment.into_data(), b"Buttercups");
+        assert_eq!(segment.data, b"Buttercups");
     }
 
     #[test]
@@ -366,12 +971,7 @@ mod tests {
         // Point the context into the middle of the COM segment; it should
         // resync to the next valid position, which is the APP1 segment starting
         // at 0x22.
-        let mut context = JpegContext {
-            buf: &buf,
-            position: 0x18,
-            comments: vec![],
-            dimensions: None,
-        };
+        let mut context = JpegContext::new(&buf, 0x18);
         let segment = context.read_segment().unwrap().unwrap();
         assert_eq!(segment.marker, 0xffe1);
         assert_eq!(segment.position, 0x22);
@@ -395,12 +995,7 @@ mod tests {
         //
         // Point the context to a few bytes before the end of the file. It
         // should resync to the end of the file, and return None.
-        let mut context = JpegContext {
-            buf: &buf,
-            position: 0xb950,
-            comments: vec![],
-            dimensions: None,
-        };
+        let mut context = JpegContext::new(&buf, 0xb950);
         let segment = context.read_segment().unwrap();
         assert!(segment.is_none());
     }
@@ -409,15 +1004,355 @@ mod tests {
     fn test_sof0_without_length() {
         let buf = &[0xff, 0xe0]; // SOF0 marker without length
 
-        let mut context = JpegContext {
-            buf,
-            position: 0,
-            comments: vec![],
-            dimensions: None,
-        };
+        let mut context = JpegContext::new(buf, 0);
         let segment = context.read_segment().unwrap_err();
 
-        assert_matches!(segment, JpegDecodingError::UnexpectedEndOfData(0));
+        assert_matches!(
+            segment,
+            JpegDecodingError::UnexpectedEndOfData { position: 0 }
+        );
+    }
+
+    #[test]
+    fn test_segment_length() {
+        // The APP1 segment at 0x22 claims to run past the end of the data.
+        let data = sample_image();
+        let err = read_jpeg_data(&data[..0x30]).unwrap_err();
+        assert_eq!(
+            err,
+            JpegDecodingError::TruncatedSegment {
+                marker: Marker::App(1),
+                position: 0x22,
+                length: u16::from_be_bytes([data[0x24], data[0x25]]).into(),
+                available: 0x0c,
+            }
+        );
+        assert_eq!(err.kind(), ErrorKind::Truncated);
+        assert_eq!(err.offset(), Some(0x22));
+
+        // A length too short to cover the length field itself.
+        let mut data = data;
+        data[0x24..0x26].copy_from_slice(&[0, 1]);
+        let err = read_jpeg_data(&data).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid APP1 segment length 1 at position 34"
+        );
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_dnl_height() {
+        let buf = dnl_image();
+
+        // By default, we stop at the SOS marker and report the height from
+        // the SOF segment.
+        let metadata = read_jpeg_data(&buf).unwrap();
+        assert_eq!((metadata.width, metadata.height), (16, 0));
+
+        // When asked to, we skip the scan data (including stuffed bytes and
+        // restart markers) and read the height from the DNL segment.
+        let options = JpegOptions::new().scan_for_dnl(true);
+        let metadata = read_jpeg_data_with(&buf, &options).unwrap();
+        assert_eq!((metadata.width, metadata.height), (16, 32));
+    }
+
+    #[test]
+    fn test_dnl_missing() {
+        let mut buf = dnl_image();
+
+        // Replace the DNL segment with EOI: the height stays at 0.
+        let dnl = buf.len() - 8;
+        buf.splice(dnl.., [0xff, 0xd9]);
+        let options = JpegOptions::new().scan_for_dnl(true);
+        let metadata = read_jpeg_data_with(&buf, &options).unwrap();
+        assert_eq!((metadata.width, metadata.height), (16, 0));
+    }
+
+    #[test]
+    fn test_dnl_too_short() {
+        let mut buf = dnl_image();
+
+        // Make the DNL segment's length 2, so it has no room for the height.
+        let dnl = buf.len() - 8;
+        buf.splice(dnl.., [0xff, 0xdc, 0x00, 0x02, 0xff, 0xd9]);
+        let options = JpegOptions::new().scan_for_dnl(true);
+        let err = read_jpeg_data_with(&buf, &options).unwrap_err();
+        assert_matches!(err, JpegDecodingError::DnlDataTooShort { position, length: 0 } if position == dnl);
+    }
+
+    #[test]
+    fn test_jpeg_metadata() {
+        let metadata = read_jpeg_data(&sample_image()).unwrap();
+        let jpeg = metadata.jpeg.unwrap();
+        assert_eq!(jpeg.components, 3);
+        assert_eq!(jpeg.jfif.unwrap().version, (1, 1));
+        assert_eq!(jpeg.jfxx, None);
+        assert_eq!(jpeg.adobe, None);
+        assert_eq!(jpeg.color_model, ColorModel::YCbCr);
+        assert_eq!(jpeg.mpf, None);
+    }
+
+    #[test]
+    fn test_read_options() {
+        let mut buf = sample_image();
+        let mut segments = Vec::new();
+        for (marker, data) in [
+            (0xe1, &b"http://ns.adobe.com/xap/1.0/\0<x:xmpmeta/>"[..]),
+            (0xe2, b"ICC_PROFILE\0\x02\x02def"),
+            (0xe2, b"ICC_PROFILE\0\x01\x02abc"),
+        ] {
+            segments.extend_from_slice(&[0xff, marker]);
+            segments.extend_from_slice(&(data.len() as u16 + 2).to_be_bytes());
+            segments.extend_from_slice(data);
+        }
+        buf.splice(2..2, segments);
+
+        // The payloads are only extracted when asked for.
+        let metadata = read_jpeg_data(&buf).unwrap();
+        assert_eq!(
+            (metadata.exif, metadata.xmp, metadata.icc_profile),
+            (None, None, None)
+        );
+
+        let options = ReadOptions::new().exif(true).xmp(true).icc_profile(true);
+        let metadata = read_jpeg_data_ref(&buf, &options, &mut ()).unwrap();
+        assert_eq!(metadata.exif, Some(&b"MM\0*\0\0\0\x08\0\0\0\0\0\0"[..]));
+        assert_eq!(metadata.xmp.as_deref(), Some(&b"<x:xmpmeta/>"[..]));
+        assert_eq!(metadata.icc_profile.as_deref(), Some(&b"abcdef"[..]));
+
+        // Stopping at the dimensions still reads what comes before the SOF.
+        let options = ReadOptions::new().stop_at(StopAt::Dimensions);
+        let metadata = read_jpeg_data_ref(&buf, &options, &mut ()).unwrap();
+        assert_eq!((metadata.width, metadata.height), (512, 341));
+        assert_eq!(metadata.comments, vec![b"Buttercups"]);
+        let metadata = read_jpeg_data_ref(&buf, &options.comments(false), &mut ()).unwrap();
+        assert!(metadata.comments.is_empty());
+    }
+
+    #[test]
+    fn test_limits() {
+        let buf = sample_image();
+        let read = |limits| read_jpeg_data_ref(&buf, &ReadOptions::new().limits(limits), &mut ());
+        let err = read(Limits::new().max_height(Some(300))).unwrap_err();
+        assert_matches!(
+            err,
+            JpegDecodingError::LimitExceeded {
+                kind: LimitKind::Height,
+                value: 341,
+                limit: 300
+            }
+        );
+        let err = read(Limits::new().max_segments(Some(5))).unwrap_err();
+        assert_matches!(
+            err,
+            JpegDecodingError::LimitExceeded {
+                kind: LimitKind::Segments,
+                value: 6,
+                ..
+            }
+        );
+        let err = read(Limits::new().max_comment_bytes(Some(9))).unwrap_err();
+        assert_matches!(
+            err,
+            JpegDecodingError::LimitExceeded {
+                kind: LimitKind::CommentBytes,
+                value: 10,
+                limit: 9
+            }
+        );
+        assert!(read(
+            Limits::new()
+                .max_segments(Some(11))
+                .max_comment_bytes(Some(10))
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn test_strictness() {
+        let mut buf = sample_image();
+        // Garbage between the COM and APP1 segments.
+        buf.splice(0x22..0x22, [0, 1, 2]);
+
+        let metadata = read_jpeg_data(&buf).unwrap();
+        assert_eq!(metadata.comments, vec![b"Buttercups"]);
+        assert_eq!(
+            metadata.warnings,
+            vec![Warning {
+                offset: 0x22,
+                kind: WarningKind::ExtraneousBytes,
+                message: "3 extraneous bytes at position 34".to_string(),
+            }]
+        );
+
+        let options = ReadOptions::new().strictness(Strictness::Strict);
+        let err = read_jpeg_data_ref(&buf, &options, &mut ()).unwrap_err();
+        assert_eq!(
+            err,
+            JpegDecodingError::ExtraneousBytes {
+                position: 0x22,
+                count: 3
+            }
+        );
+        // The error is at the same offset as the warning.
+        assert_eq!(err.offset(), Some(0x22));
+        assert!(read_jpeg_data(&sample_image()).unwrap().warnings.is_empty());
+    }
+
+    #[test]
+    fn test_truncation() {
+        let data = sample_image();
+        let to_end = ReadOptions::new().stop_at(StopAt::End);
+        let metadata = read_jpeg_data_ref(&data, &to_end, &mut ()).unwrap();
+        assert!(!metadata.truncated);
+        assert_eq!(metadata.trailing_bytes, None);
+
+        let mut appended = data.clone();
+        appended.extend_from_slice(b"PK\x03\x04");
+        let metadata = read_jpeg_data_ref(&appended, &to_end, &mut ()).unwrap();
+        assert_eq!(metadata.trailing_bytes, Some(data.len()..data.len() + 4));
+        // Only the metadata is read by default, so the end isn't looked for.
+        let metadata = read_jpeg_data(&appended).unwrap();
+        assert_eq!(metadata.trailing_bytes, None);
+
+        // Cut short in the middle of the scan data.
+        let cut = &data[..data.len() - 100];
+        let metadata = read_jpeg_data_ref(cut, &to_end, &mut ()).unwrap();
+        assert!(metadata.truncated);
+        assert_eq!(metadata.warnings[0].kind, WarningKind::Truncated);
+        let strict = to_end.strictness(Strictness::Strict);
+        let err = read_jpeg_data_ref(cut, &strict, &mut ()).unwrap_err();
+        assert_eq!(
+            err,
+            JpegDecodingError::MissingEoi {
+                position: cut.len()
+            }
+        );
+        assert_eq!(err.kind(), ErrorKind::Truncated);
+    }
+
+    #[test]
+    fn test_mpf() {
+        let buf = mpf::tests::mpf_image(false);
+        let metadata = read_jpeg_data(&buf).unwrap();
+        assert_eq!((metadata.width, metadata.height), (64, 48));
+        let jpeg = metadata.jpeg.unwrap();
+        let mpf = jpeg.mpf.unwrap();
+        let sizes: Vec<_> = mpf.entries.iter().map(|e| (e.width, e.height)).collect();
+        assert_eq!(sizes, vec![(64, 48), (32, 24)]);
+        assert_eq!(jpeg.gain_map, None);
+    }
+
+    #[test]
+    fn test_gain_map() {
+        let buf = gainmap::tests::ultra_hdr_image();
+        let options = ReadOptions::new().xmp(true);
+        let metadata = read_jpeg_data_ref(&buf, &options, &mut ()).unwrap();
+        let gain_map = metadata.jpeg.unwrap().gain_map.unwrap();
+        assert_eq!((gain_map.width, gain_map.height), (32, 24));
+        assert_eq!(gain_map.hdr_capacity_max(), 2.5);
+    }
+
+    #[test]
+    fn test_visitor() {
+        struct App9(Vec<u8>);
+        impl MetadataVisitor for App9 {
+            fn visit_jpeg_segment(&mut self, segment: &Segment) -> Flow {
+                if segment.marker == Marker::App(9) {
+                    self.0 = segment.data.to_vec();
+                    return Flow::Stop;
+                }
+                Flow::Continue
+            }
+        }
+
+        let mut buf = sample_image();
+        buf.splice(2..2, *b"\xff\xe9\x00\x05abc");
+        let mut visitor = App9(Vec::new());
+        let options = JpegOptions::default();
+        let err = read_jpeg_data_with_visitor(&buf, &options, &mut visitor).unwrap_err();
+        assert_eq!(visitor.0, b"abc");
+        // We stopped before reaching the SOF segment.
+        assert_matches!(err, JpegDecodingError::NoSofMarker { position: 9, .. });
+
+        // Stopping at the SOF segment returns what was read up to there,
+        // without the DHT segments after it.
+        struct StopAtSof(Vec<Marker>);
+        impl MetadataVisitor for StopAtSof {
+            fn visit_jpeg_segment(&mut self, segment: &Segment) -> Flow {
+                self.0.push(segment.marker);
+                if segment.marker == Marker::Sof(0) {
+                    return Flow::Stop;
+                }
+                Flow::Continue
+            }
+        }
+        let buf = sample_image();
+        let mut visitor = StopAtSof(Vec::new());
+        let metadata = read_jpeg_data_with_visitor(&buf, &options, &mut visitor).unwrap();
+        assert_eq!((metadata.width, metadata.height), (512, 341));
+        assert_eq!(metadata.comments, vec![b"Buttercups"]);
+        assert_eq!(visitor.0.last(), Some(&Marker::Sof(0)));
+        assert!(!visitor.0.contains(&Marker::Dht));
+
+        // Stopping at the SOS segment means the scan data isn't searched for
+        // the EOI marker, so cutting it off goes unnoticed.
+        struct StopAtSos;
+        impl MetadataVisitor for StopAtSos {
+            fn visit_jpeg_segment(&mut self, segment: &Segment) -> Flow {
+                match segment.marker {
+                    Marker::Sos => Flow::Stop,
+                    _ => Flow::Continue,
+                }
+            }
+        }
+        let mut buf = sample_image();
+        buf.truncate(buf.len() - 2);
+        let options = ReadOptions::new().stop_at(StopAt::End);
+        let metadata = read_jpeg_data_ref(&buf, &options, &mut StopAtSos).unwrap();
+        assert!(!metadata.truncated);
+        let metadata = read_jpeg_data_ref(&buf, &options, &mut ()).unwrap();
+        assert!(metadata.truncated);
+    }
+
+    #[test]
+    fn test_color_model() {
+        let jfif = app::read_jfif(b"JFIF\0\x01\x01\x00\x00\x01\x00\x01\x00\x00");
+        let adobe = |transform| Adobe {
+            dct_encode_version: 100,
+            flags0: 0,
+            flags1: 0,
+            transform,
+        };
+        let rgb = adobe(AdobeTransform::None);
+        let ycc = adobe(AdobeTransform::YCbCr);
+        let ycck = adobe(AdobeTransform::Ycck);
+
+        let resolve = ColorModel::resolve;
+        assert_eq!(resolve(b"\x01", None, None), ColorModel::Grayscale);
+        assert_eq!(resolve(b"\x01\x02\x03", None, None), ColorModel::YCbCr);
+        assert_eq!(
+            resolve(b"\x01\x02\x03", jfif.as_ref(), None),
+            ColorModel::YCbCr
+        );
+        assert_eq!(resolve(b"RGB", None, None), ColorModel::Rgb);
+        assert_eq!(resolve(b"RGB", jfif.as_ref(), None), ColorModel::YCbCr);
+        assert_eq!(resolve(b"\x01\x02\x03", None, Some(&rgb)), ColorModel::Rgb);
+        assert_eq!(
+            resolve(b"\x01\x02\x03", None, Some(&ycc)),
+            ColorModel::YCbCr
+        );
+        assert_eq!(resolve(b"\x01\x02\x03\x04", None, None), ColorModel::Cmyk);
+        assert_eq!(
+            resolve(b"\x01\x02\x03\x04", None, Some(&rgb)),
+            ColorModel::Cmyk
+        );
+        assert_eq!(
+            resolve(b"\x01\x02\x03\x04", None, Some(&ycck)),
+            ColorModel::Ycck
+        );
+        assert_eq!(resolve(b"\x01\x02", None, None), ColorModel::Unknown);
     }
 
     /// Create a SOF0 segment from the given data, and read its dimensions.
@@ -432,8 +1367,74 @@ mod tests {
         segment.read_sof().unwrap()
     }
 
+    /// A minimal JPEG with a height of 0 in its SOF segment, and the real
+    /// height (32) in a DNL segment after the first scan.
+    #[rustfmt::skip]
+    fn dnl_image() -> Vec<u8> {
+        vec![
+            0xff, 0xd8, // SOI
+            0xff, 0xc0, 0x00, 0x0b, 0x08, 0x00, 0x00, 0x00, 0x10, 0x01, 0x01, 0x11, 0x00, // SOF0
+            0xff, 0xda, 0x00, 0x08, 0x01, 0x01, 0x00, 0x00, 0x3f, 0x00, // SOS
+            0x12, 0xff, 0x00, 0x34, 0xff, 0xd0, 0x56, 0xff, 0xff, 0xd1, 0x78, // Scan data
+            0xff, 0xdc, 0x00, 0x04, 0x00, 0x20, // DNL
+            0xff, 0xd9, // EOI
+        ]
+    }
+
     /// Read the sample image from disk.
     fn sample_image() -> Vec<u8> {
         std::fs::read("src/buttercups.jpg").unwrap()
     }
+
+    /// A segment with a marker and identifier that the reader looks at, and
+    /// whatever data after that.
+    fn arbitrary_segment() -> impl Strategy<Value = (u8, Vec<u8>)> {
+        let marker = prop_oneof![
+            prop::sample::select(vec![0xc0, 0xc2, 0xda, 0xdc, 0xe0, 0xe1, 0xe2, 0xee, 0xfe]),
+            any::<u8>(),
+        ];
+        let identifier = prop::sample::select(vec![
+            &b""[..],
+            b"JFIF\0",
+            b"JFXX\0\x10\xff\xd8",
+            b"JFXX\0\x11",
+            b"JFXX\0\x13",
+            b"Exif\0\0MM\0*\0\0\0\x08",
+            b"Exif\0\0II*\0\x08\0\0\0",
+            b"MPF\0MM\0*\0\0\0\x08\0\x01\xb0\x02\0\x07",
+            b"MPF\0II*\0\x08\0\0\0\x01\0\x02\xb0\x07\0",
+            b"ICC_PROFILE\0",
+            embedded::XMP_IDENTIFIER,
+            b"Adobe",
+        ]);
+        let data = prop::collection::vec(any::<u8>(), 0..64);
+        (marker, identifier, data)
+            .prop_map(|(marker, identifier, data)| (marker, [identifier, &data].concat()))
+    }
+
+    proptest! {
+        #[test]
+        fn test_arbitrary_segments_never_panic(
+            segments in prop::collection::vec(arbitrary_segment(), 0..12),
+            scan in prop::collection::vec(any::<u8>(), 0..32),
+        ) {
+            let mut buf = vec![0xff, 0xd8];
+            for (marker, data) in &segments {
+                buf.extend_from_slice(&[0xff, *marker]);
+                buf.extend_from_slice(&(data.len() as u16 + 2).to_be_bytes());
+                buf.extend_from_slice(data);
+            }
+            buf.extend_from_slice(&scan);
+
+            let options = ReadOptions::new()
+                .exif(true)
+                .xmp(true)
+                .icc_profile(true)
+                .scan_for_dnl(true)
+                .strictness(Strictness::Lenient);
+            let _ = read_jpeg_data_ref(&buf, &options, &mut ());
+            let _ = read_thumbnails(&buf);
+            let _ = Segments::new(&buf).through_scans(true).count();
+        }
+    }
 }
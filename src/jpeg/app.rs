@@ -0,0 +1,219 @@
+//! Decoders for the APP0 (JFIF and JFXX) and APP14 (Adobe) segments.
+//!
+//! These segments are optional, and real-world files get them wrong often
+//! enough that a malformed one is ignored rather than treated as an error.
+
+/// The contents of a JFIF APP0 segment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Jfif {
+    /// The JFIF version, as (major, minor). Most files use 1.01 or 1.02.
+    pub version: (u8, u8),
+    pub density_units: DensityUnits,
+    pub x_density: u16,
+    pub y_density: u16,
+    /// Width of the uncompressed RGB thumbnail following the header, which
+    /// is 0 if there is no thumbnail.
+    pub thumbnail_width: u8,
+    /// Height of the uncompressed RGB thumbnail following the header, which
+    /// is 0 if there is no thumbnail.
+    pub thumbnail_height: u8,
+}
+
+/// The units of the density fields in a JFIF header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DensityUnits {
+    /// No units: the densities only give the pixel aspect ratio.
+    AspectRatio,
+    PixelsPerInch,
+    PixelsPerCm,
+    Unknown(u8),
+}
+
+/// The contents of a JFXX (JFIF extension) APP0 segment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Jfxx {
+    pub format: JfxxFormat,
+    /// Width of the thumbnail. For JPEG thumbnails, this is read from the
+    /// thumbnail's own SOF segment, and is 0 if that can't be read.
+    pub thumbnail_width: u32,
+    /// Height of the thumbnail. For JPEG thumbnails, this is read from the
+    /// thumbnail's own SOF segment, and is 0 if that can't be read.
+    pub thumbnail_height: u32,
+}
+
+/// How the thumbnail in a JFXX segment is stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JfxxFormat {
+    /// A complete JPEG image (extension code 0x10).
+    Jpeg,
+    /// One byte per pixel, indexing a 256-entry RGB palette (extension code
+    /// 0x11).
+    Palette,
+    /// Three bytes per pixel, RGB (extension code 0x13).
+    Rgb,
+    Unknown(u8),
+}
+
+/// The contents of an Adobe APP14 segment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Adobe {
+    pub dct_encode_version: u16,
+    pub flags0: u16,
+    pub flags1: u16,
+    pub transform: AdobeTransform,
+}
+
+/// The color transform recorded in an Adobe APP14 segment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdobeTransform {
+    /// No transform: the components are RGB or CMYK.
+    None,
+    /// The components are YCbCr.
+    YCbCr,
+    /// The components are YCCK.
+    Ycck,
+    Unknown(u8),
+}
+
+/// Decode a JFIF APP0 segment, returning `None` if it is not one.
+pub(crate) fn read_jfif(data: &[u8]) -> Option<Jfif> {
+    let data = data.strip_prefix(b"JFIF\0")?;
+    if data.len() < 9 {
+        return None;
+    }
+    let density_units = match data[2] {
+        0 => DensityUnits::AspectRatio,
+        1 => DensityUnits::PixelsPerInch,
+        2 => DensityUnits::PixelsPerCm,
+        n => DensityUnits::Unknown(n),
+    };
+    Some(Jfif {
+        version: (data[0], data[1]),
+        density_units,
+        x_density: u16::from_be_bytes([data[3], data[4]]),
+        y_density: u16::from_be_bytes([data[5], data[6]]),
+        thumbnail_width: data[7],
+        thumbnail_height: data[8],
+    })
+}
+
+/// Decode a JFXX APP0 segment, returning `None` if it is not one.
+pub(crate) fn read_jfxx(data: &[u8]) -> Option<Jfxx> {
+    let data = data.strip_prefix(b"JFXX\0")?;
+    let (&code, thumbnail) = data.split_first()?;
+    let (format, thumbnail_width, thumbnail_height) = match code {
+        0x10 => {
+            let (width, height) = super::read_dimensions(thumbnail).unwrap_or_default();
+            (JfxxFormat::Jpeg, width, height)
+        }
+        0x11 | 0x13 => {
+            let format = if code == 0x11 {
+                JfxxFormat::Palette
+            } else {
+                JfxxFormat::Rgb
+            };
+            match thumbnail {
+                [w, h, ..] => (format, u32::from(*w), u32::from(*h)),
+                _ => return None,
+            }
+        }
+        n => (JfxxFormat::Unknown(n), 0, 0),
+    };
+    Some(Jfxx {
+        format,
+        thumbnail_width,
+        thumbnail_height,
+    })
+}
+
+/// Decode an Adobe APP14 segment, returning `None` if it is not one.
+pub(crate) fn read_adobe(data: &[u8]) -> Option<Adobe> {
+    let data = data.strip_prefix(b"Adobe")?;
+    if data.len() < 7 {
+        return None;
+    }
+    let transform = match data[6] {
+        0 => AdobeTransform::None,
+        1 => AdobeTransform::YCbCr,
+        2 => AdobeTransform::Ycck,
+        n => AdobeTransform::Unknown(n),
+    };
+    Some(Adobe {
+        dct_encode_version: u16::from_be_bytes([data[0], data[1]]),
+        flags0: u16::from_be_bytes([data[2], data[3]]),
+        flags1: u16::from_be_bytes([data[4], data[5]]),
+        transform,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_jfif() {
+        let data = b"JFIF\0\x01\x02\x01\x00\x48\x00\x48\x00\x00";
+        assert_eq!(
+            read_jfif(data),
+            Some(Jfif {
+                version: (1, 2),
+                density_units: DensityUnits::PixelsPerInch,
+                x_density: 72,
+                y_density: 72,
+                thumbnail_width: 0,
+                thumbnail_height: 0,
+            })
+        );
+
+        // Too short, or not JFIF at all.
+        assert_eq!(read_jfif(b"JFIF\0\x01\x02\x01\x00\x48\x00\x48\x00"), None);
+        assert_eq!(read_jfif(b"JFXX\0\x13\x01\x01\x00\x00\x00"), None);
+    }
+
+    #[test]
+    fn test_read_jfxx() {
+        let data = b"JFXX\0\x13\x02\x01\xff\x00\x00\x00\xff\x00";
+        assert_eq!(
+            read_jfxx(data),
+            Some(Jfxx {
+                format: JfxxFormat::Rgb,
+                thumbnail_width: 2,
+                thumbnail_height: 1,
+            })
+        );
+
+        // A JPEG thumbnail takes its dimensions from its SOF segment.
+        let mut data = b"JFXX\0\x10".to_vec();
+        data.extend_from_slice(&[0xff, 0xd8]);
+        data.extend_from_slice(&[0xff, 0xc0, 0x00, 0x0b, 0x08, 0x00, 0x06, 0x00, 0x08]);
+        data.extend_from_slice(&[0x01, 0x01, 0x11, 0x00, 0xff, 0xd9]);
+        assert_eq!(
+            read_jfxx(&data),
+            Some(Jfxx {
+                format: JfxxFormat::Jpeg,
+                thumbnail_width: 8,
+                thumbnail_height: 6,
+            })
+        );
+
+        assert_eq!(read_jfxx(b"JFXX\0\x11\x02"), None);
+    }
+
+    #[test]
+    fn test_read_adobe() {
+        let data = b"Adobe\x00\x64\x80\x00\x00\x00\x02";
+        assert_eq!(
+            read_adobe(data),
+            Some(Adobe {
+                dct_encode_version: 100,
+                flags0: 0x8000,
+                flags1: 0,
+                transform: AdobeTransform::Ycck,
+            })
+        );
+        assert_eq!(read_adobe(b"Adobe\x00\x64\x80\x00\x00\x00"), None);
+    }
+}
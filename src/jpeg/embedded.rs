@@ -0,0 +1,100 @@
+//! Extracting the EXIF and XMP payloads from APP1 segments, and the ICC
+//! profile from APP2 segments.
+
+use std::borrow::Cow;
+
+const EXIF_IDENTIFIER: &[u8] = b"Exif\0\0";
+pub(crate) const XMP_IDENTIFIER: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+const ICC_IDENTIFIER: &[u8] = b"ICC_PROFILE\0";
+
+/// One piece of an ICC profile. Profiles too big for a single segment are
+/// split across several, numbered from 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct IccChunk<'a> {
+    sequence: u8,
+    count: u8,
+    data: &'a [u8],
+}
+
+/// Return the TIFF data in an EXIF APP1 segment, or `None` if it is not one.
+pub(crate) fn read_exif(data: &[u8]) -> Option<&[u8]> {
+    data.strip_prefix(EXIF_IDENTIFIER)
+}
+
+/// Return the XMP packet in an XMP APP1 segment, or `None` if it is not one.
+pub(crate) fn read_xmp(data: &[u8]) -> Option<&[u8]> {
+    data.strip_prefix(XMP_IDENTIFIER)
+}
+
+/// Decode an ICC profile APP2 segment, or return `None` if it is not one.
+pub(crate) fn read_icc_chunk(data: &[u8]) -> Option<IccChunk<'_>> {
+    match *data.strip_prefix(ICC_IDENTIFIER)? {
+        [sequence, count, ref data @ ..] => Some(IccChunk {
+            sequence,
+            count,
+            data,
+        }),
+        _ => None,
+    }
+}
+
+/// Put the pieces of an ICC profile back together, returning `None` if any
+/// are missing or they don't agree on how many there are. A profile in a
+/// single segment is borrowed rather than copied.
+pub(crate) fn assemble_icc_profile<'a>(chunks: &[IccChunk<'a>]) -> Option<Cow<'a, [u8]>> {
+    let count = chunks.first()?.count;
+    if usize::from(count) != chunks.len() || chunks.iter().any(|c| c.count != count) {
+        return None;
+    }
+    let mut pieces = vec![None; chunks.len()];
+    for chunk in chunks {
+        let piece = pieces.get_mut(usize::from(chunk.sequence).checked_sub(1)?)?;
+        if piece.replace(chunk.data).is_some() {
+            return None;
+        }
+    }
+    match pieces.as_slice() {
+        [Some(data)] => Some(Cow::Borrowed(*data)),
+        _ => pieces
+            .into_iter()
+            .collect::<Option<Vec<_>>>()
+            .map(|pieces| Cow::Owned(pieces.concat())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_exif_and_xmp() {
+        assert_eq!(read_exif(b"Exif\0\0MM\0*"), Some(&b"MM\0*"[..]));
+        assert_eq!(read_exif(b"Exif\0MM\0*"), None);
+        let xmp = b"http://ns.adobe.com/xap/1.0/\0<x:xmpmeta/>";
+        assert_eq!(read_xmp(xmp), Some(&b"<x:xmpmeta/>"[..]));
+        assert_eq!(read_xmp(b"<x:xmpmeta/>"), None);
+    }
+
+    #[test]
+    fn test_assemble_icc_profile() {
+        let one = read_icc_chunk(b"ICC_PROFILE\0\x01\x01abc").unwrap();
+        assert_eq!(
+            assemble_icc_profile(&[one]),
+            Some(Cow::Borrowed(&b"abc"[..]))
+        );
+
+        let first = read_icc_chunk(b"ICC_PROFILE\0\x01\x02abc").unwrap();
+        let second = read_icc_chunk(b"ICC_PROFILE\0\x02\x02def").unwrap();
+        assert_eq!(
+            assemble_icc_profile(&[second, first]).as_deref(),
+            Some(&b"abcdef"[..])
+        );
+
+        // Missing, repeated or misnumbered pieces.
+        assert_eq!(assemble_icc_profile(&[first]), None);
+        assert_eq!(assemble_icc_profile(&[first, first]), None);
+        let zeroth = read_icc_chunk(b"ICC_PROFILE\0\x00\x02abc").unwrap();
+        assert_eq!(assemble_icc_profile(&[zeroth, second]), None);
+        assert_eq!(read_icc_chunk(b"ICC_PROFILE\0\x01"), None);
+    }
+}
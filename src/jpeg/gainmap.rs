@@ -0,0 +1,295 @@
+//! Detection of Ultra HDR gain maps.
+//!
+//! An Ultra HDR image is an ordinary JPEG with a second, gain map, JPEG
+//! appended to it and listed in its MPF segment. The gain map image carries
+//! its parameters as `hdrgm:` properties in an XMP APP1 segment.
+
+use std::ops::Range;
+
+use super::MpEntry;
+
+/// A gain map image attached to a JPEG through MPF.
+///
+/// The `hdrgm:` parameters are floating-point numbers, but are stored by
+/// their bit patterns so that the struct can be compared with `Eq`; read them
+/// through the methods of the same name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct GainMap {
+    /// The index of the gain map image in the MPF entries.
+    pub mpf_index: usize,
+    /// Where the gain map image's data is in the file.
+    pub range: Range<usize>,
+    pub width: u32,
+    pub height: u32,
+    /// The `hdrgm:Version` property, normally "1.0".
+    pub version: Option<String>,
+    gain_map_min: [u32; 3],
+    gain_map_max: [u32; 3],
+    gamma: [u32; 3],
+    offset_sdr: [u32; 3],
+    offset_hdr: [u32; 3],
+    hdr_capacity_min: u32,
+    hdr_capacity_max: u32,
+    pub base_rendition_is_hdr: bool,
+}
+
+impl GainMap {
+    /// The `hdrgm:GainMapMin` property, per channel (R, G, B). A single value
+    /// is used for all three channels.
+    pub fn gain_map_min(&self) -> [f32; 3] {
+        self.gain_map_min.map(f32::from_bits)
+    }
+
+    /// The `hdrgm:GainMapMax` property, per channel.
+    pub fn gain_map_max(&self) -> [f32; 3] {
+        self.gain_map_max.map(f32::from_bits)
+    }
+
+    /// The `hdrgm:Gamma` property, per channel.
+    pub fn gamma(&self) -> [f32; 3] {
+        self.gamma.map(f32::from_bits)
+    }
+
+    /// The `hdrgm:OffsetSDR` property, per channel.
+    pub fn offset_sdr(&self) -> [f32; 3] {
+        self.offset_sdr.map(f32::from_bits)
+    }
+
+    /// The `hdrgm:OffsetHDR` property, per channel.
+    pub fn offset_hdr(&self) -> [f32; 3] {
+        self.offset_hdr.map(f32::from_bits)
+    }
+
+    /// The `hdrgm:HDRCapacityMin` property.
+    pub fn hdr_capacity_min(&self) -> f32 {
+        f32::from_bits(self.hdr_capacity_min)
+    }
+
+    /// The `hdrgm:HDRCapacityMax` property.
+    pub fn hdr_capacity_max(&self) -> f32 {
+        f32::from_bits(self.hdr_capacity_max)
+    }
+}
+
+/// Read the gain map parameters from the XMP packet of the image at `index`
+/// in the MPF entries, or return `None` if it has no `hdrgm:` gain map
+/// properties.
+pub(crate) fn read_gain_map(
+    xmp: &[u8],
+    index: usize,
+    entry: &MpEntry,
+    range: Range<usize>,
+) -> Option<GainMap> {
+    let xmp = std::str::from_utf8(xmp).ok()?;
+    // GainMapMax and HDRCapacityMax are the only properties without a
+    // default, so they are what mark this as a gain map.
+    let gain_map_max = channels(property(xmp, "GainMapMax")?)?;
+    let hdr_capacity_max = scalar(property(xmp, "HDRCapacityMax")?)?;
+
+    let channels_or = |name, default| {
+        property(xmp, name)
+            .and_then(channels)
+            .unwrap_or([default; 3])
+    };
+    let scalar_or = |name, default| property(xmp, name).and_then(scalar).unwrap_or(default);
+
+    Some(GainMap {
+        mpf_index: index,
+        range,
+        width: entry.width,
+        height: entry.height,
+        version: property(xmp, "Version").map(|v| v.concat()),
+        gain_map_min: channels_or("GainMapMin", 0.0).map(f32::to_bits),
+        gain_map_max: gain_map_max.map(f32::to_bits),
+        gamma: channels_or("Gamma", 1.0).map(f32::to_bits),
+        offset_sdr: channels_or("OffsetSDR", 1.0 / 64.0).map(f32::to_bits),
+        offset_hdr: channels_or("OffsetHDR", 1.0 / 64.0).map(f32::to_bits),
+        hdr_capacity_min: scalar_or("HDRCapacityMin", 0.0).to_bits(),
+        hdr_capacity_max: hdr_capacity_max.to_bits(),
+        base_rendition_is_hdr: property(xmp, "BaseRenditionIsHDR")
+            .is_some_and(|v| v.concat().eq_ignore_ascii_case("true")),
+    })
+}
+
+/// Find the value of an `hdrgm:` property, which can be written as an
+/// attribute (`hdrgm:Gamma="1.0"`), as an element
+/// (`<hdrgm:Gamma>1.0</hdrgm:Gamma>`), or as an element containing an
+/// `rdf:Seq` with one `rdf:li` per channel.
+fn property<'a>(xmp: &'a str, name: &str) -> Option<Vec<&'a str>> {
+    let tag = format!("hdrgm:{}", name);
+    let mut search = xmp;
+    loop {
+        let found = search.find(&tag)?;
+        let before = search[..found].chars().next_back();
+        let rest = &search[found + tag.len()..];
+        search = rest;
+
+        // Skip matches that are part of a longer name, or a closing tag.
+        if !matches!(before, Some(c) if c == '<' || c.is_whitespace()) {
+            continue;
+        }
+        // A match that can't be read, such as one in a comment, doesn't stop
+        // the search for a later one that can.
+        let rest = rest.trim_start();
+        if let Some(rest) = rest.strip_prefix('=') {
+            let rest = rest.trim_start();
+            let Some(quote) = rest.chars().next().filter(|&c| c == '"' || c == '\'') else {
+                continue;
+            };
+            let value = &rest[1..];
+            let Some(end) = value.find(quote) else {
+                continue;
+            };
+            return Some(vec![&value[..end]]);
+        } else if let Some(rest) = rest.strip_prefix('>') {
+            let Some(end) = rest.find(&format!("</{}>", tag)) else {
+                continue;
+            };
+            let content = &rest[..end];
+            if !content.contains("<rdf:li") {
+                return Some(vec![content.trim()]);
+            }
+            return Some(
+                content
+                    .split("<rdf:li")
+                    .skip(1)
+                    .filter_map(|item| {
+                        let item = &item[item.find('>')? + 1..];
+                        Some(item[..item.find("</rdf:li>")?].trim())
+                    })
+                    .collect(),
+            );
+        }
+    }
+}
+
+fn scalar(values: Vec<&str>) -> Option<f32> {
+    values.first()?.trim().parse().ok()
+}
+
+fn channels(values: Vec<&str>) -> Option<[f32; 3]> {
+    let values = values
+        .iter()
+        .map(|v| v.trim().parse().ok())
+        .collect::<Option<Vec<f32>>>()?;
+    match values[..] {
+        [v] => Some([v; 3]),
+        [r, g, b] => Some([r, g, b]),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::jpeg::embedded::XMP_IDENTIFIER;
+    use crate::jpeg::mpf::read_mpf;
+    use crate::{ReadOptions, StopAt};
+
+    #[test]
+    fn test_property() {
+        let xmp = r#"<rdf:Description hdrgm:Version="1.0" hdrgm:GainMapMax = '3.5'
+            xmlns:hdrgm="http://ns.adobe.com/hdr-gain-map/1.0/">
+            <hdrgm:Gamma>
+              <rdf:Seq><rdf:li>1.0</rdf:li><rdf:li>1.5</rdf:li><rdf:li>2</rdf:li></rdf:Seq>
+            </hdrgm:Gamma>
+            <hdrgm:HDRCapacityMax> 3.5 </hdrgm:HDRCapacityMax>
+          </rdf:Description>"#;
+        assert_eq!(property(xmp, "Version"), Some(vec!["1.0"]));
+        assert_eq!(property(xmp, "GainMapMax"), Some(vec!["3.5"]));
+        assert_eq!(property(xmp, "Gamma"), Some(vec!["1.0", "1.5", "2"]));
+        assert_eq!(property(xmp, "HDRCapacityMax"), Some(vec!["3.5"]));
+        assert_eq!(property(xmp, "GainMapMin"), None);
+        assert_eq!(property(xmp, "Capacity"), None);
+
+        // A match that can't be read is skipped, rather than ending the
+        // search.
+        let xmp = r#"<!-- hdrgm:Gamma= --> <hdrgm:Gamma>2</hdrgm:Gamma>"#;
+        assert_eq!(property(xmp, "Gamma"), Some(vec!["2"]));
+        let xmp = r#"<!-- <hdrgm:Gamma> --> <x hdrgm:Gamma="2"/>"#;
+        assert_eq!(property(xmp, "Gamma"), Some(vec!["2"]));
+    }
+
+    #[test]
+    fn test_channels() {
+        assert_eq!(channels(vec!["2"]), Some([2.0; 3]));
+        assert_eq!(channels(vec!["1", "2", "3"]), Some([1.0, 2.0, 3.0]));
+        assert_eq!(channels(vec!["1", "2"]), None);
+        assert_eq!(channels(vec!["x"]), None);
+    }
+
+    /// Read the MPF segment of a test image, returning the gain map.
+    fn find_gain_map(buf: &[u8], options: &ReadOptions) -> Option<GainMap> {
+        let mpf = read_mpf(buf, 6, &buf[6..], options, &mut 0).unwrap();
+        mpf.unwrap().1
+    }
+
+    #[test]
+    fn test_find_gain_map() {
+        let buf = ultra_hdr_image();
+        let gain_map = find_gain_map(&buf, &ReadOptions::new().xmp(true)).unwrap();
+        assert_eq!(gain_map.mpf_index, 1);
+        assert_eq!((gain_map.width, gain_map.height), (32, 24));
+        assert_eq!(gain_map.version.as_deref(), Some("1.0"));
+        assert_eq!(gain_map.gain_map_min(), [0.0; 3]);
+        assert_eq!(gain_map.gain_map_max(), [2.5; 3]);
+        assert_eq!(gain_map.gamma(), [1.0; 3]);
+        assert_eq!(gain_map.offset_sdr(), [1.0 / 64.0; 3]);
+        assert_eq!(gain_map.hdr_capacity_min(), 0.0);
+        assert_eq!(gain_map.hdr_capacity_max(), 2.5);
+        assert!(!gain_map.base_rendition_is_hdr);
+        assert_eq!(&buf[gain_map.range][..2], b"\xff\xd8");
+    }
+
+    #[test]
+    fn test_no_gain_map() {
+        // The second image has no XMP, so it isn't a gain map.
+        let buf = crate::jpeg::mpf::tests::mpf_image(false);
+        assert_eq!(find_gain_map(&buf, &ReadOptions::new().xmp(true)), None);
+    }
+
+    #[test]
+    fn test_gain_map_options() {
+        // The gain map is only looked for if the XMP or everything in the
+        // file was asked for.
+        let buf = ultra_hdr_image();
+        assert_eq!(find_gain_map(&buf, &ReadOptions::new()), None);
+        let options = ReadOptions::new().stop_at(StopAt::End);
+        assert!(find_gain_map(&buf, &options).is_some());
+        let options = ReadOptions::new().xmp(true).stop_at(StopAt::Dimensions);
+        assert_eq!(find_gain_map(&buf, &options), None);
+    }
+
+    #[test]
+    fn test_gain_map_type() {
+        // Only an image without a type can be a gain map.
+        let mut buf = ultra_hdr_image();
+        let options = ReadOptions::new().xmp(true);
+        let mp_entries_end = 6 + u16::from_be_bytes([buf[4], buf[5]]) as usize - 2;
+        buf[mp_entries_end - 15] = 0x01;
+        assert_eq!(find_gain_map(&buf, &options), None);
+    }
+
+    /// An MPF image whose second image has gain map properties in its XMP.
+    pub(crate) fn ultra_hdr_image() -> Vec<u8> {
+        let xmp = br#"<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF><rdf:Description
+            xmlns:hdrgm="http://ns.adobe.com/hdr-gain-map/1.0/"
+            hdrgm:Version="1.0" hdrgm:GainMapMax="2.5" hdrgm:HDRCapacityMax="2.5"/>
+            </rdf:RDF></x:xmpmeta>"#;
+        let mut app1 = vec![0xff, 0xe1];
+        app1.extend_from_slice(&((2 + XMP_IDENTIFIER.len() + xmp.len()) as u16).to_be_bytes());
+        app1.extend_from_slice(XMP_IDENTIFIER);
+        app1.extend_from_slice(xmp);
+
+        // Insert the XMP segment just after the gain map's SOI marker, and
+        // fix up its size in the MP entries at the end of the MPF segment.
+        let mut buf = crate::jpeg::mpf::tests::mpf_image(false);
+        let gain_map_start = buf.len() - 17;
+        buf.splice(gain_map_start + 2..gain_map_start + 2, app1.iter().copied());
+        let size = 17 + app1.len() as u32;
+        let mp_entries_end = 6 + u16::from_be_bytes([buf[4], buf[5]]) as usize - 2;
+        buf[mp_entries_end - 12..mp_entries_end - 8].copy_from_slice(&size.to_be_bytes());
+        buf
+    }
+}
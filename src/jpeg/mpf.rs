@@ -0,0 +1,440 @@
+//! Decoder for the Multi-Picture Format (MPF) APP2 segment.
+//!
+//! MPF lets a JPEG file carry further images after its EOI marker: large
+//! previews, stereo pairs, gain maps and so on. The APP2 segment holds a
+//! TIFF-structured "MP Index IFD" listing every image in the file, including
+//! the primary one.
+
+use super::{embedded, gainmap, GainMap, JpegContext};
+use crate::limits::LimitExceeded;
+use crate::tiff::{Ifd, Tiff, UNDEFINED};
+use crate::{LimitKind, Limits, ReadOptions, StopAt};
+
+/// The contents of an MPF APP2 segment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Mpf {
+    /// The position of the MP header (the TIFF header after the "MPF\0"
+    /// identifier) in the file. Image offsets are relative to this.
+    pub header_offset: usize,
+    /// The images listed in the MP Index IFD, starting with the primary
+    /// image.
+    pub entries: Vec<MpEntry>,
+}
+
+/// An image listed in the MP Index IFD.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct MpEntry {
+    pub image_type: MpImageType,
+    /// True if this image has dependent child images.
+    pub dependent_parent: bool,
+    /// True if this image is a dependent child of another image.
+    pub dependent_child: bool,
+    /// True if this is the representative image of the file.
+    pub representative: bool,
+    /// The offset of the image's data, relative to the MP header. This is 0
+    /// for the primary image, which starts at the beginning of the file.
+    pub offset: u32,
+    /// The size of the image's data in bytes.
+    pub size: u32,
+    /// Width of the image, read from its SOF segment. This is 0 if the
+    /// image lies outside the file or can't be read, or if the file was read
+    /// with [`StopAt::Dimensions`].
+    pub width: u32,
+    /// Height of the image, read from its SOF segment. This is 0 if the
+    /// image lies outside the file or can't be read, or if the file was read
+    /// with [`StopAt::Dimensions`].
+    pub height: u32,
+}
+
+/// The type of an image listed in the MP Index IFD.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MpImageType {
+    /// No type given. Gain maps are usually recorded like this.
+    Undefined,
+    BaselinePrimary,
+    LargeThumbnailVga,
+    LargeThumbnailFullHd,
+    Panorama,
+    Disparity,
+    MultiAngle,
+    Unknown(u32),
+}
+
+impl MpEntry {
+    /// The range of the image's data in the file, given where the MP header
+    /// is. Returns `None` if the range overflows.
+    pub fn range(&self, header_offset: usize) -> Option<std::ops::Range<usize>> {
+        let start = if self.offset == 0 {
+            0
+        } else {
+            header_offset.checked_add(self.offset as usize)?
+        };
+        Some(start..start.checked_add(self.size as usize)?)
+    }
+}
+
+impl From<u32> for MpImageType {
+    fn from(code: u32) -> Self {
+        match code {
+            0x000000 => MpImageType::Undefined,
+            0x030000 => MpImageType::BaselinePrimary,
+            0x010001 => MpImageType::LargeThumbnailVga,
+            0x010002 => MpImageType::LargeThumbnailFullHd,
+            0x020001 => MpImageType::Panorama,
+            0x020002 => MpImageType::Disparity,
+            0x020003 => MpImageType::MultiAngle,
+            n => MpImageType::Unknown(n),
+        }
+    }
+}
+
+/// MPF tag for the list of MP entries.
+const MP_ENTRY: u16 = 0xb002;
+
+/// Decode an MPF APP2 segment, returning `None` if it is not one, or an
+/// error if it goes over one of the limits. Along with the segment comes the
+/// gain map, if one of the images listed is one.
+///
+/// `buf` is the whole file, and `data_offset` is the position of the
+/// segment's data within it. The segments of the images listed are counted
+/// in `segment_count` along with the file's own.
+pub(crate) fn read_mpf(
+    buf: &[u8],
+    data_offset: usize,
+    data: &[u8],
+    options: &ReadOptions,
+    segment_count: &mut u64,
+) -> Result<Option<(Mpf, Option<GainMap>)>, LimitExceeded> {
+    let Some(tiff) = data.strip_prefix(b"MPF\0").and_then(Tiff::new) else {
+        return Ok(None);
+    };
+    let Some(index) = tiff.first_ifd() else {
+        return Ok(None);
+    };
+    // The MP Index IFD is the only one we follow.
+    options
+        .limits
+        .check(LimitKind::IfdDepth, index.depth() as u64)?;
+    read_entries(buf, data_offset + 4, tiff, index, options, segment_count)
+}
+
+/// Read the MP entries in the MP Index IFD, and the images they point to.
+fn read_entries(
+    buf: &[u8],
+    header_offset: usize,
+    tiff: Tiff,
+    index: Ifd,
+    options: &ReadOptions,
+    segment_count: &mut u64,
+) -> Result<Option<(Mpf, Option<GainMap>)>, LimitExceeded> {
+    let Some(bytes) = index
+        .find(MP_ENTRY)
+        .filter(|e| e.kind == UNDEFINED)
+        .and_then(|entry| entry.read_bytes(&tiff, 1))
+    else {
+        return Ok(None);
+    };
+    let limits = &options.limits;
+    limits.check(LimitKind::MpEntries, (bytes.len() / 16) as u64)?;
+
+    // Only the first image that could be a gain map is looked at for one,
+    // and only if the XMP, or everything in the file, was asked for.
+    let mut find_gain_map = options.xmp || options.stop_at == StopAt::End;
+    let mut gain_map = None;
+    let mut entries = Vec::new();
+    for (index, raw) in bytes.chunks_exact(16).enumerate() {
+        let u32_at = |i: usize| {
+            let bytes = [raw[i], raw[i + 1], raw[i + 2], raw[i + 3]];
+            if tiff.big_endian {
+                u32::from_be_bytes(bytes)
+            } else {
+                u32::from_le_bytes(bytes)
+            }
+        };
+        let attributes = u32_at(0);
+        let mut entry = MpEntry {
+            image_type: MpImageType::from(attributes & 0x00ff_ffff),
+            dependent_parent: attributes & 0x8000_0000 != 0,
+            dependent_child: attributes & 0x4000_0000 != 0,
+            representative: attributes & 0x2000_0000 != 0,
+            size: u32_at(4),
+            offset: u32_at(8),
+            width: 0,
+            height: 0,
+        };
+        // Reading each image means walking its segments, which isn't worth
+        // doing when only the file's own dimensions were asked for.
+        let range = entry.range(header_offset).filter(|r| r.end <= buf.len());
+        if let (Some(range), false) = (range, options.stop_at == StopAt::Dimensions) {
+            let want_xmp = find_gain_map && index > 0 && entry.image_type == MpImageType::Undefined;
+            let image = read_image(&buf[range.clone()], want_xmp, limits, segment_count)?;
+            if let Some((width, height)) = image.dimensions {
+                entry.width = width;
+                entry.height = height;
+            }
+            if want_xmp {
+                find_gain_map = false;
+                gain_map = image
+                    .xmp
+                    .and_then(|xmp| gainmap::read_gain_map(xmp, index, &entry, range));
+            }
+        }
+        entries.push(entry);
+    }
+
+    let mpf = Mpf {
+        header_offset,
+        entries,
+    };
+    Ok(Some((mpf, gain_map)))
+}
+
+/// What is read from an image listed in the MP Index IFD.
+struct Image<'a> {
+    dimensions: Option<(u32, u32)>,
+    xmp: Option<&'a [u8]>,
+}
+
+/// Read the dimensions of an image listed in the MP Index IFD, and its XMP
+/// packet if `want_xmp` is set, from its segments up to its first scan. Each
+/// segment counts towards the [`LimitKind::Segments`] limit.
+fn read_image<'a>(
+    image: &'a [u8],
+    want_xmp: bool,
+    limits: &Limits,
+    segment_count: &mut u64,
+) -> Result<Image<'a>, LimitExceeded> {
+    let mut found = Image {
+        dimensions: None,
+        xmp: None,
+    };
+    if !image.starts_with(b"\xff\xd8") {
+        return Ok(found);
+    }
+    let mut context = JpegContext::new(image, 2);
+    while let Ok(Some(segment)) = context.read_segment() {
+        *segment_count += 1;
+        limits.check(LimitKind::Segments, *segment_count)?;
+        if segment.marker == 0xffd9 || segment.marker == 0xffda {
+            break;
+        }
+        if segment.is_sof() && found.dimensions.is_none() {
+            let dimensions = segment.read_sof().ok();
+            found.dimensions = dimensions.map(|(w, h)| (w.into(), h.into()));
+        } else if segment.is_app(1) && want_xmp && found.xmp.is_none() {
+            found.xmp = embedded::read_xmp(segment.data);
+        }
+        if found.dimensions.is_some() && (found.xmp.is_some() || !want_xmp) {
+            break;
+        }
+    }
+    Ok(found)
+}
+
+/// Update the MP Index IFD of an MPF segment, starting from its MP header,
+/// after the primary image has changed size by `size_change` bytes. Of
+/// those, `offset_change` were after the MP header, and move the other images
+/// that much further from it.
+pub(crate) fn adjust_mpf_entries(data: &mut [u8], size_change: i64, offset_change: i64) {
+    let Some(tiff) = Tiff::new(data) else {
+        return;
+    };
+    let Some(range) = tiff
+        .first_ifd()
+        .and_then(|index| index.find(MP_ENTRY))
+        .filter(|e| e.kind == UNDEFINED)
+        .and_then(|entry| entry.value_range(&tiff, 1))
+    else {
+        return;
+    };
+    let adjust = |value: u32, change: i64| {
+        tiff.u32_bytes((i64::from(value) + change).clamp(0, u32::MAX.into()) as u32)
+    };
+    let mut updates = Vec::new();
+    let entries = range.clone().step_by(16);
+    for start in entries.take_while(|start| start + 16 <= range.end) {
+        let (Some(size), Some(offset)) = (tiff.u32_at(start + 4), tiff.u32_at(start + 8)) else {
+            break;
+        };
+        if offset == 0 {
+            updates.push((start + 4, adjust(size, size_change)));
+        } else {
+            updates.push((start + 8, adjust(offset, offset_change)));
+        }
+    }
+    for (position, bytes) in updates {
+        data[position..position + 4].copy_from_slice(&bytes);
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_mpf() {
+        let buf = mpf_image(false);
+        let data = &buf[6..];
+        let (mpf, gain_map) = read_mpf(&buf, 6, data, &ReadOptions::default(), &mut 0)
+            .unwrap()
+            .unwrap();
+        assert_eq!(gain_map, None);
+        assert_eq!(mpf.header_offset, 10);
+        assert_eq!(mpf.entries.len(), 2);
+
+        let primary = &mpf.entries[0];
+        assert_eq!(primary.image_type, MpImageType::BaselinePrimary);
+        assert!(primary.dependent_parent);
+        assert!(primary.representative);
+        assert_eq!(primary.offset, 0);
+        assert_eq!((primary.width, primary.height), (64, 48));
+
+        let secondary = &mpf.entries[1];
+        assert_eq!(secondary.image_type, MpImageType::Undefined);
+        assert!(!secondary.representative);
+        assert_eq!(secondary.size, 17);
+        assert_eq!((secondary.width, secondary.height), (32, 24));
+        let range = secondary.range(mpf.header_offset).unwrap();
+        assert_eq!(range, buf.len() - 17..buf.len());
+    }
+
+    #[test]
+    fn test_secondary_image_out_of_bounds() {
+        let mut buf = mpf_image(true);
+        buf.truncate(buf.len() - 1);
+        let (mpf, _) = read_mpf(&buf, 6, &buf[6..], &ReadOptions::default(), &mut 0)
+            .unwrap()
+            .unwrap();
+        assert_eq!(mpf.entries.len(), 2);
+        assert_eq!((mpf.entries[1].width, mpf.entries[1].height), (0, 0));
+    }
+
+    #[test]
+    fn test_not_mpf() {
+        let options = ReadOptions::default();
+        let read = |data| read_mpf(b"", 0, data, &options, &mut 0);
+        assert_eq!(read(b"MPF\0II*\0\x08\0\0\0"), Ok(None));
+        assert_eq!(read(b"ICC_PROFILE\0"), Ok(None));
+    }
+
+    #[test]
+    fn test_ifd_depth_limit() {
+        let buf = mpf_image(false);
+        let options = ReadOptions::new().limits(Limits::new().max_ifd_depth(Some(0)));
+        let err = read_mpf(&buf, 6, &buf[6..], &options, &mut 0).unwrap_err();
+        assert_eq!(
+            (err.kind, err.value, err.limit),
+            (LimitKind::IfdDepth, 1, 0)
+        );
+    }
+
+    #[test]
+    fn test_mp_entries_limit() {
+        let buf = mpf_image(false);
+        let options = ReadOptions::new().limits(Limits::new().max_mp_entries(Some(1)));
+        let err = read_mpf(&buf, 6, &buf[6..], &options, &mut 0).unwrap_err();
+        assert_eq!(
+            (err.kind, err.value, err.limit),
+            (LimitKind::MpEntries, 2, 1)
+        );
+    }
+
+    #[test]
+    fn test_segments_limit() {
+        // The primary image is read through its MPF segment to its SOF, and
+        // the secondary one starts with its SOF: 3 segments, on top of the 1
+        // already read.
+        let buf = mpf_image(false);
+        let mut segment_count = 1;
+        read_mpf(
+            &buf,
+            6,
+            &buf[6..],
+            &ReadOptions::default(),
+            &mut segment_count,
+        )
+        .unwrap();
+        assert_eq!(segment_count, 4);
+
+        let options = ReadOptions::new().limits(Limits::new().max_segments(Some(3)));
+        let err = read_mpf(&buf, 6, &buf[6..], &options, &mut 1).unwrap_err();
+        assert_eq!(
+            (err.kind, err.value, err.limit),
+            (LimitKind::Segments, 4, 3)
+        );
+    }
+
+    #[test]
+    fn test_dimensions_only() {
+        // The images aren't read at all.
+        let buf = mpf_image(false);
+        let options = ReadOptions::new()
+            .stop_at(StopAt::Dimensions)
+            .limits(Limits::new().max_segments(Some(0)));
+        let (mpf, _) = read_mpf(&buf, 6, &buf[6..], &options, &mut 0)
+            .unwrap()
+            .unwrap();
+        assert_eq!(mpf.entries.len(), 2);
+        assert_eq!((mpf.entries[1].width, mpf.entries[1].height), (0, 0));
+        assert_eq!(mpf.entries[1].size, 17);
+    }
+
+    /// A JPEG with an MPF segment listing itself and a second image appended
+    /// after its EOI marker.
+    pub(crate) fn mpf_image(little_endian: bool) -> Vec<u8> {
+        let secondary = [
+            0xff, 0xd8, 0xff, 0xc0, 0x00, 0x0b, 0x08, 0x00, 24, 0x00, 32, 0x01, 0x01, 0x11, 0x00,
+            0xff, 0xd9,
+        ];
+        let primary_end = [
+            0xff, 0xc0, 0x00, 0x0b, 0x08, 0x00, 48, 0x00, 64, 0x01, 0x01, 0x11, 0x00, 0xff, 0xd9,
+        ];
+
+        // The MP Index IFD has one entry pointing at 32 bytes of MP entries
+        // just after it, at offset 8 + 2 + 12 + 4 = 26.
+        let u16 = |n: u16| {
+            if little_endian {
+                n.to_le_bytes()
+            } else {
+                n.to_be_bytes()
+            }
+        };
+        let u32 = |n: u32| {
+            if little_endian {
+                n.to_le_bytes()
+            } else {
+                n.to_be_bytes()
+            }
+        };
+        let mut mpf = b"MPF\0".to_vec();
+        mpf.extend_from_slice(if little_endian { b"II*\0" } else { b"MM\0*" });
+        mpf.extend_from_slice(&u32(8));
+        mpf.extend_from_slice(&u16(1));
+        mpf.extend_from_slice(&u16(MP_ENTRY));
+        mpf.extend_from_slice(&u16(UNDEFINED));
+        mpf.extend_from_slice(&u32(32));
+        mpf.extend_from_slice(&u32(26));
+        mpf.extend_from_slice(&u32(0));
+
+        let segment_len = 2 + mpf.len() + 32;
+        let primary_len = 2 + 2 + segment_len + primary_end.len();
+        let secondary_offset = primary_len - 10;
+        mpf.extend_from_slice(&u32(0xa003_0000));
+        mpf.extend_from_slice(&u32(primary_len as u32));
+        mpf.extend_from_slice(&u32(0));
+        mpf.extend_from_slice(&[0; 4]);
+        mpf.extend_from_slice(&u32(0));
+        mpf.extend_from_slice(&u32(secondary.len() as u32));
+        mpf.extend_from_slice(&u32(secondary_offset as u32));
+        mpf.extend_from_slice(&[0; 4]);
+
+        let mut buf = vec![0xff, 0xd8, 0xff, 0xe2];
+        buf.extend_from_slice(&(segment_len as u16).to_be_bytes());
+        buf.extend_from_slice(&mpf);
+        buf.extend_from_slice(&primary_end);
+        buf.extend_from_slice(&secondary);
+        buf
+    }
+}
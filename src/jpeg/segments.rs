@@ -0,0 +1,390 @@
+//! Iterating over the segments in JPEG data.
+
+use std::fmt::{self, Display};
+use std::ops::Range;
+
+use super::{JpegContext, JpegDecodingError};
+
+/// A JPEG marker, identified by the byte following 0xff.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Marker {
+    /// Start Of Frame: SOF0 to SOF15, except 4, 8 and 12.
+    Sof(u8),
+    /// Define Huffman Table.
+    Dht,
+    /// Reserved for JPEG extensions.
+    Jpg,
+    /// Define Arithmetic Coding conditioning.
+    Dac,
+    /// Restart marker: RST0 to RST7.
+    Rst(u8),
+    /// Start Of Image.
+    Soi,
+    /// End Of Image.
+    Eoi,
+    /// Start Of Scan.
+    Sos,
+    /// Define Quantization Table.
+    Dqt,
+    /// Define Number of Lines.
+    Dnl,
+    /// Define Restart Interval.
+    Dri,
+    /// Define Hierarchical Progression.
+    Dhp,
+    /// Expand reference components.
+    Exp,
+    /// Application segment: APP0 to APP15.
+    App(u8),
+    /// Reserved for JPEG extensions: JPG0 to JPG13.
+    JpgN(u8),
+    /// Comment.
+    Com,
+    /// Temporary private use in arithmetic coding.
+    Tem,
+    /// Any other byte, which isn't a valid marker.
+    Reserved(u8),
+}
+
+impl Marker {
+    /// The marker for the byte following 0xff.
+    pub fn from_code(code: u8) -> Self {
+        match code {
+            0xc4 => Marker::Dht,
+            0xc8 => Marker::Jpg,
+            0xcc => Marker::Dac,
+            0xc0..=0xcf => Marker::Sof(code - 0xc0),
+            0xd0..=0xd7 => Marker::Rst(code - 0xd0),
+            0xd8 => Marker::Soi,
+            0xd9 => Marker::Eoi,
+            0xda => Marker::Sos,
+            0xdb => Marker::Dqt,
+            0xdc => Marker::Dnl,
+            0xdd => Marker::Dri,
+            0xde => Marker::Dhp,
+            0xdf => Marker::Exp,
+            0xe0..=0xef => Marker::App(code - 0xe0),
+            0xf0..=0xfd => Marker::JpgN(code - 0xf0),
+            0xfe => Marker::Com,
+            0x01 => Marker::Tem,
+            _ => Marker::Reserved(code),
+        }
+    }
+
+    /// The byte following 0xff for this marker.
+    pub fn code(&self) -> u8 {
+        match *self {
+            Marker::Sof(n) => 0xc0 + n,
+            Marker::Dht => 0xc4,
+            Marker::Jpg => 0xc8,
+            Marker::Dac => 0xcc,
+            Marker::Rst(n) => 0xd0 + n,
+            Marker::Soi => 0xd8,
+            Marker::Eoi => 0xd9,
+            Marker::Sos => 0xda,
+            Marker::Dqt => 0xdb,
+            Marker::Dnl => 0xdc,
+            Marker::Dri => 0xdd,
+            Marker::Dhp => 0xde,
+            Marker::Exp => 0xdf,
+            Marker::App(n) => 0xe0 + n,
+            Marker::JpgN(n) => 0xf0 + n,
+            Marker::Com => 0xfe,
+            Marker::Tem => 0x01,
+            Marker::Reserved(code) => code,
+        }
+    }
+
+    /// Returns true if this marker stands alone, without a length or any
+    /// data.
+    pub fn is_standalone(&self) -> bool {
+        matches!(
+            self,
+            Marker::Soi | Marker::Eoi | Marker::Rst(_) | Marker::Tem
+        )
+    }
+}
+
+impl Display for Marker {
+    /// The marker's name from the JPEG specification, such as `APP1` or
+    /// `SOF0`. Invalid markers are shown as their two bytes in hex.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Marker::Sof(n) => write!(f, "SOF{}", n),
+            Marker::Dht => write!(f, "DHT"),
+            Marker::Jpg => write!(f, "JPG"),
+            Marker::Dac => write!(f, "DAC"),
+            Marker::Rst(n) => write!(f, "RST{}", n),
+            Marker::Soi => write!(f, "SOI"),
+            Marker::Eoi => write!(f, "EOI"),
+            Marker::Sos => write!(f, "SOS"),
+            Marker::Dqt => write!(f, "DQT"),
+            Marker::Dnl => write!(f, "DNL"),
+            Marker::Dri => write!(f, "DRI"),
+            Marker::Dhp => write!(f, "DHP"),
+            Marker::Exp => write!(f, "EXP"),
+            Marker::App(n) => write!(f, "APP{}", n),
+            Marker::JpgN(n) => write!(f, "JPG{}", n),
+            Marker::Com => write!(f, "COM"),
+            Marker::Tem => write!(f, "TEM"),
+            Marker::Reserved(code) => write!(f, "0xff{:02x}", code),
+        }
+    }
+}
+
+/// A segment in JPEG data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Segment<'a> {
+    pub marker: Marker,
+    /// The position of the segment's marker (its 0xff byte) in the JPEG data.
+    pub offset: usize,
+    /// The segment's payload, after the marker and length. This is empty for
+    /// standalone markers.
+    pub data: &'a [u8],
+}
+
+/// An iterator over the segments in JPEG data.
+///
+/// The iterator starts with the SOI marker, which must be at the start of the
+/// data, and by default stops after the SOS (Start Of Scan) segment, since
+/// that is the end of the metadata. Use [`Segments::through_scans`] to carry
+/// on past the entropy-coded data of each scan to the EOI marker; the restart
+/// markers in the scan data are returned as segments too. Garbage between
+/// segments is skipped over, as [`super::read_jpeg_data`] does, and
+/// [`Segments::skipped`] says where it was.
+///
+/// If a segment can't be read, the iterator returns the error and then stops.
+///
+/// # Example
+///
+/// ```
+/// use pb_imgsize::jpeg::{Marker, Segments};
+///
+/// let data = include_bytes!("../buttercups.jpg");
+/// let comments: Vec<_> = Segments::new(data)
+///     .filter_map(Result::ok)
+///     .filter(|segment| segment.marker == Marker::Com)
+///     .map(|segment| segment.data)
+///     .collect();
+/// assert_eq!(comments, vec![b"Buttercups"]);
+/// ```
+pub struct Segments<'a> {
+    context: JpegContext<'a>,
+    through_scans: bool,
+    state: State,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Start,
+    Segments,
+    Scan,
+    Done,
+}
+
+impl<'a> Segments<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Segments {
+            context: JpegContext::new(buf, 0),
+            through_scans: false,
+            state: State::Start,
+        }
+    }
+
+    /// Whether to carry on through the scan data after each SOS segment,
+    /// rather than stopping at the first one.
+    pub fn through_scans(mut self, through_scans: bool) -> Self {
+        self.through_scans = through_scans;
+        self
+    }
+
+    /// The position in the data where the next segment would start.
+    pub fn position(&self) -> usize {
+        self.context.position
+    }
+
+    /// The garbage bytes that were skipped over to find the segment last
+    /// returned, if there were any. Entropy-coded data is not garbage.
+    pub fn skipped(&self) -> Option<Range<usize>> {
+        self.context.skipped.clone()
+    }
+
+    fn read_next(&mut self) -> Result<Option<Segment<'a>>, JpegDecodingError> {
+        let buf = self.context.buf;
+        self.context.skipped = None;
+        loop {
+            match self.state {
+                State::Start => {
+                    if !buf.starts_with(b"\xff\xd8") {
+                        return Err(JpegDecodingError::NoSoiMarker);
+                    }
+                    self.context.position = 2;
+                    self.state = State::Segments;
+                    return Ok(Some(standalone(Marker::Soi, 0)));
+                }
+                State::Segments => {
+                    let Some(segment) = self.context.read_segment()? else {
+                        // read_segment stops at EOI without reading it.
+                        self.state = State::Done;
+                        let position = self.context.position;
+                        if buf.get(position..position + 2) == Some(b"\xff\xd9") {
+                            self.context.position += 2;
+                            return Ok(Some(standalone(Marker::Eoi, position)));
+                        }
+                        return Ok(None);
+                    };
+                    let segment = segment.to_segment();
+                    if segment.marker == Marker::Sos {
+                        self.state = if self.through_scans {
+                            State::Scan
+                        } else {
+                            State::Done
+                        };
+                    }
+                    return Ok(Some(segment));
+                }
+                State::Scan => {
+                    self.context.skip_scan_data(true);
+                    let position = self.context.position;
+                    match buf.get(position + 1) {
+                        Some(&code @ 0xd0..=0xd7) => {
+                            self.context.position += 2;
+                            return Ok(Some(standalone(Marker::from_code(code), position)));
+                        }
+                        _ => self.state = State::Segments,
+                    }
+                }
+                State::Done => return Ok(None),
+            }
+        }
+    }
+}
+
+fn standalone(marker: Marker, offset: usize) -> Segment<'static> {
+    Segment {
+        marker,
+        offset,
+        data: &[],
+    }
+}
+
+impl<'a> Iterator for Segments<'a> {
+    type Item = Result<Segment<'a>, JpegDecodingError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let result = self.read_next().transpose();
+        if let Some(Err(_)) = result {
+            self.state = State::Done;
+        }
+        result
+    }
+}
+
+impl<'a> std::iter::FusedIterator for Segments<'a> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+
+    #[test]
+    fn test_marker_codes() {
+        for code in 0..=0xff {
+            assert_eq!(Marker::from_code(code).code(), code);
+        }
+        assert_eq!(Marker::from_code(0xc2), Marker::Sof(2));
+        assert_eq!(Marker::from_code(0xc4), Marker::Dht);
+        assert_eq!(Marker::from_code(0xd3), Marker::Rst(3));
+        assert_eq!(Marker::from_code(0xe1), Marker::App(1));
+        assert_eq!(Marker::from_code(0x02), Marker::Reserved(0x02));
+        assert!(Marker::Rst(0).is_standalone());
+        assert!(!Marker::Sos.is_standalone());
+    }
+
+    #[test]
+    fn test_segments() {
+        let buf = sample_image();
+        let segments = Segments::new(&buf).collect::<Result<Vec<_>, _>>().unwrap();
+        let markers: Vec<_> = segments.iter().map(|s| s.marker).collect();
+        assert_eq!(
+            markers,
+            vec![
+                Marker::Soi,
+                Marker::App(0),
+                Marker::Com,
+                Marker::App(1),
+                Marker::Dqt,
+                Marker::Dqt,
+                Marker::Sof(0),
+                Marker::Dht,
+                Marker::Dht,
+                Marker::Dht,
+                Marker::Dht,
+                Marker::Sos,
+            ]
+        );
+        assert_eq!(segments[2].offset, 0x14);
+        assert_eq!(segments[2].data, b"Buttercups");
+    }
+
+    #[test]
+    fn test_through_scans() {
+        let buf = sample_image();
+        let segments = Segments::new(&buf)
+            .through_scans(true)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let last = segments.last().unwrap();
+        assert_eq!(last.marker, Marker::Eoi);
+        assert_eq!(last.offset, buf.len() - 2);
+    }
+
+    #[test]
+    fn test_restart_markers() {
+        #[rustfmt::skip]
+        let buf = [
+            0xff, 0xd8, // SOI
+            0xff, 0xda, 0x00, 0x08, 0x01, 0x01, 0x00, 0x00, 0x3f, 0x00, // SOS
+            0x12, 0xff, 0x00, 0x34, 0xff, 0xd0, 0x56, 0xff, 0xff, 0xd1, 0x78, // Scan data
+            0xff, 0xdc, 0x00, 0x04, 0x00, 0x20, // DNL
+            0xff, 0xd9, // EOI
+        ];
+        let segments = Segments::new(&buf)
+            .through_scans(true)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let markers: Vec<_> = segments.iter().map(|s| (s.marker, s.offset)).collect();
+        assert_eq!(
+            markers,
+            vec![
+                (Marker::Soi, 0),
+                (Marker::Sos, 2),
+                (Marker::Rst(0), 16),
+                (Marker::Rst(1), 20),
+                (Marker::Dnl, 23),
+                (Marker::Eoi, 29),
+            ]
+        );
+        assert_eq!(segments[4].data, [0x00, 0x20]);
+    }
+
+    #[test]
+    fn test_marker_names() {
+        assert_eq!(Marker::from_code(0xe1).to_string(), "APP1");
+        assert_eq!(Marker::from_code(0xc2).to_string(), "SOF2");
+        assert_eq!(Marker::from_code(0xfe).to_string(), "COM");
+        assert_eq!(Marker::from_code(0x02).to_string(), "0xff02");
+    }
+
+    #[test]
+    fn test_no_soi() {
+        let mut segments = Segments::new(b"\x89PNG");
+        assert_matches!(segments.next(), Some(Err(JpegDecodingError::NoSoiMarker)));
+        assert_matches!(segments.next(), None);
+    }
+
+    fn sample_image() -> Vec<u8> {
+        std::fs::read("src/buttercups.jpg").unwrap()
+    }
+}
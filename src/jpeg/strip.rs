@@ -0,0 +1,208 @@
+//! Removing metadata segments from a JPEG image.
+
+use super::embedded;
+use super::segments::{Marker, Segment, Segments};
+use super::write::Rewriter;
+use super::JpegDecodingError;
+use crate::exif::remove_private_tags;
+use crate::xmp::remove_private_properties;
+use crate::{ExifPolicy, StripPolicy};
+
+/// The identifier of an APP1 segment holding part of an extended XMP packet.
+const EXTENDED_XMP_IDENTIFIER: &[u8] = b"http://ns.adobe.com/xmp/extension/\0";
+
+/// Copy the JPEG data, leaving out the metadata segments that the policy
+/// doesn't keep. Everything from the SOS segment on, including any images
+/// appended through MPF, is copied as it is.
+pub(crate) fn strip_jpeg(buf: &[u8], policy: &StripPolicy) -> Result<Vec<u8>, JpegDecodingError> {
+    let mut rewriter = Rewriter::new(buf);
+    for segment in Segments::new(buf) {
+        let segment = segment?;
+        if segment.marker == Marker::App(1) && embedded::read_exif(segment.data).is_some() {
+            match policy.exif {
+                ExifPolicy::Keep => {}
+                ExifPolicy::RemovePrivate => {
+                    let mut data = segment.data.to_vec();
+                    remove_private_tags(&mut data[6..]);
+                    let start = segment.offset + 4;
+                    rewriter.replace(start..start + data.len(), &data);
+                }
+                ExifPolicy::Drop => rewriter.remove(&segment),
+            }
+        } else if !keep(&segment, policy) {
+            rewriter.remove(&segment);
+        } else if segment.marker == Marker::App(1)
+            && embedded::read_xmp(segment.data).is_some()
+            && policy.exif == ExifPolicy::RemovePrivate
+        {
+            let mut data = segment.data.to_vec();
+            remove_private_properties(&mut data[embedded::XMP_IDENTIFIER.len()..]);
+            let start = segment.offset + 4;
+            rewriter.replace(start..start + data.len(), &data);
+        }
+    }
+    Ok(rewriter.finish())
+}
+
+/// Whether to keep a segment other than the EXIF segment.
+fn keep(segment: &Segment, policy: &StripPolicy) -> bool {
+    let data = segment.data;
+    let remove_private = policy.exif == ExifPolicy::RemovePrivate;
+    match segment.marker {
+        Marker::App(1) if embedded::read_xmp(data).is_some() => policy.xmp,
+        // The rest of an XMP packet too big for one segment is split up
+        // anywhere, so it can't be cleaned up a segment at a time.
+        Marker::App(1) if data.starts_with(EXTENDED_XMP_IDENTIFIER) => {
+            policy.xmp && !remove_private
+        }
+        Marker::App(2) if embedded::read_icc_chunk(data).is_some() => policy.icc_profile,
+        // The JFIF, JFXX and Adobe segments, and the MPF index, are needed to
+        // show the image.
+        Marker::App(0) | Marker::App(14) => true,
+        Marker::App(2) if data.starts_with(b"MPF\0") => true,
+        // Photoshop's APP13 segment holds IPTC data, and can hold copies of
+        // the EXIF data and XMP packet too.
+        Marker::App(13) => policy.other && !remove_private,
+        Marker::App(_) => policy.other,
+        Marker::Com => policy.comments,
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jpeg::{mpf, read_jpeg_data, read_jpeg_data_ref, write_jpeg_comments};
+    use crate::{exif, xmp, ReadOptions};
+
+    /// The sample image, with an XMP segment and an ICC profile added after
+    /// its EXIF segment, which is replaced by one with a GPS IFD.
+    fn sample_image() -> Vec<u8> {
+        let mut data = std::fs::read("src/buttercups.jpg").unwrap();
+        let segment = |marker: u8, payload: &[&[u8]]| {
+            let payload = payload.concat();
+            let mut segment = vec![0xff, marker];
+            segment.extend_from_slice(&(payload.len() as u16 + 2).to_be_bytes());
+            segment.extend(payload);
+            segment
+        };
+        let exif = exif::tests::exif(true);
+        let segments = [
+            segment(0xe1, &[b"Exif\0\0", &exif]),
+            segment(0xe1, &[embedded::XMP_IDENTIFIER, b"<x:xmpmeta/>"]),
+            segment(0xe2, &[b"ICC_PROFILE\0\x01\x01", b"profile"]),
+            segment(0xed, &[b"Photoshop 3.0\0"]),
+        ]
+        .concat();
+        data.splice(0x22..0x3a, segments);
+        data
+    }
+
+    fn markers(data: &[u8]) -> Vec<Marker> {
+        Segments::new(data)
+            .map(|segment| segment.unwrap().marker)
+            .collect()
+    }
+
+    #[test]
+    fn test_strip_all() {
+        let data = sample_image();
+        let stripped = strip_jpeg(&data, &StripPolicy::new()).unwrap();
+        let all = ReadOptions::new().exif(true).xmp(true).icc_profile(true);
+        let metadata = read_jpeg_data_ref(&stripped, &all, &mut ()).unwrap();
+        assert_eq!((metadata.width, metadata.height), (512, 341));
+        assert!(metadata.comments.is_empty());
+        assert_eq!(metadata.exif, None);
+        assert_eq!(metadata.xmp, None);
+        assert_eq!(metadata.icc_profile.as_deref(), Some(&b"profile"[..]));
+        assert!(metadata.jpeg.unwrap().jfif.is_some());
+        assert_eq!(
+            &markers(&stripped)[..3],
+            [Marker::Soi, Marker::App(0), Marker::App(2)]
+        );
+        // The image data is the same.
+        let sos = |data: &[u8]| data.windows(2).position(|w| w == b"\xff\xda").unwrap();
+        assert_eq!(&stripped[sos(&stripped)..], &data[sos(&data)..]);
+
+        let policy = StripPolicy::new().icc_profile(false).comments(true);
+        let stripped = strip_jpeg(&data, &policy).unwrap();
+        let metadata = read_jpeg_data(&stripped).unwrap();
+        assert_eq!(metadata.comments, vec![b"Buttercups".to_vec()]);
+        assert!(!markers(&stripped).contains(&Marker::App(2)));
+    }
+
+    #[test]
+    fn test_strip_private_tags() {
+        let data = sample_image();
+        let stripped = strip_jpeg(&data, &StripPolicy::private_tags_only()).unwrap();
+        // Only the APP13 segment is left out.
+        assert_eq!(stripped.len(), data.len() - 18);
+        let mut expected = markers(&data);
+        expected.retain(|&marker| marker != Marker::App(13));
+        assert_eq!(markers(&stripped), expected);
+        let mut expected = exif::tests::exif(true);
+        exif::remove_private_tags(&mut expected);
+        let options = ReadOptions::new().exif(true);
+        let metadata = read_jpeg_data_ref(&stripped, &options, &mut ()).unwrap();
+        assert_eq!(metadata.exif, Some(&expected[..]));
+    }
+
+    #[test]
+    fn test_strip_private_xmp() {
+        // The sample image with an XMP packet holding GPS properties, and an
+        // extended XMP segment, in place of the empty XMP packet.
+        let mut data = sample_image();
+        let xmp = Segments::new(&data)
+            .map(Result::unwrap)
+            .find(|s| embedded::read_xmp(s.data).is_some())
+            .unwrap();
+        let segment = |payload: &[&[u8]]| {
+            let payload = payload.concat();
+            let mut segment = vec![0xff, 0xe1];
+            segment.extend_from_slice(&(payload.len() as u16 + 2).to_be_bytes());
+            segment.extend(payload);
+            segment
+        };
+        let segments = [
+            segment(&[embedded::XMP_IDENTIFIER, xmp::tests::XMP]),
+            segment(&[EXTENDED_XMP_IDENTIFIER, b"<exif:GPSAltitude>"]),
+        ];
+        data.splice(
+            xmp.offset..xmp.offset + 4 + xmp.data.len(),
+            segments.concat(),
+        );
+
+        let stripped = strip_jpeg(&data, &StripPolicy::private_tags_only()).unwrap();
+        let options = ReadOptions::new().xmp(true);
+        let metadata = read_jpeg_data_ref(&stripped, &options, &mut ()).unwrap();
+        let mut expected = xmp::tests::XMP.to_vec();
+        xmp::remove_private_properties(&mut expected);
+        assert_eq!(metadata.xmp.as_deref(), Some(&expected[..]));
+        // The extended XMP is gone, but the comment is still there.
+        let markers = markers(&stripped);
+        assert_eq!(markers.iter().filter(|&&m| m == Marker::App(1)).count(), 2);
+        assert!(markers.contains(&Marker::Com));
+
+        // Keeping the EXIF data as it is keeps the rest as it is too.
+        let policy = StripPolicy::private_tags_only().exif(ExifPolicy::Keep);
+        assert_eq!(strip_jpeg(&data, &policy).unwrap(), data);
+    }
+
+    #[test]
+    fn test_mpf() {
+        // A COM segment after the MPF segment is removed, and the secondary
+        // image is still found.
+        let buf = mpf::tests::mpf_image(false);
+        let buf = write_jpeg_comments(&buf, &["Hello"]).unwrap();
+        let secondary = |data: &[u8]| {
+            let mpf = read_jpeg_data(data).unwrap().jpeg.unwrap().mpf.unwrap();
+            (mpf.entries[1].width, mpf.entries[1].height)
+        };
+        assert_eq!(secondary(&buf), (32, 24));
+
+        let stripped = strip_jpeg(&buf, &StripPolicy::new()).unwrap();
+        assert_eq!(stripped.len(), buf.len() - 9);
+        assert!(!markers(&stripped).contains(&Marker::Com));
+        assert_eq!(secondary(&stripped), (32, 24));
+    }
+}
@@ -0,0 +1,282 @@
+//! Finding the thumbnails embedded in a JPEG image.
+
+use std::ops::Range;
+
+use super::app::{read_jfif, read_jfxx, JfxxFormat};
+use super::{read_dimensions, JpegContext, JpegDecodingError};
+use crate::limits::LimitExceeded;
+use crate::tiff::{Ifd, Tiff};
+use crate::{LimitKind, Limits};
+
+/// A thumbnail embedded in a JPEG image.
+///
+/// The thumbnail's data is borrowed from the image, not copied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Thumbnail<'a> {
+    pub source: ThumbnailSource,
+    pub format: ThumbnailFormat,
+    /// Width of the thumbnail. For JPEG thumbnails, this is read from the
+    /// thumbnail's own SOF segment, and is 0 if that can't be read.
+    pub width: u32,
+    /// Height of the thumbnail. For JPEG thumbnails, this is read from the
+    /// thumbnail's own SOF segment, and is 0 if that can't be read.
+    pub height: u32,
+    /// Where the thumbnail's data is in the image.
+    pub range: Range<usize>,
+    /// The thumbnail's data. For JPEG thumbnails, this is a complete JPEG
+    /// image, which can be passed to [`crate::read_bytes`]. For RGB
+    /// thumbnails, it is 3 bytes per pixel. For palette thumbnails, it is a
+    /// 768-byte RGB palette, followed by 1 byte per pixel.
+    pub data: &'a [u8],
+}
+
+/// Where in the image a thumbnail was found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThumbnailSource {
+    /// IFD1 of the EXIF APP1 segment.
+    Exif,
+    /// The JFIF APP0 segment.
+    Jfif,
+    /// A JFXX (JFIF extension) APP0 segment.
+    Jfxx,
+}
+
+/// How a thumbnail's data is stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThumbnailFormat {
+    Jpeg,
+    Palette,
+    Rgb,
+}
+
+/// EXIF tag giving the offset of the JPEG thumbnail in IFD1.
+const JPEG_INTERCHANGE_FORMAT: u16 = 0x0201;
+/// EXIF tag giving the length of the JPEG thumbnail in IFD1.
+const JPEG_INTERCHANGE_FORMAT_LENGTH: u16 = 0x0202;
+
+/// Find the thumbnails embedded in JPEG data.
+///
+/// This looks for a JPEG thumbnail in IFD1 of the EXIF segment, an RGB
+/// thumbnail in the JFIF segment, and JPEG, palette or RGB thumbnails in JFXX
+/// segments. Thumbnails that run past the end of their segment are skipped.
+pub fn read_thumbnails(buf: &[u8]) -> Result<Vec<Thumbnail<'_>>, JpegDecodingError> {
+    read_thumbnails_with(buf, &Limits::default())
+}
+
+/// Find the thumbnails embedded in JPEG data, failing if the EXIF thumbnail's
+/// IFD is deeper than [`Limits::max_ifd_depth`] allows.
+pub fn read_thumbnails_with<'a>(
+    buf: &'a [u8],
+    limits: &Limits,
+) -> Result<Vec<Thumbnail<'a>>, JpegDecodingError> {
+    let mut context = JpegContext::new(buf, 2);
+    let mut thumbnails = vec![];
+
+    while let Some(segment) = context.read_segment()? {
+        if segment.marker == 0xffd9 || segment.marker == 0xffda {
+            break;
+        }
+
+        let data = segment.data;
+        let found = if segment.is_app(0) {
+            jfif_thumbnail(data).or_else(|| jfxx_thumbnail(data))
+        } else if segment.is_app(1) {
+            exif_thumbnail(data, limits)?
+        } else {
+            None
+        };
+
+        if let Some((source, format, (width, height), range)) = found {
+            if let Some(thumbnail_data) = data.get(range.clone()) {
+                let offset = segment.data_offset();
+                thumbnails.push(Thumbnail {
+                    source,
+                    format,
+                    width,
+                    height,
+                    range: offset + range.start..offset + range.end,
+                    data: thumbnail_data,
+                });
+            }
+        }
+    }
+
+    Ok(thumbnails)
+}
+
+/// A thumbnail's source, format, dimensions and range within its segment.
+type Found = (ThumbnailSource, ThumbnailFormat, (u32, u32), Range<usize>);
+
+/// Find the RGB thumbnail after the header of a JFIF APP0 segment.
+fn jfif_thumbnail(data: &[u8]) -> Option<Found> {
+    let jfif = read_jfif(data)?;
+    let pixels = usize::from(jfif.thumbnail_width) * usize::from(jfif.thumbnail_height);
+    if pixels == 0 {
+        return None;
+    }
+    let dimensions = (jfif.thumbnail_width.into(), jfif.thumbnail_height.into());
+    Some((
+        ThumbnailSource::Jfif,
+        ThumbnailFormat::Rgb,
+        dimensions,
+        14..14 + 3 * pixels,
+    ))
+}
+
+/// Find the thumbnail in a JFXX APP0 segment.
+fn jfxx_thumbnail(data: &[u8]) -> Option<Found> {
+    let jfxx = read_jfxx(data)?;
+    let pixels = jfxx.thumbnail_width as usize * jfxx.thumbnail_height as usize;
+    let (format, range) = match jfxx.format {
+        JfxxFormat::Jpeg => (ThumbnailFormat::Jpeg, 6..data.len()),
+        JfxxFormat::Palette => (ThumbnailFormat::Palette, 8..8 + 768 + pixels),
+        JfxxFormat::Rgb => (ThumbnailFormat::Rgb, 8..8 + 3 * pixels),
+        JfxxFormat::Unknown(_) => return None,
+    };
+    let dimensions = (jfxx.thumbnail_width, jfxx.thumbnail_height);
+    Some((ThumbnailSource::Jfxx, format, dimensions, range))
+}
+
+/// Find the JPEG thumbnail in IFD1 of an EXIF APP1 segment.
+fn exif_thumbnail(data: &[u8], limits: &Limits) -> Result<Option<Found>, LimitExceeded> {
+    let ifd1 = data
+        .strip_prefix(b"Exif\0\0")
+        .and_then(Tiff::new)
+        .and_then(|tiff| tiff.first_ifd()?.next());
+    let Some(ifd1) = ifd1 else {
+        return Ok(None);
+    };
+    limits.check(LimitKind::IfdDepth, ifd1.depth() as u64)?;
+    Ok(jpeg_interchange(data, &ifd1))
+}
+
+/// Find the JPEG thumbnail that IFD1 points to.
+fn jpeg_interchange(data: &[u8], ifd1: &Ifd) -> Option<Found> {
+    let offset = ifd1.find_u32(JPEG_INTERCHANGE_FORMAT)? as usize;
+    let length = ifd1.find_u32(JPEG_INTERCHANGE_FORMAT_LENGTH)? as usize;
+    // Offsets in the TIFF data are relative to the TIFF header, which comes
+    // after the 6-byte "Exif\0\0" identifier.
+    let start = offset.checked_add(6)?;
+    let range = start..start.checked_add(length)?;
+    let dimensions = read_dimensions(data.get(range.clone())?).unwrap_or_default();
+    Some((
+        ThumbnailSource::Exif,
+        ThumbnailFormat::Jpeg,
+        dimensions,
+        range,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_thumbnails() {
+        let exif = exif_with_thumbnail(&tiny_jpeg(4, 3), 0);
+        let jfif = b"JFIF\0\x01\x02\x00\x00\x01\x00\x01\x01\x01\xff\x80\x00";
+        let jfxx = b"JFXX\0\x13\x02\x01\x00\x00\xff\xff\xff\xff";
+        let buf = image(&[(0xe0, jfif), (0xe1, &exif), (0xe0, jfxx)]);
+
+        let thumbnails = read_thumbnails(&buf).unwrap();
+        assert_eq!(thumbnails.len(), 3);
+
+        let t = &thumbnails[0];
+        assert_eq!(
+            (t.source, t.format),
+            (ThumbnailSource::Jfif, ThumbnailFormat::Rgb)
+        );
+        assert_eq!((t.width, t.height), (1, 1));
+        assert_eq!(t.data, b"\xff\x80\x00");
+        assert_eq!(&buf[t.range.clone()], t.data);
+
+        let t = &thumbnails[1];
+        assert_eq!(
+            (t.source, t.format),
+            (ThumbnailSource::Exif, ThumbnailFormat::Jpeg)
+        );
+        assert_eq!((t.width, t.height), (4, 3));
+        assert_eq!(&buf[t.range.clone()], t.data);
+        let metadata = crate::read_bytes(t.data).unwrap();
+        assert_eq!((metadata.width, metadata.height), (4, 3));
+
+        let t = &thumbnails[2];
+        assert_eq!(
+            (t.source, t.format),
+            (ThumbnailSource::Jfxx, ThumbnailFormat::Rgb)
+        );
+        assert_eq!((t.width, t.height), (2, 1));
+        assert_eq!(t.data, b"\x00\x00\xff\xff\xff\xff");
+    }
+
+    #[test]
+    fn test_truncated_thumbnails() {
+        // The EXIF thumbnail's length runs past the end of the segment, and
+        // the JFIF thumbnail is missing a byte.
+        let exif = exif_with_thumbnail(&tiny_jpeg(4, 3), 1);
+        let jfif = b"JFIF\0\x01\x02\x00\x00\x01\x00\x01\x01\x01\xff\x80";
+        let buf = image(&[(0xe0, jfif), (0xe1, &exif)]);
+        assert_eq!(read_thumbnails(&buf).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_ifd_depth_limit() {
+        // IFD1 is at depth 2, so a limit of 1 stops the walk there, both when
+        // looking for thumbnails and when reading the EXIF data.
+        let exif = exif_with_thumbnail(&tiny_jpeg(4, 3), 0);
+        let buf = image(&[(0xe1, &exif)]);
+        let limits = Limits::new().max_ifd_depth(Some(1));
+        let expected = JpegDecodingError::LimitExceeded {
+            kind: LimitKind::IfdDepth,
+            value: 2,
+            limit: 1,
+        };
+        assert_eq!(read_thumbnails_with(&buf, &limits), Err(expected));
+        let options = crate::ReadOptions::new().exif(true).limits(limits);
+        let err = crate::read_bytes_with(&options, &buf).unwrap_err();
+        assert_eq!(err.kind(), crate::ErrorKind::LimitExceeded);
+        assert!(crate::read_bytes(&buf).is_ok());
+    }
+
+    #[test]
+    fn test_no_thumbnails() {
+        let buf = std::fs::read("src/buttercups.jpg").unwrap();
+        assert_eq!(read_thumbnails(&buf).unwrap(), vec![]);
+    }
+
+    /// A JPEG containing the given APPn segments, followed by a SOF0 segment.
+    fn image(segments: &[(u8, &[u8])]) -> Vec<u8> {
+        let mut buf = vec![0xff, 0xd8];
+        for (marker, data) in segments {
+            buf.extend_from_slice(&[0xff, *marker]);
+            buf.extend_from_slice(&(data.len() as u16 + 2).to_be_bytes());
+            buf.extend_from_slice(data);
+        }
+        buf.extend_from_slice(&tiny_jpeg(100, 100)[2..]);
+        buf
+    }
+
+    /// A JPEG with just a SOF0 segment.
+    fn tiny_jpeg(width: u8, height: u8) -> Vec<u8> {
+        vec![
+            0xff, 0xd8, 0xff, 0xc0, 0x00, 0x0b, 0x08, 0x00, height, 0x00, width, 0x01, 0x01, 0x11,
+            0x00, 0xff, 0xd9,
+        ]
+    }
+
+    /// An EXIF segment with an empty IFD0, and an IFD1 pointing to the given
+    /// thumbnail. The thumbnail's length is overstated by `extra` bytes.
+    fn exif_with_thumbnail(thumbnail: &[u8], extra: u32) -> Vec<u8> {
+        let mut exif = b"Exif\0\0MM\0*\0\0\0\x08".to_vec();
+        // IFD0: no entries, next IFD at 14.
+        exif.extend_from_slice(b"\0\0\0\0\0\x0e");
+        // IFD1: two entries, then no next IFD. The thumbnail follows at 44.
+        exif.extend_from_slice(b"\0\x02");
+        exif.extend_from_slice(b"\x02\x01\0\x04\0\0\0\x01\0\0\0\x2c");
+        exif.extend_from_slice(b"\x02\x02\0\x04\0\0\0\x01");
+        exif.extend_from_slice(&(thumbnail.len() as u32 + extra).to_be_bytes());
+        exif.extend_from_slice(b"\0\0\0\0");
+        exif.extend_from_slice(thumbnail);
+        exif
+    }
+}
@@ -0,0 +1,165 @@
+//! Checking a whole JPEG file, rather than just its header: the scan
+//! headers, the restart markers within each scan, and the EOI marker.
+
+use super::segments::{Marker, Segment, Segments};
+use super::{read_sof_component_ids, JpegDecodingError};
+
+/// Check that the JPEG data is complete and well-formed: each SOS segment
+/// refers only to components of the frame, the restart markers in each scan
+/// count up from RST0, there is nothing but entropy-coded data between the
+/// segments, and the image ends with an EOI marker.
+pub(crate) fn validate_jpeg(buf: &[u8]) -> Result<(), JpegDecodingError> {
+    let mut component_ids = Vec::new();
+    let mut next_restart = 0;
+    let mut segments = Segments::new(buf).through_scans(true);
+    while let Some(segment) = segments.next() {
+        let segment = segment?;
+        if let Some(skipped) = segments.skipped() {
+            return Err(JpegDecodingError::ExtraneousBytes {
+                position: skipped.start,
+                count: skipped.len(),
+            });
+        }
+        match segment.marker {
+            Marker::Sof(_) => component_ids = read_sof_component_ids(segment.data),
+            Marker::Sos => {
+                check_scan_header(&segment, &component_ids)?;
+                next_restart = 0;
+            }
+            Marker::Rst(n) if n != next_restart => {
+                return Err(JpegDecodingError::RestartOutOfSequence {
+                    position: segment.offset,
+                    expected: next_restart,
+                    found: n,
+                });
+            }
+            Marker::Rst(n) => next_restart = (n + 1) % 8,
+            Marker::Eoi => return Ok(()),
+            _ => {}
+        }
+    }
+    Err(JpegDecodingError::MissingEoi {
+        position: buf.len(),
+    })
+}
+
+/// Check that an SOS segment has the right length for its number of
+/// components, and that each one is a component of the frame, used once.
+fn check_scan_header(segment: &Segment, component_ids: &[u8]) -> Result<(), JpegDecodingError> {
+    let position = segment.offset;
+    let invalid = JpegDecodingError::InvalidScanHeader { position };
+    let Some((&count, rest)) = segment.data.split_first() else {
+        return Err(invalid);
+    };
+    // Each component has an ID and table selectors, and then there are three
+    // bytes of spectral selection and successive approximation.
+    if !(1..=4).contains(&count) || rest.len() != 2 * usize::from(count) + 3 {
+        return Err(invalid);
+    }
+    let components = &rest[..2 * usize::from(count)];
+    for (i, component) in components.chunks_exact(2).enumerate() {
+        let id = component[0];
+        let repeated = components[..2 * i].chunks_exact(2).any(|c| c[0] == id);
+        if repeated || !component_ids.contains(&id) {
+            return Err(JpegDecodingError::InvalidScanComponent {
+                position,
+                component: id,
+            });
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 1x1 grayscale image whose scan has the given data.
+    fn image(sos: &[u8], scan: &[u8]) -> Vec<u8> {
+        let mut buf = vec![0xff, 0xd8];
+        buf.extend_from_slice(b"\xff\xc0\x00\x0b\x08\x00\x01\x00\x01\x01\x01\x11\x00");
+        buf.extend_from_slice(&[0xff, 0xda, 0x00, sos.len() as u8 + 2]);
+        buf.extend_from_slice(sos);
+        buf.extend_from_slice(scan);
+        buf
+    }
+
+    const SOS: &[u8] = b"\x01\x01\x00\x00\x3f\x00";
+
+    #[test]
+    fn test_validate_jpeg() {
+        let data = std::fs::read("src/buttercups.jpg").unwrap();
+        assert_eq!(validate_jpeg(&data), Ok(()));
+        assert_eq!(
+            validate_jpeg(&data[..data.len() - 2]),
+            Err(JpegDecodingError::MissingEoi {
+                position: data.len() - 2
+            })
+        );
+
+        let scan = b"\x12\xff\xd0\x34\xff\x00\xff\xd1\x56\xff\xd9";
+        assert_eq!(validate_jpeg(&image(SOS, scan)), Ok(()));
+        let scan = b"\x12\xff\xd0\x34\xff\xd2\x56\xff\xd9";
+        assert_eq!(
+            validate_jpeg(&image(SOS, scan)),
+            Err(JpegDecodingError::RestartOutOfSequence {
+                position: 29,
+                expected: 1,
+                found: 2
+            })
+        );
+    }
+
+    #[test]
+    fn test_garbage_between_segments() {
+        // Anything after a scan up to the next marker is entropy-coded
+        // data, but a byte after the COM segment that follows it is garbage.
+        let scan = b"\x12\xff\xd0\x34\xff\xfe\x00\x03x\xff\xd9";
+        let mut buf = image(SOS, scan);
+        assert_eq!(validate_jpeg(&buf), Ok(()));
+        buf.insert(buf.len() - 2, 0x00);
+        assert_eq!(
+            validate_jpeg(&buf),
+            Err(JpegDecodingError::ExtraneousBytes {
+                position: 34,
+                count: 1
+            })
+        );
+
+        // Garbage between the SOF and SOS segments.
+        let mut buf = image(SOS, b"\xff\xd9");
+        buf.splice(15..15, *b"ab");
+        assert_eq!(
+            validate_jpeg(&buf),
+            Err(JpegDecodingError::ExtraneousBytes {
+                position: 15,
+                count: 2
+            })
+        );
+    }
+
+    #[test]
+    fn test_scan_header() {
+        let eoi = b"\xff\xd9";
+        assert_eq!(
+            validate_jpeg(&image(b"\x01\x02\x00\x00\x3f\x00", eoi)),
+            Err(JpegDecodingError::InvalidScanComponent {
+                position: 15,
+                component: 2
+            })
+        );
+        assert_eq!(
+            validate_jpeg(&image(b"\x02\x01\x00\x01\x00\x00\x3f\x00", eoi)),
+            Err(JpegDecodingError::InvalidScanComponent {
+                position: 15,
+                component: 1
+            })
+        );
+        for sos in [&b""[..], b"\x00\x00\x3f\x00", b"\x01\x01\x00\x00\x3f"] {
+            assert_eq!(
+                validate_jpeg(&image(sos, eoi)),
+                Err(JpegDecodingError::InvalidScanHeader { position: 15 })
+            );
+        }
+    }
+}
@@ -0,0 +1,233 @@
+//! Writing a copy of a JPEG image with some of its segments changed.
+
+use std::ops::Range;
+
+use super::embedded;
+use super::mpf::adjust_mpf_entries;
+use super::segments::{Marker, Segment, Segments};
+use super::JpegDecodingError;
+use crate::exif;
+
+/// The most data a segment can hold: its length field counts itself too.
+const MAX_SEGMENT_DATA: usize = 0xffff - 2;
+
+/// Copies JPEG data with parts of it replaced, and keeps the MPF segment's
+/// offsets to the images after the primary one right.
+pub(super) struct Rewriter<'a> {
+    buf: &'a [u8],
+    out: Vec<u8>,
+    /// Where we've copied up to.
+    copied: usize,
+    /// Where each change was made, and how many bytes it added (or removed,
+    /// if negative).
+    changes: Vec<(usize, i64)>,
+}
+
+impl<'a> Rewriter<'a> {
+    pub(super) fn new(buf: &'a [u8]) -> Self {
+        Rewriter {
+            buf,
+            out: Vec::with_capacity(buf.len()),
+            copied: 0,
+            changes: Vec::new(),
+        }
+    }
+
+    /// Replace a range of the data with the given bytes. Ranges must be
+    /// replaced in order.
+    pub(super) fn replace(&mut self, range: Range<usize>, bytes: &[u8]) {
+        self.out
+            .extend_from_slice(&self.buf[self.copied..range.start]);
+        self.out.extend_from_slice(bytes);
+        self.copied = range.end;
+        let change = bytes.len() as i64 - range.len() as i64;
+        if change != 0 {
+            self.changes.push((range.start, change));
+        }
+    }
+
+    /// Leave a segment out.
+    pub(super) fn remove(&mut self, segment: &Segment) {
+        self.replace(segment_range(segment), &[]);
+    }
+
+    /// Copy the rest of the data, and return the new data.
+    pub(super) fn finish(mut self) -> Vec<u8> {
+        self.out.extend_from_slice(&self.buf[self.copied..]);
+        let (Some(old), Some(new)) = (find_mpf(self.buf), find_mpf(&self.out)) else {
+            return self.out;
+        };
+        let size_change = self.changes.iter().map(|&(_, change)| change).sum();
+        let offset_change = self
+            .changes
+            .iter()
+            .filter(|&&(position, _)| position > old)
+            .map(|&(_, change)| change)
+            .sum();
+        // The MP header follows the marker, length and "MPF\0".
+        adjust_mpf_entries(&mut self.out[new + 8..], size_change, offset_change);
+        self.out
+    }
+}
+
+/// The range a segment takes up in the data, including its marker and
+/// length.
+fn segment_range(segment: &Segment) -> Range<usize> {
+    segment.offset..segment.offset + 4 + segment.data.len()
+}
+
+/// The position of the MPF segment, if there is one.
+fn find_mpf(buf: &[u8]) -> Option<usize> {
+    Segments::new(buf)
+        .map_while(Result::ok)
+        .find(|s| s.marker == Marker::App(2) && s.data.starts_with(b"MPF\0"))
+        .map(|s| s.offset)
+}
+
+/// Copy the JPEG data, replacing its COM segments with one for each of the
+/// comments. Everything else, including the scan data, is copied as it is.
+///
+/// The new COM segments go where the first old one was or, if there wasn't
+/// one, after the APPn segments at the start of the image, so that the JFIF
+/// or EXIF segment stays first.
+pub(crate) fn write_jpeg_comments(
+    buf: &[u8],
+    comments: &[impl AsRef<[u8]>],
+) -> Result<Vec<u8>, JpegDecodingError> {
+    let mut segments = Vec::new();
+    for comment in comments {
+        let comment = comment.as_ref();
+        if comment.len() > MAX_SEGMENT_DATA {
+            return Err(JpegDecodingError::CommentTooLong {
+                length: comment.len(),
+            });
+        }
+        let length = (comment.len() + 2) as u16;
+        segments.extend_from_slice(&[0xff, Marker::Com.code()]);
+        segments.extend_from_slice(&length.to_be_bytes());
+        segments.extend_from_slice(comment);
+    }
+
+    let mut rewriter = Rewriter::new(buf);
+    let mut inserted = false;
+    for segment in Segments::new(buf) {
+        let segment = segment?;
+        let insert = match segment.marker {
+            Marker::Soi | Marker::App(_) => false,
+            _ => !inserted,
+        };
+        if insert {
+            rewriter.replace(segment.offset..segment.offset, &segments);
+            inserted = true;
+        }
+        if segment.marker == Marker::Com {
+            rewriter.remove(&segment);
+        }
+    }
+    let mut out = rewriter.finish();
+    if !inserted {
+        // There are only APPn segments, with no frame to put comments before.
+        out.extend_from_slice(&segments);
+    }
+    Ok(out)
+}
+
+/// Copy the JPEG data with the orientation in its EXIF segment changed.
+/// Nothing moves, so everything else is copied as it is. Returns `None` if
+/// there is no EXIF segment before the first scan, or it has no orientation.
+pub(crate) fn set_jpeg_orientation(
+    buf: &[u8],
+    orientation: u16,
+) -> Result<Option<Vec<u8>>, JpegDecodingError> {
+    for segment in Segments::new(buf) {
+        let segment = segment?;
+        if segment.marker != Marker::App(1) {
+            continue;
+        }
+        if let Some(tiff) = embedded::read_exif(segment.data) {
+            let end = segment.offset + 4 + segment.data.len();
+            let mut out = buf.to_vec();
+            let found = exif::set_orientation(&mut out[end - tiff.len()..end], orientation);
+            return Ok(found.then_some(out));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jpeg::{mpf, read_jpeg_data};
+
+    #[test]
+    fn test_write_comments() {
+        let data = std::fs::read("src/buttercups.jpg").unwrap();
+        let written = write_jpeg_comments(&data, &["Daisies", "Dandelions"]).unwrap();
+        let metadata = read_jpeg_data(&written).unwrap();
+        assert_eq!(
+            metadata.comments,
+            vec![b"Daisies".to_vec(), b"Dandelions".to_vec()]
+        );
+        // The COM segment was at 0x14, after the JFIF segment.
+        assert_eq!(&written[..0x14], &data[..0x14]);
+        assert_eq!(&written[0x14..0x1f], b"\xff\xfe\x00\x09Daisies");
+        // Apart from the comments, nothing has changed.
+        let com = 0x14..0x14 + 4 + b"Buttercups".len();
+        let added = 4 + b"Daisies".len() + 4 + b"Dandelions".len();
+        assert_eq!(&written[0x14 + added..], &data[com.end..]);
+
+        let written = write_jpeg_comments(&data, &[] as &[&[u8]]).unwrap();
+        assert_eq!(written.len(), data.len() - com.len());
+        assert!(read_jpeg_data(&written).unwrap().comments.is_empty());
+
+        // Adding a comment to an image that had none puts it after the APP1
+        // segment, before the DQT segment at 0x3a.
+        let rewritten = write_jpeg_comments(&written, &["Buttercups"]).unwrap();
+        assert_eq!(rewritten.len(), data.len());
+        let dqt = 0x3a - com.len();
+        assert_eq!(&rewritten[dqt..dqt + 4], b"\xff\xfe\x00\x0c");
+        assert_eq!(&rewritten[dqt + com.len()..], &written[dqt..]);
+    }
+
+    #[test]
+    fn test_mpf() {
+        // The secondary image stays where the MPF segment says it is.
+        let buf = mpf::tests::mpf_image(true);
+        let written = write_jpeg_comments(&buf, &["A comment"]).unwrap();
+        assert_eq!(written.len(), buf.len() + 13);
+        let mpf = read_jpeg_data(&written).unwrap().jpeg.unwrap().mpf.unwrap();
+        assert_eq!(mpf.entries[0].size, buf.len() as u32 - 17 + 13);
+        assert_eq!((mpf.entries[1].width, mpf.entries[1].height), (32, 24));
+    }
+
+    #[test]
+    fn test_set_orientation() {
+        let data = std::fs::read("src/buttercups.jpg").unwrap();
+        // The sample image's EXIF data has no orientation.
+        assert_eq!(set_jpeg_orientation(&data, 1), Ok(None));
+
+        let mut tiff = exif::tests::exif(true);
+        tiff.splice(0..0, *b"Exif\0\0");
+        let mut segment = vec![0xff, 0xe1];
+        segment.extend_from_slice(&(tiff.len() as u16 + 2).to_be_bytes());
+        segment.extend(tiff);
+        let mut data = data;
+        data.splice(0x22..0x3a, segment);
+        let written = set_jpeg_orientation(&data, 8).unwrap().unwrap();
+        assert_eq!(written.len(), data.len());
+        // The orientation's value is at 0x22 + 10 + 18.
+        assert_eq!(&written[0x3e..0x40], b"\x00\x08");
+        assert_eq!(&written[..0x3e], &data[..0x3e]);
+        assert_eq!(&written[0x40..], &data[0x40..]);
+    }
+
+    #[test]
+    fn test_comment_too_long() {
+        let data = std::fs::read("src/buttercups.jpg").unwrap();
+        let comment = vec![b'x'; MAX_SEGMENT_DATA + 1];
+        assert_eq!(
+            write_jpeg_comments(&data, &[comment]),
+            Err(JpegDecodingError::CommentTooLong { length: 65534 })
+        );
+    }
+}
@@ -5,15 +5,24 @@
 //! image.
 //!
 //! For PNG images, the dimensions are extracted from the IHDR chunk, and the
-//! comments are extracted from tEXt chunks with the keyword "comment".
+//! comments are extracted from tEXt chunks with the keyword "comment". The
+//! colorimetry chunks (gAMA, cHRM, sRGB, sBIT and bKGD) and the cICP, mDCv and
+//! cLLI chunks describing HDR images are also decoded, and their placement
+//! checked.
 //!
 //! For JPEG images, the dimensions are extracted from the SOFx chunk, and the
-//! comments are extracted from COM chunks.
+//! comments are extracted from COM chunks. The JFIF, JFXX and Adobe headers
+//! are also decoded, and used to work out the image's color model.
 //!
 //! The reader is fast because it only reads the chunks that are necessary to
 //! extract the dimensions and comments. It does not decode the image data.
+//! To look at the other chunks and segments, use [`png::Chunks`] and
+//! [`jpeg::Segments`].
 //!
-//! The reader does not attempt to read EXIF data.
+//! The reader does not attempt to decode EXIF data, apart from finding
+//! embedded thumbnails with [`jpeg::read_thumbnails`], but it can extract the
+//! raw EXIF, XMP and ICC payloads if asked to with [`ReadOptions`], which also
+//! controls how much of the image is read.
 //!
 //! # Example
 //!
@@ -25,17 +34,42 @@
 //! assert_eq!(vec![b"Buttercups".to_vec()], metadata.comments);
 //! ```
 
-mod jpeg;
-mod png;
+mod batch;
+mod exif;
+pub mod jpeg;
+mod limits;
+#[cfg(feature = "mmap")]
+mod mmap;
+mod options;
+pub mod png;
+mod strip;
+mod tiff;
+mod visitor;
+mod warnings;
+mod xmp;
+use std::borrow::Cow;
 use std::fmt::Display;
 use std::io;
+use std::ops::Range;
 use std::path::Path;
 
+pub use batch::{
+    read_dir_recursive, read_dir_recursive_iter, read_many, read_many_with, DirOptions,
+    ReadDirRecursive,
+};
 pub use jpeg::JpegDecodingError;
+pub use limits::{LimitKind, Limits};
+#[cfg(feature = "mmap")]
+pub use mmap::MappedImage;
+pub use options::{ImageFormat, ReadOptions, StopAt};
 pub use png::PngDecodingError;
+pub use strip::{ExifPolicy, StripPolicy};
+pub use visitor::{Flow, MetadataVisitor};
+pub use warnings::{Strictness, Warning, WarningKind};
 
 /// An error that occurred while reading an image.
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum Error {
     Io(io::Error),
     Decoding(DecodingError),
@@ -64,8 +98,81 @@ impl Display for Error {
 
 impl std::error::Error for Error {}
 
+impl Error {
+    /// What kind of error this is, for handling errors without matching on
+    /// every format's error type.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::Io(_) => ErrorKind::Io,
+            Error::Decoding(e) => e.kind(),
+        }
+    }
+
+    /// Where in the data the error is, if it is anywhere in particular.
+    pub fn offset(&self) -> Option<usize> {
+        match self {
+            Error::Io(_) => None,
+            Error::Decoding(e) => e.offset(),
+        }
+    }
+}
+
+/// The broad kind of an [`Error`], whichever format it came from.
+///
+/// # Examples
+///
+/// ```
+/// use pb_imgsize::{read_bytes, ErrorKind};
+///
+/// let data = include_bytes!("watercolors.png");
+/// let err = read_bytes(&data[..30]).unwrap_err();
+/// assert_eq!(err.kind(), ErrorKind::Truncated);
+/// assert_eq!(err.offset(), Some(8));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// The file couldn't be read.
+    Io,
+    /// The data isn't a JPEG or PNG image.
+    UnknownFormat,
+    /// The image is in a format that [`ReadOptions::formats`] doesn't allow.
+    FormatNotAllowed,
+    /// The data ends before the image does.
+    Truncated,
+    /// A PNG chunk's CRC doesn't match its contents.
+    ChecksumMismatch,
+    /// Something the image must have isn't there, such as a JPEG's SOF
+    /// segment or a PNG's IHDR chunk.
+    Missing,
+    /// Segments or chunks are in the wrong order, or repeated.
+    OutOfOrder,
+    /// A segment or chunk has the wrong length or invalid contents.
+    InvalidData,
+    /// One of the [`Limits`] was exceeded.
+    LimitExceeded,
+}
+
+impl Display for ErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ErrorKind::Io => "I/O error",
+            ErrorKind::UnknownFormat => "unknown format",
+            ErrorKind::FormatNotAllowed => "format not allowed",
+            ErrorKind::Truncated => "truncated",
+            ErrorKind::ChecksumMismatch => "checksum mismatch",
+            ErrorKind::Missing => "missing",
+            ErrorKind::OutOfOrder => "out of order",
+            ErrorKind::InvalidData => "invalid data",
+            ErrorKind::LimitExceeded => "limit exceeded",
+        };
+        f.write_str(name)
+    }
+}
+
 /// An error that occurred while decoding an image.
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum DecodingError {
     // #[error("Unknown magic number in image data: 0x{0:08x}")]
     UnknownMagic(u32),
@@ -76,19 +183,77 @@ pub enum DecodingError {
     // #[error(transparent)]
     Png(png::PngDecodingError),
 
-    // #[error("Image data too short: {0} bytes")]
-    TooShort(usize),
+    // #[error("Image data too short: {length} bytes, need at least {required}")]
+    TooShort {
+        length: usize,
+        required: usize,
+    },
+
+    // #[error("Image format not allowed: {0}")]
+    FormatNotAllowed(ImageFormat),
+
+    // #[error("Image {kind} of {value} exceeds the limit of {limit}")]
+    LimitExceeded {
+        kind: LimitKind,
+        value: u64,
+        limit: u64,
+    },
+
+    // #[error("Invalid EXIF orientation: {0}, expected 1 to 8")]
+    InvalidOrientation(u16),
+
+    // #[error("No EXIF orientation tag to change")]
+    MissingOrientation,
 }
 
 impl From<jpeg::JpegDecodingError> for DecodingError {
     fn from(e: jpeg::JpegDecodingError) -> Self {
-        DecodingError::Jpeg(e)
+        match e {
+            jpeg::JpegDecodingError::LimitExceeded { kind, value, limit } => {
+                DecodingError::LimitExceeded { kind, value, limit }
+            }
+            e => DecodingError::Jpeg(e),
+        }
     }
 }
 
 impl From<png::PngDecodingError> for DecodingError {
     fn from(e: png::PngDecodingError) -> Self {
-        DecodingError::Png(e)
+        match e {
+            png::PngDecodingError::LimitExceeded { kind, value, limit } => {
+                DecodingError::LimitExceeded { kind, value, limit }
+            }
+            e => DecodingError::Png(e),
+        }
+    }
+}
+
+impl DecodingError {
+    /// What kind of error this is.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            DecodingError::UnknownMagic(_) => ErrorKind::UnknownFormat,
+            DecodingError::Jpeg(e) => e.kind(),
+            DecodingError::Png(e) => e.kind(),
+            DecodingError::TooShort { .. } => ErrorKind::Truncated,
+            DecodingError::FormatNotAllowed(_) => ErrorKind::FormatNotAllowed,
+            DecodingError::LimitExceeded { .. } => ErrorKind::LimitExceeded,
+            DecodingError::InvalidOrientation(_) => ErrorKind::InvalidData,
+            DecodingError::MissingOrientation => ErrorKind::Missing,
+        }
+    }
+
+    /// Where in the data the error is, if it is anywhere in particular.
+    pub fn offset(&self) -> Option<usize> {
+        match self {
+            DecodingError::UnknownMagic(_) | DecodingError::FormatNotAllowed(_) => Some(0),
+            DecodingError::Jpeg(e) => e.offset(),
+            DecodingError::Png(e) => e.offset(),
+            DecodingError::TooShort { length, .. } => Some(*length),
+            DecodingError::LimitExceeded { .. }
+            | DecodingError::InvalidOrientation(_)
+            | DecodingError::MissingOrientation => None,
+        }
     }
 }
 
@@ -100,17 +265,182 @@ impl Display for DecodingError {
             }
             DecodingError::Jpeg(e) => write!(f, "JPEG decoding error: {}", e),
             DecodingError::Png(e) => write!(f, "PNG decoding error: {}", e),
-            DecodingError::TooShort(n) => write!(f, "Image data too short: {} bytes", n),
+            DecodingError::TooShort { length, required } => write!(
+                f,
+                "Image data too short: {} bytes, need at least {}",
+                length, required
+            ),
+            DecodingError::FormatNotAllowed(format) => {
+                write!(f, "Image format not allowed: {}", format)
+            }
+            DecodingError::LimitExceeded { kind, value, limit } => write!(
+                f,
+                "Image {} of {} exceeds the limit of {}",
+                kind, value, limit
+            ),
+            DecodingError::InvalidOrientation(orientation) => write!(
+                f,
+                "Invalid EXIF orientation: {}, expected 1 to 8",
+                orientation
+            ),
+            DecodingError::MissingOrientation => write!(f, "No EXIF orientation tag to change"),
         }
     }
 }
 
 /// An image's dimensions, along with any comments found in the data.
-#[derive(Debug, Clone, PartialEq, Eq)]
+///
+/// More fields may be added in later versions, so outside this crate the
+/// struct can't be built with a struct literal; use [`ImageMetadata::new`]
+/// and set the fields afterwards instead.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
 pub struct ImageMetadata {
     pub width: u32,
     pub height: u32,
     pub comments: Vec<Vec<u8>>,
+    /// The EXIF data (starting with the TIFF header), if asked for with
+    /// [`ReadOptions::exif`] and present.
+    pub exif: Option<Vec<u8>>,
+    /// The XMP packet, if asked for with [`ReadOptions::xmp`] and present.
+    pub xmp: Option<Vec<u8>>,
+    /// The ICC profile, if asked for with [`ReadOptions::icc_profile`] and
+    /// present.
+    pub icc_profile: Option<Vec<u8>>,
+    /// Problems that reading carried on past. See [`Strictness`].
+    pub warnings: Vec<Warning>,
+    /// Whether the data ends before the image does: a PNG without an IEND
+    /// chunk, or a JPEG without an EOI marker.
+    ///
+    /// For a JPEG, this is only meaningful when reading to [`StopAt::End`].
+    /// Otherwise it is always `false`, which says nothing about whether the
+    /// image is complete.
+    pub truncated: bool,
+    /// Any bytes after the end of the image, which could be another file
+    /// appended to it.
+    ///
+    /// This is only meaningful when reading to [`StopAt::End`], or at least
+    /// [`StopAt::Metadata`] for a PNG. Otherwise it is always `None`, whether
+    /// or not there are bytes after the image.
+    ///
+    /// A JPEG with an MPF segment stores its other images here, after the
+    /// primary image's EOI marker.
+    pub trailing_bytes: Option<Range<usize>>,
+    /// JPEG-specific metadata, if the image is a JPEG.
+    pub jpeg: Option<jpeg::JpegMetadata>,
+    /// PNG-specific metadata, if the image is a PNG.
+    pub png: Option<png::PngMetadata>,
+}
+
+/// An image's dimensions, along with any comments found in the data, borrowed
+/// from the data rather than copied.
+///
+/// # Examples
+///
+/// ```
+/// # fn main() -> Result<(), pb_imgsize::DecodingError> {
+/// let data = include_bytes!("buttercups.jpg");
+/// let metadata = pb_imgsize::read_bytes_ref(data)?;
+/// assert_eq!(metadata.comments, vec![b"Buttercups"]);
+/// assert_eq!(metadata.to_owned(), pb_imgsize::read_bytes(data)?);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ImageMetadataRef<'a> {
+    pub width: u32,
+    pub height: u32,
+    pub comments: Vec<&'a [u8]>,
+    /// The EXIF data (starting with the TIFF header), if asked for with
+    /// [`ReadOptions::exif`] and present.
+    pub exif: Option<&'a [u8]>,
+    /// The XMP packet, if asked for with [`ReadOptions::xmp`] and present.
+    /// This is only copied if it was compressed in the image data.
+    pub xmp: Option<Cow<'a, [u8]>>,
+    /// The ICC profile, if asked for with [`ReadOptions::icc_profile`] and
+    /// present. This is only copied if it was split up or compressed in the
+    /// image data.
+    pub icc_profile: Option<Cow<'a, [u8]>>,
+    /// Problems that reading carried on past. See [`Strictness`].
+    pub warnings: Vec<Warning>,
+    /// Whether the data ends before the image does: a PNG without an IEND
+    /// chunk, or a JPEG without an EOI marker.
+    ///
+    /// For a JPEG, this is only meaningful when reading to [`StopAt::End`].
+    /// Otherwise it is always `false`, which says nothing about whether the
+    /// image is complete.
+    pub truncated: bool,
+    /// Any bytes after the end of the image, which could be another file
+    /// appended to it.
+    ///
+    /// This is only meaningful when reading to [`StopAt::End`], or at least
+    /// [`StopAt::Metadata`] for a PNG. Otherwise it is always `None`, whether
+    /// or not there are bytes after the image.
+    ///
+    /// A JPEG with an MPF segment stores its other images here, after the
+    /// primary image's EOI marker.
+    pub trailing_bytes: Option<Range<usize>>,
+    /// JPEG-specific metadata, if the image is a JPEG.
+    pub jpeg: Option<jpeg::JpegMetadata>,
+    /// PNG-specific metadata, if the image is a PNG.
+    pub png: Option<png::PngMetadata>,
+}
+
+impl ImageMetadata {
+    /// Metadata with the given dimensions and comments, and nothing else.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let metadata = pb_imgsize::ImageMetadata::new(512, 341, vec![b"Buttercups".to_vec()]);
+    /// assert_eq!(metadata.exif, None);
+    /// ```
+    pub fn new(width: u32, height: u32, comments: Vec<Vec<u8>>) -> Self {
+        ImageMetadata {
+            width,
+            height,
+            comments,
+            ..Default::default()
+        }
+    }
+
+    /// The format of the image, or `None` if the metadata wasn't read from
+    /// an image, as when it was built with [`ImageMetadata::new`].
+    pub fn format(&self) -> Option<ImageFormat> {
+        if self.jpeg.is_some() {
+            Some(ImageFormat::Jpeg)
+        } else if self.png.is_some() {
+            Some(ImageFormat::Png)
+        } else {
+            None
+        }
+    }
+}
+
+impl<'a> ImageMetadataRef<'a> {
+    /// Copy the borrowed data, so that it no longer refers to the image data.
+    pub fn to_owned(&self) -> ImageMetadata {
+        self.clone().into()
+    }
+}
+
+impl<'a> From<ImageMetadataRef<'a>> for ImageMetadata {
+    fn from(metadata: ImageMetadataRef<'a>) -> Self {
+        ImageMetadata {
+            width: metadata.width,
+            height: metadata.height,
+            comments: metadata.comments.iter().map(|c| c.to_vec()).collect(),
+            exif: metadata.exif.map(|exif| exif.to_vec()),
+            xmp: metadata.xmp.map(Cow::into_owned),
+            icc_profile: metadata.icc_profile.map(Cow::into_owned),
+            warnings: metadata.warnings,
+            truncated: metadata.truncated,
+            trailing_bytes: metadata.trailing_bytes,
+            jpeg: metadata.jpeg,
+            png: metadata.png,
+        }
+    }
 }
 
 /// Reads the dimensions and comments of an image from a file.
@@ -130,16 +460,59 @@ pub struct ImageMetadata {
 /// ```
 /// # fn main() -> Result<(), pb_imgsize::Error> {
 /// let metadata = pb_imgsize::read_file("src/buttercups.jpg")?;
-/// assert_eq!(metadata, pb_imgsize::ImageMetadata {
-///   width: 512,
-///   height: 341,
-///   comments: vec![b"Buttercups".to_vec()],
-/// });
+/// assert_eq!(metadata.width, 512);
+/// assert_eq!(metadata.height, 341);
+/// assert_eq!(metadata.comments, vec![b"Buttercups".to_vec()]);
 /// # Ok(())
 /// # }
 pub fn read_file(path: impl AsRef<Path>) -> Result<ImageMetadata, Error> {
+    read_file_with(&ReadOptions::default(), path)
+}
+
+/// Reads the metadata of an image from a file, using the given options.
+///
+/// Note: This function works by reading the entire file into memory.
+pub fn read_file_with(
+    options: &ReadOptions,
+    path: impl AsRef<Path>,
+) -> Result<ImageMetadata, Error> {
     let buf = std::fs::read(path)?;
-    Ok(read_bytes(&buf)?)
+    Ok(read_bytes_with(options, &buf)?)
+}
+
+/// Reads the dimensions and comments of an image from a file, by mapping it
+/// into memory rather than reading all of it.
+///
+/// Only the parts of the file that are needed are read from disk, which is
+/// much faster than [`read_file`] for large files. The comments are copied
+/// out of the mapping before it is closed; to borrow them instead, use
+/// [`MappedImage`].
+///
+/// A file that is empty or too short returns an error, as with
+/// [`read_bytes`], and a file that shrinks while it is being read returns an
+/// [`io::ErrorKind::UnexpectedEof`] error, rather than crashing the process
+/// with `SIGBUS` or returning metadata that may no longer match it: see
+/// [`MappedImage`] for how.
+///
+/// This function requires the `mmap` feature.
+///
+/// # Examples
+///
+/// ```
+/// # fn main() -> Result<(), pb_imgsize::Error> {
+/// let metadata = pb_imgsize::read_file_mmap("src/buttercups.jpg")?;
+/// assert_eq!(metadata.width, 512);
+/// assert_eq!(metadata.height, 341);
+/// # Ok(())
+/// # }
+/// ```
+#[cfg(feature = "mmap")]
+pub fn read_file_mmap(path: impl AsRef<Path>) -> Result<ImageMetadata, Error> {
+    let image = MappedImage::open(path)?;
+    let metadata = image.metadata(&ReadOptions::default())?.to_owned();
+    // Copying the comments out reads the mapping again.
+    image.check_unchanged()?;
+    Ok(metadata)
 }
 
 /// Reads the dimensions and comments of an image from a byte slice.
@@ -160,21 +533,204 @@ pub fn read_file(path: impl AsRef<Path>) -> Result<ImageMetadata, Error> {
 ///
 /// let data = include_bytes!("buttercups.jpg");
 /// let metadata = read_bytes(data)?;
-/// assert_eq!(metadata, pb_imgsize::ImageMetadata {
-///    width: 512,
-///    height: 341,
-///    comments: vec![b"Buttercups".to_vec()]
-/// });
+/// assert_eq!(metadata.width, 512);
+/// assert_eq!(metadata.height, 341);
+/// assert_eq!(metadata.comments, vec![b"Buttercups".to_vec()]);
 /// # Ok(())
 /// # }
 /// ```
 pub fn read_bytes(data: &[u8]) -> Result<ImageMetadata, DecodingError> {
+    read_bytes_with_visitor(data, &mut ())
+}
+
+/// Reads the dimensions and comments of an image from a byte slice, calling
+/// the visitor for each JPEG segment or PNG chunk along the way.
+///
+/// See [`MetadataVisitor`] for an example.
+pub fn read_bytes_with_visitor(
+    data: &[u8],
+    visitor: &mut impl MetadataVisitor,
+) -> Result<ImageMetadata, DecodingError> {
+    read_ref(&ReadOptions::default(), data, visitor).map(ImageMetadata::from)
+}
+
+/// Reads the metadata of an image from a byte slice, using the given options.
+///
+/// See [`ReadOptions`] for an example.
+pub fn read_bytes_with(options: &ReadOptions, data: &[u8]) -> Result<ImageMetadata, DecodingError> {
+    read_ref(options, data, &mut ()).map(ImageMetadata::from)
+}
+
+/// Reads the dimensions and comments of an image from a byte slice, without
+/// copying the comments out of it.
+///
+/// This saves an allocation for each comment, compared to [`read_bytes`].
+/// See [`ImageMetadataRef`] for an example.
+pub fn read_bytes_ref(data: &[u8]) -> Result<ImageMetadataRef<'_>, DecodingError> {
+    read_ref(&ReadOptions::default(), data, &mut ())
+}
+
+/// Reads the metadata of an image from a byte slice, using the given options,
+/// without copying the comments or payloads out of it.
+pub fn read_bytes_ref_with<'a>(
+    options: &ReadOptions,
+    data: &'a [u8],
+) -> Result<ImageMetadataRef<'a>, DecodingError> {
+    read_ref(options, data, &mut ())
+}
+
+/// Reads the metadata of an image from a byte slice, after checking that the
+/// whole image is intact rather than just its header.
+///
+/// For a PNG, every chunk's CRC is checked through to the IEND chunk, which
+/// must be present, the IDAT chunks must be contiguous, and the image data
+/// must inflate to exactly the size that the IHDR chunk calls for. For a JPEG,
+/// each SOS segment must refer only to components of the frame, the restart
+/// markers in each scan must be in sequence, there must be nothing but
+/// entropy-coded data between segments, and there must be an EOI marker.
+/// The metadata is read with [`Strictness::Strict`], so problems that a normal
+/// read only warns about, such as chunks in the wrong order, are errors too.
+///
+/// This reads the whole image, so it is much slower than [`read_bytes`].
+///
+/// # Examples
+///
+/// ```
+/// use pb_imgsize::{validate, DecodingError, JpegDecodingError};
+///
+/// let data = include_bytes!("buttercups.jpg");
+/// assert!(validate(data).is_ok());
+/// assert_eq!(
+///     validate(&data[..1000]),
+///     Err(DecodingError::Jpeg(JpegDecodingError::MissingEoi {
+///         position: 1000
+///     }))
+/// );
+/// ```
+pub fn validate(data: &[u8]) -> Result<ImageMetadata, DecodingError> {
+    let options = ReadOptions::new().strictness(Strictness::Strict);
+    let metadata = read_bytes_with(&options, data)?;
+    if data.starts_with(b"\xff\xd8") {
+        jpeg::validate_jpeg(data)?;
+    } else {
+        png::validate_png(data)?;
+    }
+    Ok(metadata)
+}
+
+/// Reads the metadata of an image from a file, after checking that the whole
+/// image is intact. See [`validate`] for what is checked.
+pub fn validate_file(path: impl AsRef<Path>) -> Result<ImageMetadata, Error> {
+    let buf = std::fs::read(path)?;
+    Ok(validate(&buf)?)
+}
+
+/// Writes a copy of an image with its comments replaced: the COM segments of
+/// a JPEG, or the `comment` tEXt chunks of a PNG. Everything else, including
+/// the image data, is copied unchanged.
+///
+/// To remove the comments, pass an empty list; to add one, read the existing
+/// comments and pass them along with the new one.
+///
+/// A PNG without an IEND chunk has been cut short, and gives
+/// [`PngDecodingError::MissingIEND`] rather than a copy with the comments
+/// tacked on after the truncated data.
+///
+/// # Examples
+///
+/// ```
+/// use pb_imgsize::{read_bytes, write_comments};
+///
+/// let data = include_bytes!("buttercups.jpg");
+/// let mut comments = read_bytes(data)?.comments;
+/// comments.push(b"Photographed in May".to_vec());
+/// let written = write_comments(data, &comments)?;
+/// assert_eq!(read_bytes(&written)?.comments, comments);
+/// # Ok::<(), pb_imgsize::DecodingError>(())
+/// ```
+pub fn write_comments(
+    data: &[u8],
+    comments: &[impl AsRef<[u8]>],
+) -> Result<Vec<u8>, DecodingError> {
+    match image_format(data)? {
+        ImageFormat::Jpeg => Ok(jpeg::write_jpeg_comments(data, comments)?),
+        ImageFormat::Png => Ok(png::write_png_comments(data, comments)?),
+    }
+}
+
+/// Writes a copy of an image with its EXIF orientation tag changed, for
+/// example back to 1 (upright) after the pixels have been rotated elsewhere.
+/// The tag is changed in place, in a JPEG APP1 segment or a PNG eXIf chunk,
+/// so nothing else in the image moves; a PNG chunk's CRC is worked out afresh.
+///
+/// An image with no orientation tag is already upright, so setting it to 1
+/// returns an unchanged copy; setting it to anything else returns
+/// [`DecodingError::MissingOrientation`], since adding a tag would mean
+/// rewriting the EXIF data.
+///
+/// # Examples
+///
+/// ```
+/// use pb_imgsize::{set_orientation, DecodingError};
+///
+/// let data = include_bytes!("buttercups.jpg");
+/// assert_eq!(set_orientation(data, 1)?, data);
+/// assert_eq!(set_orientation(data, 6), Err(DecodingError::MissingOrientation));
+/// # Ok::<(), pb_imgsize::DecodingError>(())
+/// ```
+pub fn set_orientation(data: &[u8], orientation: u16) -> Result<Vec<u8>, DecodingError> {
+    if !(1..=8).contains(&orientation) {
+        return Err(DecodingError::InvalidOrientation(orientation));
+    }
+    let written = match image_format(data)? {
+        ImageFormat::Jpeg => jpeg::set_jpeg_orientation(data, orientation)?,
+        ImageFormat::Png => png::set_png_orientation(data, orientation)?,
+    };
+    match written {
+        Some(written) => Ok(written),
+        None if orientation == 1 => Ok(data.to_vec()),
+        None => Err(DecodingError::MissingOrientation),
+    }
+}
+
+/// Writes a copy of an image with metadata removed, as set by the policy,
+/// without decoding or re-encoding the image data. See [`StripPolicy`] for
+/// what can be kept.
+///
+/// Only the primary image of a JPEG is stripped: images stored after it
+/// through MPF, such as gain maps, are copied as they are.
+///
+/// # Examples
+///
+/// ```
+/// use pb_imgsize::{read_bytes_with, strip, ReadOptions, StripPolicy};
+///
+/// let data = include_bytes!("buttercups.jpg");
+/// let stripped = strip(&StripPolicy::new(), data)?;
+/// let options = ReadOptions::new().exif(true);
+/// let metadata = read_bytes_with(&options, &stripped)?;
+/// assert!(metadata.comments.is_empty());
+/// assert_eq!(metadata.exif, None);
+/// # Ok::<(), pb_imgsize::DecodingError>(())
+/// ```
+pub fn strip(policy: &StripPolicy, data: &[u8]) -> Result<Vec<u8>, DecodingError> {
+    match image_format(data)? {
+        ImageFormat::Jpeg => Ok(jpeg::strip_jpeg(data, policy)?),
+        ImageFormat::Png => Ok(png::strip_png(data, policy)?),
+    }
+}
+
+/// Works out the format of the image from its first few bytes.
+fn image_format(data: &[u8]) -> Result<ImageFormat, DecodingError> {
     if data.len() < 4 {
-        Err(DecodingError::TooShort(0))
+        Err(DecodingError::TooShort {
+            length: data.len(),
+            required: 4,
+        })
     } else if data.starts_with(b"\xff\xd8") {
-        Ok(jpeg::read_jpeg_data(data)?)
+        Ok(ImageFormat::Jpeg)
     } else if data.starts_with(b"\x89PNG") {
-        Ok(png::read_png_data(data)?)
+        Ok(ImageFormat::Png)
     } else {
         Err(DecodingError::UnknownMagic(u32::from_be_bytes([
             data[0], data[1], data[2], data[3],
@@ -182,15 +738,32 @@ pub fn read_bytes(data: &[u8]) -> Result<ImageMetadata, DecodingError> {
     }
 }
 
+fn read_ref<'a>(
+    options: &ReadOptions,
+    data: &'a [u8],
+    visitor: &mut impl MetadataVisitor,
+) -> Result<ImageMetadataRef<'a>, DecodingError> {
+    let format = image_format(data)?;
+    if !options.allows(format) {
+        return Err(DecodingError::FormatNotAllowed(format));
+    }
+    match format {
+        ImageFormat::Jpeg => Ok(jpeg::read_jpeg_data_ref(data, options, visitor)?),
+        ImageFormat::Png => Ok(png::read_png_data_ref(data, options, visitor)?),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
 
     #[test]
     fn test_jpeg_file() {
         let metadata = read_file(Path::new("src/buttercups.jpg")).unwrap();
         assert_eq!(512, metadata.width);
         assert_eq!(341, metadata.height);
+        assert_eq!(metadata.format(), Some(ImageFormat::Jpeg));
         let comments = metadata
             .comments
             .iter()
@@ -204,6 +777,8 @@ mod tests {
         let metadata = read_file(Path::new("src/watercolors.png")).unwrap();
         assert_eq!(400, metadata.width);
         assert_eq!(224, metadata.height);
+        assert_eq!(metadata.format(), Some(ImageFormat::Png));
+        assert_eq!(ImageMetadata::new(400, 224, vec![]).format(), None);
         let comments = metadata
             .comments
             .iter()
@@ -211,4 +786,159 @@ mod tests {
             .collect::<Vec<_>>();
         assert_eq!(vec!["Abstract watercolors".to_string()], comments);
     }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_read_file_mmap() {
+        let metadata = read_file_mmap("src/watercolors.png").unwrap();
+        assert_eq!(metadata, read_file("src/watercolors.png").unwrap());
+
+        // Empty and truncated files give errors, not crashes.
+        let dir = std::env::temp_dir().join(format!("imgsize-mmap-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let empty = dir.join("empty.jpg");
+        std::fs::write(&empty, b"").unwrap();
+        assert!(matches!(
+            read_file_mmap(&empty),
+            Err(Error::Decoding(DecodingError::TooShort { length: 0, .. }))
+        ));
+        let data = std::fs::read("src/buttercups.jpg").unwrap();
+        let truncated = dir.join("truncated.jpg");
+        std::fs::write(&truncated, &data[..40]).unwrap();
+        assert!(matches!(
+            read_file_mmap(&truncated),
+            Err(Error::Decoding(DecodingError::Jpeg(_)))
+        ));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_allowed_formats() {
+        let options = ReadOptions::new().formats(&[ImageFormat::Png]);
+        let data = std::fs::read("src/buttercups.jpg").unwrap();
+        assert_eq!(
+            read_bytes_with(&options, &data),
+            Err(DecodingError::FormatNotAllowed(ImageFormat::Jpeg))
+        );
+        let metadata = read_file_with(&options, "src/watercolors.png").unwrap();
+        assert_eq!(metadata.width, 400);
+    }
+
+    #[test]
+    fn test_png_ref() {
+        let data = std::fs::read("src/watercolors.png").unwrap();
+        let metadata = read_bytes_ref(&data).unwrap();
+        assert_eq!(metadata.comments, vec![b"Abstract watercolors"]);
+        // The comment is borrowed straight from the data.
+        let range = data.as_ptr_range();
+        assert!(range.contains(&metadata.comments[0].as_ptr()));
+        assert_eq!(metadata.to_owned(), read_bytes(&data).unwrap());
+    }
+
+    #[test]
+    fn test_validate_strictly() {
+        // A gAMA chunk after the image data is only a warning when reading,
+        // but validating rejects it.
+        let mut data = std::fs::read("src/watercolors.png").unwrap();
+        let gama = png::tests::chunk(b"gAMA", &[0, 0, 0xb1, 0x8f]);
+        data.splice(149863..149863, gama);
+        assert_eq!(read_bytes(&data).unwrap().warnings.len(), 1);
+        assert_eq!(validate(&data).unwrap_err().kind(), ErrorKind::OutOfOrder);
+    }
+
+    /// Records the offset of each segment or chunk that the reader visits.
+    #[derive(Default)]
+    struct Recorder {
+        offsets: Vec<usize>,
+    }
+
+    impl MetadataVisitor for Recorder {
+        fn visit_jpeg_segment(&mut self, segment: &jpeg::Segment) -> Flow {
+            self.offsets.push(segment.offset);
+            Flow::Continue
+        }
+
+        fn visit_png_chunk(&mut self, chunk: &png::Chunk) -> Flow {
+            self.offsets.push(chunk.offset);
+            Flow::Continue
+        }
+    }
+
+    /// Damage an image by overwriting some of its bytes, mostly near the
+    /// start where the headers are, and maybe cutting it short.
+    fn damaged_image() -> impl Strategy<Value = Vec<u8>> {
+        let images = prop_oneof![
+            Just(&include_bytes!("buttercups.jpg")[..]),
+            Just(&include_bytes!("watercolors.png")[..]),
+        ];
+        let edits = prop::collection::vec((any::<prop::sample::Index>(), any::<u8>()), 0..8);
+        let header_edits = prop::collection::vec((0usize..1024, any::<u8>()), 0..8);
+        let cut = prop::option::of(any::<prop::sample::Index>());
+        (images, edits, header_edits, cut).prop_map(|(image, edits, header_edits, cut)| {
+            let mut data = image.to_vec();
+            for (index, byte) in edits {
+                let i = index.index(data.len());
+                data[i] = byte;
+            }
+            for (i, byte) in header_edits {
+                data[i] = byte;
+            }
+            if let Some(cut) = cut {
+                data.truncate(cut.index(data.len()));
+            }
+            data
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn test_damaged_images_never_panic(data in damaged_image()) {
+            for strictness in [Strictness::Strict, Strictness::Normal, Strictness::Lenient] {
+                let options = ReadOptions::new()
+                    .exif(true)
+                    .xmp(true)
+                    .icc_profile(true)
+                    .scan_for_dnl(true)
+                    .strictness(strictness);
+                let _ = read_bytes_with(&options, &data);
+            }
+            let _ = validate(&data);
+            let _ = write_comments(&data, &["comment"]);
+            let _ = strip(&StripPolicy::private_tags_only(), &data);
+            let _ = set_orientation(&data, 1);
+            let _ = jpeg::read_thumbnails(&data);
+            for segment in jpeg::Segments::new(&data).through_scans(true) {
+                let _ = segment;
+            }
+            for chunk in png::Chunks::new(&data).verify_crc(false) {
+                let _ = chunk;
+            }
+        }
+
+        #[test]
+        fn test_readers_agree(data in damaged_image()) {
+            // Borrowed and owned metadata are the same.
+            let owned = read_bytes(&data);
+            prop_assert_eq!(&owned, &read_bytes_ref(&data).map(ImageMetadata::from));
+
+            // The reader sees the same segments or chunks as iterating over
+            // them, up to where it stops.
+            let mut recorder = Recorder::default();
+            if read_bytes_with_visitor(&data, &mut recorder).is_ok() {
+                let offsets: Vec<usize> = if data.starts_with(b"\xff\xd8") {
+                    jpeg::Segments::new(&data)
+                        .skip(1)
+                        .map_while(Result::ok)
+                        .map(|segment| segment.offset)
+                        .collect()
+                } else {
+                    png::Chunks::new(&data)
+                        .map_while(Result::ok)
+                        .map(|chunk| chunk.offset)
+                        .collect()
+                };
+                prop_assert!(offsets.starts_with(&recorder.offsets));
+            }
+        }
+    }
 }
@@ -0,0 +1,212 @@
+//! Limits on the resources that reading an image may use.
+
+use std::fmt::{self, Display};
+
+/// Limits on what an image may contain, for reading untrusted data. Set them
+/// with [`crate::ReadOptions::limits`].
+///
+/// A limit of `None` means no limit. By default, only the size of compressed
+/// data (such as a PNG iCCP chunk) after inflating it is limited, to 64 MiB,
+/// and the number of images listed in a JPEG MPF segment, to 256.
+///
+/// # Example
+///
+/// ```
+/// use pb_imgsize::{DecodingError, LimitKind, Limits, ReadOptions};
+///
+/// let limits = Limits::new().max_pixels(Some(100_000));
+/// let options = ReadOptions::new().limits(limits);
+/// let data = include_bytes!("buttercups.jpg");
+/// assert_eq!(
+///     pb_imgsize::read_bytes_with(&options, data),
+///     Err(DecodingError::LimitExceeded {
+///         kind: LimitKind::Pixels,
+///         value: 512 * 341,
+///         limit: 100_000,
+///     })
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Limits {
+    pub(crate) max_width: Option<u32>,
+    pub(crate) max_height: Option<u32>,
+    pub(crate) max_pixels: Option<u64>,
+    pub(crate) max_segments: Option<usize>,
+    pub(crate) max_comment_bytes: Option<usize>,
+    pub(crate) max_inflated_size: Option<usize>,
+    pub(crate) max_ifd_depth: Option<usize>,
+    pub(crate) max_mp_entries: Option<usize>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_width: None,
+            max_height: None,
+            max_pixels: None,
+            max_segments: None,
+            max_comment_bytes: None,
+            max_inflated_size: Some(64 << 20),
+            max_ifd_depth: None,
+            max_mp_entries: Some(256),
+        }
+    }
+}
+
+impl Limits {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The widest image to read.
+    pub fn max_width(mut self, max_width: Option<u32>) -> Self {
+        self.max_width = max_width;
+        self
+    }
+
+    /// The tallest image to read.
+    pub fn max_height(mut self, max_height: Option<u32>) -> Self {
+        self.max_height = max_height;
+        self
+    }
+
+    /// The most pixels (width times height) in the image. This rejects
+    /// decompression bombs before anything tries to decode them.
+    pub fn max_pixels(mut self, max_pixels: Option<u64>) -> Self {
+        self.max_pixels = max_pixels;
+        self
+    }
+
+    /// The most JPEG segments or PNG chunks to read. For a JPEG, the segments
+    /// of the further images listed in an MPF segment count too.
+    pub fn max_segments(mut self, max_segments: Option<usize>) -> Self {
+        self.max_segments = max_segments;
+        self
+    }
+
+    /// The most bytes of comments, in total.
+    pub fn max_comment_bytes(mut self, max_comment_bytes: Option<usize>) -> Self {
+        self.max_comment_bytes = max_comment_bytes;
+        self
+    }
+
+    /// The most bytes that any one piece of compressed data may inflate to.
+    /// This is 64 MiB by default.
+    pub fn max_inflated_size(mut self, max_inflated_size: Option<usize>) -> Self {
+        self.max_inflated_size = max_inflated_size;
+        self
+    }
+
+    /// How deep to go into the TIFF IFDs used by EXIF and MPF data. IFD0 is
+    /// at depth 1, and each IFD reached from another, through its next-IFD
+    /// offset or a pointer such as the Exif or GPS IFD, is one deeper. EXIF
+    /// data is only walked when it is asked for with [`crate::ReadOptions::exif`].
+    pub fn max_ifd_depth(mut self, max_ifd_depth: Option<usize>) -> Self {
+        self.max_ifd_depth = max_ifd_depth;
+        self
+    }
+
+    /// The most images that a JPEG MPF segment may list. Each one is another
+    /// JPEG whose segments are read for its dimensions. This is 256 by
+    /// default.
+    pub fn max_mp_entries(mut self, max_mp_entries: Option<usize>) -> Self {
+        self.max_mp_entries = max_mp_entries;
+        self
+    }
+}
+
+/// Which of the [`Limits`] was exceeded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum LimitKind {
+    Width,
+    Height,
+    Pixels,
+    Segments,
+    CommentBytes,
+    /// The `value` reported for this limit is only a lower bound, since
+    /// inflating stops as soon as it goes over the limit.
+    InflatedSize,
+    IfdDepth,
+    MpEntries,
+}
+
+impl Display for LimitKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            LimitKind::Width => "width",
+            LimitKind::Height => "height",
+            LimitKind::Pixels => "pixel count",
+            LimitKind::Segments => "segment count",
+            LimitKind::CommentBytes => "comment bytes",
+            LimitKind::InflatedSize => "inflated size",
+            LimitKind::IfdDepth => "IFD depth",
+            LimitKind::MpEntries => "MPF entry count",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A limit that was exceeded, which each format's error type converts from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct LimitExceeded {
+    pub kind: LimitKind,
+    pub value: u64,
+    pub limit: u64,
+}
+
+impl Limits {
+    /// Check the value against the limit of the given kind, if there is one.
+    pub(crate) fn check(&self, kind: LimitKind, value: u64) -> Result<(), LimitExceeded> {
+        let limit = match kind {
+            LimitKind::Width => self.max_width.map(u64::from),
+            LimitKind::Height => self.max_height.map(u64::from),
+            LimitKind::Pixels => self.max_pixels,
+            LimitKind::Segments => self.max_segments.map(|n| n as u64),
+            LimitKind::CommentBytes => self.max_comment_bytes.map(|n| n as u64),
+            LimitKind::InflatedSize => self.max_inflated_size.map(|n| n as u64),
+            LimitKind::IfdDepth => self.max_ifd_depth.map(|n| n as u64),
+            LimitKind::MpEntries => self.max_mp_entries.map(|n| n as u64),
+        };
+        match limit {
+            Some(limit) if value > limit => Err(LimitExceeded { kind, value, limit }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Check an image's width, height and pixel count.
+    pub(crate) fn check_dimensions(&self, width: u32, height: u32) -> Result<(), LimitExceeded> {
+        self.check(LimitKind::Width, width.into())?;
+        self.check(LimitKind::Height, height.into())?;
+        self.check(LimitKind::Pixels, u64::from(width) * u64::from(height))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let limits = Limits::new().max_width(Some(100)).max_pixels(Some(5000));
+        assert_eq!(limits.check_dimensions(100, 50), Ok(()));
+        assert_eq!(
+            limits.check_dimensions(101, 1),
+            Err(LimitExceeded {
+                kind: LimitKind::Width,
+                value: 101,
+                limit: 100
+            })
+        );
+        assert_eq!(
+            limits.check_dimensions(100, 51),
+            Err(LimitExceeded {
+                kind: LimitKind::Pixels,
+                value: 5100,
+                limit: 5000
+            })
+        );
+        assert_eq!(limits.check(LimitKind::Segments, u64::MAX), Ok(()));
+    }
+}
@@ -0,0 +1,179 @@
+//! Reading images by mapping their files into memory.
+
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use crate::{read_bytes_ref_with, Error, ImageMetadataRef, ReadOptions};
+
+#[cfg(unix)]
+mod guard;
+
+/// Files smaller than this are read into memory rather than mapped: mapping
+/// them saves little.
+const MAP_THRESHOLD: u64 = 64 * 1024;
+
+/// An image file mapped into memory, whose metadata can be read without
+/// copying anything out of the mapping.
+///
+/// Files under 64 KiB are read into memory instead of being mapped.
+///
+/// # Truncation
+///
+/// A file that is empty or too short gives an error when its metadata is
+/// read, as with [`crate::read_bytes`]. A file that shrinks after it was
+/// opened, even while it is being read, gives an
+/// [`io::ErrorKind::UnexpectedEof`] error from [`MappedImage::metadata`]
+/// rather than metadata that may not match it.
+///
+/// On most platforms, reading a mapped page past the end of a file that has
+/// shrunk raises `SIGBUS`, which would kill the process. On Unix, a `SIGBUS`
+/// handler is installed the first time a file is mapped: while the file is
+/// mapped, a fault inside it reads as zeros instead, and `SIGBUS` from
+/// anywhere else goes to the handler that was there before. The bytes
+/// returned by [`MappedImage::data`], and borrowed by the metadata, read as
+/// zeros past the new end too; call [`MappedImage::check_unchanged`] when you
+/// are done with them to make sure that they were real. On Windows, a file
+/// can't be truncated while it is mapped.
+///
+/// This requires the `mmap` feature.
+///
+/// # Examples
+///
+/// ```
+/// use pb_imgsize::{MappedImage, ReadOptions};
+///
+/// let image = MappedImage::open("src/watercolors.png")?;
+/// let metadata = image.metadata(&ReadOptions::default())?;
+/// assert_eq!(metadata.comments, vec![b"Abstract watercolors"]);
+/// // The comment is borrowed from the mapping.
+/// assert!(image.data().as_ptr_range().contains(&metadata.comments[0].as_ptr()));
+/// image.check_unchanged()?;
+/// # Ok::<(), pb_imgsize::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct MappedImage {
+    file: File,
+    data: Data,
+}
+
+#[derive(Debug)]
+enum Data {
+    /// A mapping, and the guard that turns its faults into zeros, which is
+    /// dropped first.
+    Mapped {
+        #[cfg(unix)]
+        _guard: guard::Guard,
+        map: memmap2::Mmap,
+    },
+    Read(Vec<u8>),
+}
+
+impl MappedImage {
+    /// Open a file, and map it into memory if it is large enough to be worth
+    /// it.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+        if len < MAP_THRESHOLD {
+            let data = Data::Read(read_all(&file)?);
+            return Ok(MappedImage { file, data });
+        }
+        // SAFETY: The mapping is read-only. If the file shrinks while it is
+        // mapped, reading past its new end faults, which the guard turns into
+        // zeros; check_unchanged then reports it.
+        let map = unsafe { memmap2::Mmap::map(&file)? };
+        #[cfg(unix)]
+        let data = match guard::Guard::new(&map) {
+            Some(guard) => Data::Mapped { _guard: guard, map },
+            // Without a guard, the file is read after all.
+            None => Data::Read(read_all(&file)?),
+        };
+        #[cfg(not(unix))]
+        let data = Data::Mapped { map };
+        Ok(MappedImage { file, data })
+    }
+
+    /// The contents of the file.
+    pub fn data(&self) -> &[u8] {
+        match &self.data {
+            Data::Mapped { map, .. } => map,
+            Data::Read(buf) => buf,
+        }
+    }
+
+    /// Read the image's metadata, borrowing from the mapping. If the file
+    /// has shrunk since it was opened, this returns an
+    /// [`io::ErrorKind::UnexpectedEof`] error instead.
+    pub fn metadata(&self, options: &ReadOptions) -> Result<ImageMetadataRef<'_>, Error> {
+        let metadata = read_bytes_ref_with(options, self.data());
+        self.check_unchanged()?;
+        Ok(metadata?)
+    }
+
+    /// Check that the file is still at least as long as it was when it was
+    /// mapped, returning an [`io::ErrorKind::UnexpectedEof`] error if not.
+    /// Call this when you are done with the borrowed metadata to make sure
+    /// that it still matches the file.
+    pub fn check_unchanged(&self) -> Result<(), Error> {
+        if self.file.metadata()?.len() < self.data().len() as u64 {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "file was truncated while being read",
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Read the whole file into memory.
+fn read_all(file: &File) -> io::Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity(file.metadata()?.len() as usize);
+    io::Read::read_to_end(&mut &*file, &mut buf)?;
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::StopAt;
+
+    #[test]
+    fn test_open() {
+        // The JPEG is small enough to read; the PNG is mapped.
+        let image = MappedImage::open("src/buttercups.jpg").unwrap();
+        assert!(matches!(image.data, Data::Read(_)));
+        let metadata = image.metadata(&ReadOptions::default()).unwrap();
+        assert_eq!((metadata.width, metadata.height), (512, 341));
+        let image = MappedImage::open("src/watercolors.png").unwrap();
+        assert!(matches!(image.data, Data::Mapped { .. }));
+        assert_eq!(image.data(), std::fs::read("src/watercolors.png").unwrap());
+    }
+
+    #[test]
+    fn test_check_unchanged() {
+        let dir = std::env::temp_dir().join(format!("imgsize-mapped-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("image.png");
+        std::fs::copy("src/watercolors.png", &path).unwrap();
+        let image = MappedImage::open(&path).unwrap();
+        image.check_unchanged().unwrap();
+
+        // Shrink the file while it is mapped. Reading the pages past its new
+        // end gives zeros rather than a crash, and the metadata gives an
+        // error.
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(100)
+            .unwrap();
+        let err = image.check_unchanged().unwrap_err();
+        assert!(matches!(err, Error::Io(e) if e.kind() == io::ErrorKind::UnexpectedEof));
+        let err = image.metadata(&ReadOptions::new().stop_at(StopAt::End));
+        assert!(matches!(err, Err(Error::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof));
+        assert!(image.data()[100_000..].iter().all(|&b| b == 0));
+        drop(image);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
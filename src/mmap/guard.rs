@@ -0,0 +1,157 @@
+//! Keeping a mapped file that shrinks from crashing the process.
+//!
+//! Reading a page of a mapped file that lies past the end of the file raises
+//! `SIGBUS`, which kills the process by default. While a [`Guard`] is alive,
+//! a `SIGBUS` handler covers its mapping: a fault inside it maps a page of
+//! zeros over the page that faulted, and the read carries on. The zeros are
+//! never taken for the file's contents, because the file's length is checked
+//! after reading it (see [`super::MappedImage::check_unchanged`]). Faults
+//! anywhere else go to whatever handled `SIGBUS` before.
+
+use std::ffi::{c_int, c_void};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+
+/// How many mappings can be guarded at once. A file opened while they are
+/// all in use is read into memory instead.
+const SLOTS: usize = 64;
+
+/// The address range of a guarded mapping, or zeros if the slot is free.
+struct Slot {
+    start: AtomicUsize,
+    end: AtomicUsize,
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const FREE: Slot = Slot {
+    start: AtomicUsize::new(0),
+    end: AtomicUsize::new(0),
+};
+
+static GUARDED: [Slot; SLOTS] = [FREE; SLOTS];
+
+static PAGE_SIZE: AtomicUsize = AtomicUsize::new(0);
+
+/// The `SIGBUS` action before ours was installed, or `None` if ours couldn't
+/// be.
+static PREVIOUS: OnceLock<Option<libc::sigaction>> = OnceLock::new();
+
+/// A `SIGBUS` handler that takes a `siginfo_t`.
+type Handler = extern "C" fn(c_int, *mut libc::siginfo_t, *mut c_void);
+
+/// A mapping covered by the `SIGBUS` handler until this is dropped.
+#[derive(Debug)]
+pub(super) struct Guard {
+    slot: usize,
+}
+
+impl Guard {
+    /// Guard the mapped data, or return `None` if the handler can't be
+    /// installed or too many mappings are guarded already.
+    pub(super) fn new(data: &[u8]) -> Option<Guard> {
+        install()?;
+        let start = data.as_ptr() as usize;
+        let end = start + data.len();
+        for (index, slot) in GUARDED.iter().enumerate() {
+            let claimed = slot
+                .start
+                .compare_exchange(0, start, Ordering::SeqCst, Ordering::SeqCst);
+            if claimed.is_ok() {
+                slot.end.store(end, Ordering::SeqCst);
+                return Some(Guard { slot: index });
+            }
+        }
+        None
+    }
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        let slot = &GUARDED[self.slot];
+        slot.end.store(0, Ordering::SeqCst);
+        slot.start.store(0, Ordering::SeqCst);
+    }
+}
+
+/// Install the `SIGBUS` handler, if it isn't already. Returns `None` if it
+/// can't be.
+fn install() -> Option<()> {
+    let previous = PREVIOUS.get_or_init(|| {
+        // SAFETY: sysconf has no preconditions, and the sigaction structs
+        // are plain data that the zeroed values are valid for.
+        unsafe {
+            let page_size = libc::sysconf(libc::_SC_PAGESIZE);
+            if page_size <= 0 {
+                return None;
+            }
+            PAGE_SIZE.store(page_size as usize, Ordering::SeqCst);
+            let mut action: libc::sigaction = std::mem::zeroed();
+            let handler: Handler = handle_sigbus;
+            action.sa_sigaction = handler as usize;
+            action.sa_flags = libc::SA_SIGINFO | libc::SA_ONSTACK;
+            libc::sigemptyset(&mut action.sa_mask);
+            let mut previous: libc::sigaction = std::mem::zeroed();
+            if libc::sigaction(libc::SIGBUS, &action, &mut previous) != 0 {
+                return None;
+            }
+            Some(previous)
+        }
+    });
+    previous.map(|_| ())
+}
+
+extern "C" fn handle_sigbus(signal: c_int, info: *mut libc::siginfo_t, context: *mut c_void) {
+    // SAFETY: the kernel passes a valid siginfo_t to an SA_SIGINFO handler.
+    let address = unsafe { (*info).si_addr() } as usize;
+    let guarded = GUARDED.iter().any(|slot| {
+        let start = slot.start.load(Ordering::SeqCst);
+        start != 0 && start <= address && address < slot.end.load(Ordering::SeqCst)
+    });
+    if guarded {
+        let page_size = PAGE_SIZE.load(Ordering::SeqCst);
+        let page = address & !(page_size - 1);
+        // SAFETY: the page is part of a guarded mapping, which only this
+        // crate reads from and which is unmapped as a whole when it is
+        // dropped, so replacing it changes nothing else.
+        let mapped = unsafe {
+            libc::mmap(
+                page as *mut c_void,
+                page_size,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_FIXED,
+                -1,
+                0,
+            )
+        };
+        if mapped != libc::MAP_FAILED {
+            return;
+        }
+    }
+    // SAFETY: the arguments are the ones this handler was called with.
+    unsafe { forward(signal, info, context) }
+}
+
+/// Pass a signal that isn't ours to the handler that was there before.
+unsafe fn forward(signal: c_int, info: *mut libc::siginfo_t, context: *mut c_void) {
+    match PREVIOUS.get() {
+        Some(Some(previous))
+            if previous.sa_sigaction != libc::SIG_DFL && previous.sa_sigaction != libc::SIG_IGN =>
+        {
+            if previous.sa_flags & libc::SA_SIGINFO != 0 {
+                let handler: Handler = std::mem::transmute(previous.sa_sigaction);
+                handler(signal, info, context);
+            } else {
+                let handler: extern "C" fn(c_int) = std::mem::transmute(previous.sa_sigaction);
+                handler(signal);
+            }
+        }
+        _ => {
+            // Go back to the default action. Returning runs the read that
+            // faulted again, which then ends the process as it would have
+            // without us.
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = libc::SIG_DFL;
+            libc::sigaction(libc::SIGBUS, &action, std::ptr::null_mut());
+        }
+    }
+}
@@ -0,0 +1,178 @@
+//! Options controlling what is read from an image.
+
+use std::fmt::{self, Display};
+
+use crate::jpeg::JpegOptions;
+use crate::{Limits, Strictness};
+
+/// An image format that this crate can read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ImageFormat {
+    Jpeg,
+    Png,
+}
+
+impl Display for ImageFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImageFormat::Jpeg => write!(f, "JPEG"),
+            ImageFormat::Png => write!(f, "PNG"),
+        }
+    }
+}
+
+/// How far into an image to read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum StopAt {
+    /// Stop as soon as the dimensions are known: after the SOF segment of a
+    /// JPEG, or the IHDR chunk of a PNG. Anything that comes before them is
+    /// still read.
+    Dimensions,
+    /// Read all the metadata, up to the first SOS segment of a JPEG or the
+    /// IEND chunk of a PNG.
+    #[default]
+    Metadata,
+    /// Read to the end of the image, skipping through the scan data of a
+    /// JPEG to its EOI marker, to find out whether the data was cut short or
+    /// carries on past the end. A PNG is read through its IEND chunk either
+    /// way, so this is the same as `Metadata` for PNG.
+    End,
+}
+
+/// Options controlling what is read from an image, for [`crate::read_bytes_with`]
+/// and [`crate::read_file_with`].
+///
+/// The defaults match [`crate::read_bytes`]: comments are collected, PNG CRCs
+/// are verified, both formats are allowed, and all the metadata is read. The
+/// EXIF, XMP and ICC payloads are only extracted if asked for.
+///
+/// # Example
+///
+/// ```
+/// use pb_imgsize::{ImageFormat, ReadOptions, StopAt};
+///
+/// let options = ReadOptions::new()
+///     .comments(false)
+///     .formats(&[ImageFormat::Jpeg])
+///     .stop_at(StopAt::Dimensions);
+/// let data = include_bytes!("buttercups.jpg");
+/// let metadata = pb_imgsize::read_bytes_with(&options, data).unwrap();
+/// assert_eq!((metadata.width, metadata.height), (512, 341));
+/// assert!(metadata.comments.is_empty());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReadOptions {
+    pub(crate) comments: bool,
+    pub(crate) verify_crc: bool,
+    pub(crate) exif: bool,
+    pub(crate) xmp: bool,
+    pub(crate) icc_profile: bool,
+    pub(crate) formats: Vec<ImageFormat>,
+    pub(crate) stop_at: StopAt,
+    pub(crate) limits: Limits,
+    pub(crate) strictness: Strictness,
+    pub(crate) jpeg: JpegOptions,
+}
+
+impl Default for ReadOptions {
+    fn default() -> Self {
+        ReadOptions {
+            comments: true,
+            verify_crc: true,
+            exif: false,
+            xmp: false,
+            icc_profile: false,
+            formats: vec![ImageFormat::Jpeg, ImageFormat::Png],
+            stop_at: StopAt::Metadata,
+            limits: Limits::default(),
+            strictness: Strictness::default(),
+            jpeg: JpegOptions::default(),
+        }
+    }
+}
+
+impl ReadOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Options for reading just the dimensions, as quickly as possible.
+    pub fn dimensions_only() -> Self {
+        Self::new().comments(false).stop_at(StopAt::Dimensions)
+    }
+
+    /// Whether to collect comments from JPEG COM segments and PNG tEXt
+    /// chunks.
+    pub fn comments(mut self, comments: bool) -> Self {
+        self.comments = comments;
+        self
+    }
+
+    /// Whether to verify the CRC of each PNG chunk. The chunk lengths are
+    /// checked either way.
+    pub fn verify_crc(mut self, verify_crc: bool) -> Self {
+        self.verify_crc = verify_crc;
+        self
+    }
+
+    /// Whether to extract the EXIF data, from a JPEG APP1 segment or a PNG
+    /// eXIf chunk.
+    pub fn exif(mut self, exif: bool) -> Self {
+        self.exif = exif;
+        self
+    }
+
+    /// Whether to extract the XMP packet, from a JPEG APP1 segment or a PNG
+    /// iTXt chunk. This also looks for an Ultra HDR gain map among the
+    /// images listed in a JPEG's MPF segment.
+    pub fn xmp(mut self, xmp: bool) -> Self {
+        self.xmp = xmp;
+        self
+    }
+
+    /// Whether to extract the ICC profile, from JPEG APP2 segments or a PNG
+    /// iCCP chunk.
+    pub fn icc_profile(mut self, icc_profile: bool) -> Self {
+        self.icc_profile = icc_profile;
+        self
+    }
+
+    /// The image formats to accept. Data in any other format returns
+    /// [`crate::DecodingError::FormatNotAllowed`].
+    pub fn formats(mut self, formats: &[ImageFormat]) -> Self {
+        self.formats = formats.to_vec();
+        self
+    }
+
+    /// How far into the image to read.
+    pub fn stop_at(mut self, stop_at: StopAt) -> Self {
+        self.stop_at = stop_at;
+        self
+    }
+
+    /// Limits on what the image may contain. Exceeding one returns
+    /// [`crate::DecodingError::LimitExceeded`].
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// How strictly to follow the specifications. Problems that are
+    /// tolerated are listed in the metadata's `warnings`.
+    pub fn strictness(mut self, strictness: Strictness) -> Self {
+        self.strictness = strictness;
+        self
+    }
+
+    /// See [`JpegOptions::scan_for_dnl`].
+    pub fn scan_for_dnl(mut self, scan_for_dnl: bool) -> Self {
+        self.jpeg.scan_for_dnl = scan_for_dnl;
+        self
+    }
+
+    pub(crate) fn allows(&self, format: ImageFormat) -> bool {
+        self.formats.contains(&format)
+    }
+}
@@ -1,61 +1,566 @@
+//! Reading metadata from PNG images.
+
+mod chunks;
+mod color;
+mod embedded;
+mod hdr;
+mod palette;
+mod strip;
+mod validate;
+mod write;
+
+use std::borrow::Cow;
 use std::fmt::Display;
 
-use super::ImageMetadata;
+pub use chunks::{Chunk, Chunks};
+pub use color::{Background, Chromaticities, ColorType, RenderingIntent, SignificantBits};
+pub use hdr::{Cicp, ContentLightLevel, MasteringDisplay};
+pub use palette::Transparency;
+pub(crate) use strip::strip_png;
+pub(crate) use validate::validate_png;
+pub(crate) use write::{set_png_orientation, write_png_comments};
+
+use super::limits::LimitExceeded;
+use super::warnings::{Diagnostics, WarningKind};
+use super::{
+    ErrorKind, Flow, ImageMetadata, ImageMetadataRef, LimitKind, MetadataVisitor, ReadOptions,
+    StopAt, Strictness,
+};
 
 /// An error that occurred while decoding a PNG image.
+///
+/// Offsets are from the start of the data. A chunk's offset is where its
+/// length field starts.
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum PngDecodingError {
+    /// There is no IHDR chunk, which must come first, at offset 8.
     MissingIHDR,
-    InvalidIHDRLength(u32),
-    InvalidChunkCrc,
+    InvalidIHDRLength {
+        offset: usize,
+        length: u32,
+    },
+    /// The CRC stored in the chunk (`expected`) doesn't match the one
+    /// calculated from its contents (`actual`).
+    InvalidChunkCrc {
+        kind: [u8; 4],
+        offset: usize,
+        expected: u32,
+        actual: u32,
+    },
+    /// The chunk's length runs past the end of the data, which only has
+    /// `available` bytes after the chunk's type.
+    TruncatedChunk {
+        kind: [u8; 4],
+        offset: usize,
+        length: u32,
+        available: usize,
+    },
+    ChunkOutOfOrder {
+        kind: [u8; 4],
+        offset: usize,
+    },
+    DuplicateChunk {
+        kind: [u8; 4],
+        offset: usize,
+    },
+    InvalidPaletteLength {
+        offset: usize,
+        length: u32,
+    },
+    PaletteTooLarge {
+        offset: usize,
+        entries: u32,
+        bit_depth: u8,
+    },
+    MalformedChunk {
+        kind: [u8; 4],
+        offset: usize,
+    },
+    /// The data ends at `offset` without an IEND chunk.
+    MissingIEND {
+        offset: usize,
+    },
+    /// The IEND chunk at `offset` comes before any IDAT chunks.
+    MissingImageData {
+        offset: usize,
+    },
+    /// The IDAT chunk at `offset` is separated from the ones before it.
+    NonContiguousImageData {
+        offset: usize,
+    },
+    /// The image data is not a valid zlib stream, as found at the chunk at
+    /// `offset`.
+    InvalidImageData {
+        offset: usize,
+    },
+    /// The image data inflates to `actual` bytes, rather than the `expected`
+    /// number for the IHDR chunk's dimensions. If there is too much, `actual`
+    /// is where inflating stopped.
+    ImageDataSizeMismatch {
+        expected: u64,
+        actual: u64,
+    },
+    InvalidBitDepth {
+        color_type: u8,
+        bit_depth: u8,
+    },
+    /// A comment to be written is too long to fit in a tEXt chunk.
+    CommentTooLong {
+        length: usize,
+    },
+    LimitExceeded {
+        kind: LimitKind,
+        value: u64,
+        limit: u64,
+    },
+}
+
+impl PngDecodingError {
+    /// What kind of error this is.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            PngDecodingError::MissingIHDR | PngDecodingError::MissingImageData { .. } => {
+                ErrorKind::Missing
+            }
+            PngDecodingError::InvalidChunkCrc { .. } => ErrorKind::ChecksumMismatch,
+            PngDecodingError::TruncatedChunk { .. } | PngDecodingError::MissingIEND { .. } => {
+                ErrorKind::Truncated
+            }
+            PngDecodingError::ChunkOutOfOrder { .. }
+            | PngDecodingError::DuplicateChunk { .. }
+            | PngDecodingError::NonContiguousImageData { .. } => ErrorKind::OutOfOrder,
+            PngDecodingError::InvalidIHDRLength { .. }
+            | PngDecodingError::InvalidPaletteLength { .. }
+            | PngDecodingError::PaletteTooLarge { .. }
+            | PngDecodingError::MalformedChunk { .. }
+            | PngDecodingError::InvalidImageData { .. }
+            | PngDecodingError::ImageDataSizeMismatch { .. }
+            | PngDecodingError::InvalidBitDepth { .. }
+            | PngDecodingError::CommentTooLong { .. } => ErrorKind::InvalidData,
+            PngDecodingError::LimitExceeded { .. } => ErrorKind::LimitExceeded,
+        }
+    }
+
+    /// Where in the data the error is, if it is anywhere in particular.
+    pub fn offset(&self) -> Option<usize> {
+        match *self {
+            PngDecodingError::MissingIHDR | PngDecodingError::InvalidBitDepth { .. } => Some(8),
+            PngDecodingError::InvalidIHDRLength { offset, .. }
+            | PngDecodingError::InvalidChunkCrc { offset, .. }
+            | PngDecodingError::TruncatedChunk { offset, .. }
+            | PngDecodingError::ChunkOutOfOrder { offset, .. }
+            | PngDecodingError::DuplicateChunk { offset, .. }
+            | PngDecodingError::InvalidPaletteLength { offset, .. }
+            | PngDecodingError::PaletteTooLarge { offset, .. }
+            | PngDecodingError::MalformedChunk { offset, .. }
+            | PngDecodingError::MissingIEND { offset }
+            | PngDecodingError::MissingImageData { offset }
+            | PngDecodingError::NonContiguousImageData { offset }
+            | PngDecodingError::InvalidImageData { offset } => Some(offset),
+            PngDecodingError::ImageDataSizeMismatch { .. }
+            | PngDecodingError::CommentTooLong { .. }
+            | PngDecodingError::LimitExceeded { .. } => None,
+        }
+    }
 }
 
 impl Display for PngDecodingError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = |kind: &[u8; 4]| String::from_utf8_lossy(kind).into_owned();
         match &self {
             PngDecodingError::MissingIHDR => write!(f, "IHDR chunk missing from PNG"),
-            PngDecodingError::InvalidIHDRLength(len) => {
-                write!(f, "Invalid IHDR chunk length: {}", len)
+            PngDecodingError::InvalidIHDRLength { offset, length } => {
+                write!(
+                    f,
+                    "Invalid IHDR chunk length {} at offset {}",
+                    length, offset
+                )
+            }
+            PngDecodingError::InvalidChunkCrc {
+                kind,
+                offset,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Invalid CRC in {} chunk at offset {}: expected 0x{:08x}, got 0x{:08x}",
+                name(kind),
+                offset,
+                expected,
+                actual
+            ),
+            PngDecodingError::TruncatedChunk {
+                kind,
+                offset,
+                length,
+                available,
+            } => write!(
+                f,
+                "{} chunk at offset {} is cut short: it needs {} bytes of data and CRC, \
+                 but only {} follow",
+                name(kind),
+                offset,
+                u64::from(*length) + 4,
+                available
+            ),
+            PngDecodingError::ChunkOutOfOrder { kind, offset } => {
+                write!(f, "{} chunk out of order at offset {}", name(kind), offset)
+            }
+            PngDecodingError::DuplicateChunk { kind, offset } => {
+                write!(f, "Duplicate {} chunk at offset {}", name(kind), offset)
+            }
+            PngDecodingError::InvalidPaletteLength { offset, length } => {
+                write!(
+                    f,
+                    "Invalid PLTE chunk length {} at offset {}",
+                    length, offset
+                )
+            }
+            PngDecodingError::PaletteTooLarge {
+                offset,
+                entries,
+                bit_depth,
+            } => write!(
+                f,
+                "Palette at offset {} has {} entries, too many for bit depth {}",
+                offset, entries, bit_depth
+            ),
+            PngDecodingError::MalformedChunk { kind, offset } => {
+                write!(f, "Malformed {} chunk at offset {}", name(kind), offset)
+            }
+            PngDecodingError::MissingIEND { offset } => {
+                write!(
+                    f,
+                    "IEND chunk missing from PNG, which ends at offset {}",
+                    offset
+                )
+            }
+            PngDecodingError::MissingImageData { offset } => {
+                write!(f, "No IDAT chunks before IEND chunk at offset {}", offset)
+            }
+            PngDecodingError::NonContiguousImageData { offset } => write!(
+                f,
+                "IDAT chunk at offset {} is not contiguous with the others",
+                offset
+            ),
+            PngDecodingError::InvalidImageData { offset } => write!(
+                f,
+                "Invalid zlib stream in IDAT chunks, at chunk at offset {}",
+                offset
+            ),
+            PngDecodingError::ImageDataSizeMismatch { expected, actual } => write!(
+                f,
+                "Image data inflates to {} bytes, but the header calls for {}",
+                actual, expected
+            ),
+            PngDecodingError::InvalidBitDepth {
+                color_type,
+                bit_depth,
+            } => write!(
+                f,
+                "Invalid bit depth {} for color type {}",
+                bit_depth, color_type
+            ),
+            PngDecodingError::CommentTooLong { length } => write!(
+                f,
+                "Comment of {} bytes is too long for a tEXt chunk",
+                length
+            ),
+            PngDecodingError::LimitExceeded { kind, value, limit } => {
+                write!(
+                    f,
+                    "Image {} of {} exceeds the limit of {}",
+                    kind, value, limit
+                )
             }
-            PngDecodingError::InvalidChunkCrc => write!(f, "Invalid chunk CRC"),
         }
     }
 }
 
 impl std::error::Error for PngDecodingError {}
 
+impl From<LimitExceeded> for PngDecodingError {
+    fn from(e: LimitExceeded) -> Self {
+        PngDecodingError::LimitExceeded {
+            kind: e.kind,
+            value: e.value,
+            limit: e.limit,
+        }
+    }
+}
+
+/// PNG-specific metadata.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct PngMetadata {
+    /// The bit depth from the IHDR chunk.
+    pub bit_depth: u8,
+    /// The color type from the IHDR chunk.
+    pub color_type: ColorType,
+    /// The gAMA chunk's gamma value, multiplied by 100000.
+    pub gamma: Option<u32>,
+    /// The cHRM chunk, if present.
+    pub chromaticities: Option<Chromaticities>,
+    /// The rendering intent from the sRGB chunk, if present. An sRGB chunk
+    /// means the image is in the sRGB color space.
+    pub srgb: Option<RenderingIntent>,
+    /// The sBIT chunk, if present.
+    pub significant_bits: Option<SignificantBits>,
+    /// The bKGD chunk, if present.
+    pub background: Option<Background>,
+    /// The palette entries from the PLTE chunk, if present.
+    pub palette: Option<Vec<[u8; 3]>>,
+    /// The tRNS chunk, if present.
+    pub transparency: Option<Transparency>,
+    /// The cICP (coding-independent code points) chunk, if present.
+    pub cicp: Option<Cicp>,
+    /// The mDCv (mastering display color volume) chunk, if present.
+    pub mastering_display: Option<MasteringDisplay>,
+    /// The cLLI (content light level information) chunk, if present.
+    pub content_light_level: Option<ContentLightLevel>,
+}
+
+impl PngMetadata {
+    fn new(bit_depth: u8, color_type: ColorType) -> Self {
+        PngMetadata {
+            bit_depth,
+            color_type,
+            gamma: None,
+            chromaticities: None,
+            srgb: None,
+            significant_bits: None,
+            background: None,
+            palette: None,
+            transparency: None,
+            cicp: None,
+            mastering_display: None,
+            content_light_level: None,
+        }
+    }
+
+    /// The gAMA chunk's gamma value, if present.
+    pub fn gamma_value(&self) -> Option<f64> {
+        self.gamma.map(|gamma| f64::from(gamma) / 100000.0)
+    }
+
+    /// The palette entries with their alpha values from the tRNS chunk, if
+    /// there is a palette. Entries without an alpha value are opaque.
+    pub fn palette_rgba(&self) -> Option<Vec<[u8; 4]>> {
+        let alpha: &[u8] = match &self.transparency {
+            Some(Transparency::PaletteAlpha(alpha)) => alpha,
+            _ => &[],
+        };
+        let palette = self.palette.as_ref()?;
+        let rgba = palette.iter().enumerate().map(|(i, &[r, g, b])| {
+            let a = alpha.get(i).copied().unwrap_or(255);
+            [r, g, b, a]
+        });
+        Some(rgba.collect())
+    }
+
+    /// Decode a chunk other than IHDR, if it's one we know about. Returns
+    /// false if it's one we know about but can't decode.
+    fn read_chunk(&mut self, chunk: &Chunk) -> Result<bool, PngDecodingError> {
+        let data = chunk.data;
+        let decoded = match &chunk.kind {
+            // PLTE: Palette
+            b"PLTE" => {
+                // An invalid palette leaves none at all.
+                self.palette = None;
+                let palette =
+                    palette::read_plte(data, chunk.offset, self.bit_depth, self.color_type)?;
+                self.palette = Some(palette);
+                true
+            }
+            // tRNS: Transparency
+            b"tRNS" => {
+                self.transparency = palette::read_trns(data, self.color_type);
+                self.transparency.is_some()
+            }
+            // gAMA: Image gamma
+            b"gAMA" => {
+                self.gamma = color::read_gama(data);
+                self.gamma.is_some()
+            }
+            // cHRM: Primary chromaticities and white point
+            b"cHRM" => {
+                self.chromaticities = color::read_chrm(data);
+                self.chromaticities.is_some()
+            }
+            // sRGB: Standard RGB color space
+            b"sRGB" => {
+                self.srgb = color::read_srgb(data);
+                self.srgb.is_some()
+            }
+            // sBIT: Significant bits
+            b"sBIT" => {
+                self.significant_bits = color::read_sbit(data, self.color_type);
+                self.significant_bits.is_some()
+            }
+            // bKGD: Background color
+            b"bKGD" => {
+                self.background = color::read_bkgd(data, self.color_type);
+                self.background.is_some()
+            }
+            // cICP: Coding-independent code points
+            b"cICP" => {
+                self.cicp = hdr::read_cicp(data);
+                self.cicp.is_some()
+            }
+            // mDCv: Mastering Display Color Volume
+            b"mDCv" => {
+                self.mastering_display = hdr::read_mdcv(data);
+                self.mastering_display.is_some()
+            }
+            // cLLI: Content Light Level Information
+            b"cLLI" => {
+                self.content_light_level = hdr::read_clli(data);
+                self.content_light_level.is_some()
+            }
+            // Ignore other chunks
+            _ => true,
+        };
+        Ok(decoded)
+    }
+}
+
+/// Chunks that must come before PLTE and IDAT.
+const BEFORE_PLTE: &[&[u8; 4]] = &[b"cHRM", b"cICP", b"gAMA", b"iCCP", b"sBIT", b"sRGB"];
+/// Chunks that must come after PLTE, if there is one, and before IDAT.
+const AFTER_PLTE: &[&[u8; 4]] = &[b"bKGD", b"hIST", b"tRNS"];
+/// Other chunks that must come before IDAT.
+const BEFORE_IDAT: &[&[u8; 4]] = &[b"cLLI", b"mDCv", b"pHYs"];
+
+/// Checks the placement rules for the chunks that have them.
+#[derive(Default)]
+struct ChunkOrder {
+    seen: Vec<[u8; 4]>,
+    plte: bool,
+    idat: bool,
+}
+
+impl ChunkOrder {
+    fn check(&mut self, chunk_type: &[u8; 4], offset: usize) -> Result<(), PngDecodingError> {
+        let before_plte = BEFORE_PLTE.contains(&chunk_type);
+        let after_plte = AFTER_PLTE.contains(&chunk_type);
+        let before_idat = before_plte || after_plte || BEFORE_IDAT.contains(&chunk_type);
+
+        let out_of_order = (before_plte && self.plte)
+            || (before_idat && self.idat)
+            || (chunk_type == b"PLTE" && self.seen.iter().any(|c| AFTER_PLTE.contains(&c)));
+        if out_of_order {
+            return Err(PngDecodingError::ChunkOutOfOrder {
+                kind: *chunk_type,
+                offset,
+            });
+        }
+
+        if before_idat || chunk_type == b"PLTE" {
+            if self.seen.contains(chunk_type) {
+                return Err(PngDecodingError::DuplicateChunk {
+                    kind: *chunk_type,
+                    offset,
+                });
+            }
+            self.seen.push(*chunk_type);
+        }
+        self.plte |= chunk_type == b"PLTE";
+        self.idat |= chunk_type == b"IDAT";
+        Ok(())
+    }
+}
+
 /// Read PNG data, and return its dimensions and any comments found.
 pub fn read_png_data<T: AsRef<[u8]>>(buf: T) -> Result<ImageMetadata, PngDecodingError> {
-    let buf = buf.as_ref();
-    let mut comments: Vec<Vec<u8>> = Vec::new();
+    read_png_data_with_visitor(buf, &mut ())
+}
+
+/// Read PNG data, calling the visitor for each chunk, and return its
+/// dimensions and any comments found.
+pub fn read_png_data_with_visitor<T: AsRef<[u8]>>(
+    buf: T,
+    visitor: &mut impl MetadataVisitor,
+) -> Result<ImageMetadata, PngDecodingError> {
+    read_png_data_ref(buf.as_ref(), &ReadOptions::default(), visitor).map(ImageMetadata::from)
+}
+
+/// Read PNG data, returning metadata that borrows from it.
+pub(crate) fn read_png_data_ref<'a>(
+    buf: &'a [u8],
+    options: &ReadOptions,
+    visitor: &mut impl MetadataVisitor,
+) -> Result<ImageMetadataRef<'a>, PngDecodingError> {
+    let mut comments: Vec<&[u8]> = Vec::new();
     let mut dimensions: Option<(u32, u32)> = None;
+    let mut png: Option<PngMetadata> = None;
+    let mut exif: Option<&[u8]> = None;
+    let mut xmp: Option<Cow<[u8]>> = None;
+    let mut icc_profile: Option<Cow<[u8]>> = None;
+    let mut order = ChunkOrder::default();
+    let limits = &options.limits;
+    let mut chunk_count = 0;
+    let mut comment_bytes = 0;
+    let mut diagnostics = Diagnostics::new(options.strictness);
 
-    let mut pos = 8;
-    while pos + 12 < buf.len() {
-        let chunk_length = u32::from_be_bytes([buf[pos], buf[pos + 1], buf[pos + 2], buf[pos + 3]]);
-        pos += 4;
-        let chunk_type = [buf[pos], buf[pos + 1], buf[pos + 2], buf[pos + 3]];
-        pos += 4;
-        if pos + chunk_length as usize + 4 > buf.len() {
-            return Err(PngDecodingError::InvalidChunkCrc);
-        }
-        let chunk_data = &buf[pos..][..chunk_length as usize];
-        pos += chunk_length as usize;
-        let chunk_crc = u32::from_be_bytes([buf[pos], buf[pos + 1], buf[pos + 2], buf[pos + 3]]);
-        pos += 4;
+    // A bad CRC is only a warning in lenient mode, so check CRCs here rather
+    // than leaving it to the iterator.
+    let lenient = options.strictness == Strictness::Lenient;
+    let mut chunks = Chunks::new(buf).verify_crc(options.verify_crc && !lenient);
+    // Where the IEND chunk ends, once we've found it.
+    let mut end = None;
+    let mut stopped = false;
 
-        let mut crc = crc32fast::Hasher::new();
-        crc.update(&chunk_type);
-        crc.update(chunk_data);
-        if crc.finalize() != chunk_crc {
-            return Err(PngDecodingError::InvalidChunkCrc);
+    loop {
+        let chunk = match chunks.next() {
+            Some(Ok(chunk)) => chunk,
+            // The data was cut short partway through this chunk. That's only
+            // an error if it's before we have the dimensions.
+            Some(Err(PngDecodingError::TruncatedChunk { .. })) if dimensions.is_some() => break,
+            Some(Err(error)) => return Err(error),
+            None => break,
+        };
+        chunk_count += 1;
+        limits.check(LimitKind::Segments, chunk_count)?;
+        let chunk_type = chunk.kind;
+        let chunk_data = chunk.data;
+        let chunk_length = chunk_data.len() as u32;
+        let offset = chunk.offset;
+
+        if lenient && options.verify_crc && !chunk.crc_matches() {
+            let error = chunk.crc_error();
+            diagnostics.report(error, WarningKind::InvalidCrc, offset, Strictness::Lenient)?;
+        }
+        if png.is_none() && &chunk_type != b"IHDR" {
+            // IHDR must come first. Chunks before it are ignored.
+            let error = PngDecodingError::ChunkOutOfOrder {
+                kind: chunk_type,
+                offset,
+            };
+            diagnostics.report(
+                error,
+                WarningKind::ChunkOutOfOrder,
+                offset,
+                Strictness::Normal,
+            )?;
         }
+        if let Err(error) = order.check(&chunk_type, offset) {
+            let kind = match error {
+                PngDecodingError::DuplicateChunk { .. } => WarningKind::DuplicateChunk,
+                _ => WarningKind::ChunkOutOfOrder,
+            };
+            diagnostics.report(error, kind, offset, Strictness::Normal)?;
+        }
+        let flow = visitor.visit_png_chunk(&chunk);
 
         match &chunk_type {
             // IHDR: Image Header
             b"IHDR" => {
                 if chunk_length != 13 {
-                    return Err(PngDecodingError::InvalidIHDRLength(chunk_length));
+                    return Err(PngDecodingError::InvalidIHDRLength {
+                        offset,
+                        length: chunk_length,
+                    });
                 }
                 let width = u32::from_be_bytes([
                     chunk_data[0],
@@ -69,39 +574,106 @@ pub fn read_png_data<T: AsRef<[u8]>>(buf: T) -> Result<ImageMetadata, PngDecodin
                     chunk_data[6],
                     chunk_data[7],
                 ]);
+                limits.check_dimensions(width, height)?;
                 dimensions = Some((width, height));
+                let color_type = ColorType::from(chunk_data[9]);
+                png.get_or_insert_with(|| PngMetadata::new(chunk_data[8], color_type));
             }
             // tEXt: Textual Data
-            b"tEXt" => {
-                let mut parts = chunk_data.splitn(2, |&b| b == 0);
-                let keyword = parts.next().unwrap();
-                let text = parts.next().unwrap();
-                if keyword == b"comment" {
-                    comments.push(text.to_vec());
+            b"tEXt" => match memchr::memchr(0, chunk_data) {
+                Some(nul) => {
+                    let (keyword, text) = (&chunk_data[..nul], &chunk_data[nul + 1..]);
+                    if keyword == b"comment" && options.comments {
+                        comment_bytes += text.len() as u64;
+                        limits.check(LimitKind::CommentBytes, comment_bytes)?;
+                        comments.push(text);
+                    }
+                }
+                None => {
+                    // There's no NUL to end the keyword.
+                    let error = PngDecodingError::MalformedChunk {
+                        kind: chunk_type,
+                        offset,
+                    };
+                    let kind = WarningKind::MalformedChunk;
+                    diagnostics.report(error, kind, offset, Strictness::Normal)?;
+                }
+            },
+            // eXIf: EXIF data
+            b"eXIf" if options.exif => {
+                if exif.is_none() {
+                    crate::exif::check_ifd_depth(chunk_data, limits)?;
                 }
+                exif.get_or_insert(chunk_data);
+            }
+            // iTXt: International Textual Data, which might be an XMP packet
+            b"iTXt" if options.xmp && xmp.is_none() => {
+                xmp = embedded::read_xmp(chunk_data, limits)?;
+            }
+            // iCCP: Embedded ICC Profile
+            b"iCCP" if options.icc_profile => {
+                icc_profile = embedded::read_iccp(chunk_data, limits)?.map(Cow::Owned);
             }
             // IEND: Image Trailer
             b"IEND" => {
+                end = Some(chunks.position());
                 break;
             }
             _ => {
-                // Ignore other chunks
+                if let Some(png) = png.as_mut() {
+                    match png.read_chunk(&chunk) {
+                        Ok(true) => {}
+                        Ok(false) => {
+                            let error = PngDecodingError::MalformedChunk {
+                                kind: chunk_type,
+                                offset,
+                            };
+                            let kind = WarningKind::MalformedChunk;
+                            diagnostics.report(error, kind, offset, Strictness::Normal)?;
+                        }
+                        Err(error) => {
+                            let kind = WarningKind::InvalidPalette;
+                            diagnostics.report(error, kind, offset, Strictness::Normal)?;
+                        }
+                    }
+                }
             }
         }
+
+        if flow == Flow::Stop || (options.stop_at == StopAt::Dimensions && dimensions.is_some()) {
+            stopped = true;
+            break;
+        }
     }
 
     let (width, height) = dimensions.ok_or(PngDecodingError::MissingIHDR)?;
-    Ok(ImageMetadata {
+    let truncated = !stopped && end.is_none();
+    if truncated {
+        let error = PngDecodingError::MissingIEND { offset: buf.len() };
+        diagnostics.report(error, WarningKind::Truncated, buf.len(), Strictness::Normal)?;
+    }
+    let trailing_bytes = end.filter(|&end| end < buf.len()).map(|end| end..buf.len());
+    Ok(ImageMetadataRef {
         width,
         height,
         comments,
+        exif,
+        xmp,
+        icc_profile,
+        warnings: diagnostics.warnings,
+        truncated,
+        trailing_bytes,
+        jpeg: None,
+        png,
     })
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
+    use crate::{Limits, Warning};
     use assert_matches::assert_matches;
+    use proptest::prelude::*;
 
     #[test]
     fn test_read_png_data_valid() {
@@ -125,7 +697,10 @@ mod tests {
         let result = read_png_data(&data);
 
         let err = result.unwrap_err();
-        assert_matches!(err, PngDecodingError::InvalidChunkCrc);
+        assert_matches!(
+            err,
+            PngDecodingError::InvalidChunkCrc { kind, offset: 8, .. } if &kind == b"IHDR"
+        );
     }
 
     #[test]
@@ -143,6 +718,23 @@ mod tests {
         let result = read_png_data(&data);
         let err = result.unwrap_err();
         assert_matches!(err, PngDecodingError::MissingIHDR);
+
+        // Cut short partway through the IHDR chunk.
+        let err = read_png_data(&sample_image()[..30]).unwrap_err();
+        assert_eq!(
+            err,
+            PngDecodingError::TruncatedChunk {
+                kind: *b"IHDR",
+                offset: 8,
+                length: 13,
+                available: 14,
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "IHDR chunk at offset 8 is cut short: it needs 17 bytes of data and CRC, \
+             but only 14 follow"
+        );
     }
 
     #[test]
@@ -170,7 +762,13 @@ mod tests {
         let result = read_png_data(&data);
         assert!(result.is_err());
         let err = result.unwrap_err();
-        assert_matches!(err, PngDecodingError::InvalidIHDRLength(14));
+        assert_matches!(
+            err,
+            PngDecodingError::InvalidIHDRLength {
+                offset: 8,
+                length: 14
+            }
+        );
     }
 
     #[test]
@@ -204,7 +802,359 @@ mod tests {
         let _ = read_png_data(data);
     }
 
+    #[test]
+    fn test_hdr_chunks() {
+        let mut data = sample_image();
+        let chunks = [
+            chunk(b"cICP", &[9, 16, 0, 1]),
+            chunk(b"cLLI", &[0x00, 0x98, 0x96, 0x80, 0x00, 0x3d, 0x09, 0x00]),
+        ]
+        .concat();
+        data.splice(33..33, chunks);
+
+        let png = read_png_data(&data).unwrap().png.unwrap();
+        assert!(png.cicp.unwrap().is_pq());
+        assert_eq!(png.mastering_display, None);
+        assert_eq!(png.content_light_level.unwrap().max_content, 10_000_000);
+    }
+
+    #[test]
+    fn test_color_chunks() {
+        let mut data = sample_image();
+        let chunks = [chunk(b"gAMA", &[0, 0, 0xb1, 0x8f]), chunk(b"sRGB", &[0])].concat();
+        data.splice(33..33, chunks);
+
+        let png = read_png_data(&data).unwrap().png.unwrap();
+        assert_eq!(png.bit_depth, 8);
+        assert_eq!(png.color_type, ColorType::Rgb);
+        assert_eq!(png.gamma, Some(45455));
+        assert_eq!(png.gamma_value(), Some(0.45455));
+        assert_eq!(png.srgb, Some(RenderingIntent::Perceptual));
+        assert_eq!(png.chromaticities.unwrap().white_point, (31270, 32900));
+        assert_eq!(png.significant_bits, None);
+        assert_eq!(png.background, Some(Background::Rgb(255, 255, 255)));
+    }
+
+    #[test]
+    fn test_chunk_order() {
+        let strict = ReadOptions::new().strictness(Strictness::Strict);
+        // The default read carries on with a warning; only a strict read
+        // fails.
+        let check = |data: &[u8], kind: WarningKind| {
+            let metadata = read_png_data(data).unwrap();
+            assert_eq!((metadata.width, metadata.height), (400, 224));
+            assert_eq!(metadata.warnings[0].kind, kind);
+            read_png_data_ref(data, &strict, &mut ()).unwrap_err()
+        };
+
+        // gAMA after IDAT.
+        let mut data = sample_image();
+        data.splice(149863..149863, chunk(b"gAMA", &[0, 0, 0xb1, 0x8f]));
+        let err = check(&data, WarningKind::ChunkOutOfOrder);
+        assert_matches!(err, PngDecodingError::ChunkOutOfOrder { kind, offset: 149863 } if &kind == b"gAMA");
+
+        // sRGB after PLTE.
+        let mut data = sample_image();
+        let chunks = [chunk(b"PLTE", &[0; 3]), chunk(b"sRGB", &[0])].concat();
+        data.splice(33..33, chunks);
+        let err = check(&data, WarningKind::ChunkOutOfOrder);
+        assert_matches!(err, PngDecodingError::ChunkOutOfOrder { kind, offset: 48 } if &kind == b"sRGB");
+
+        // PLTE after bKGD.
+        let mut data = sample_image();
+        data.splice(95..95, chunk(b"PLTE", &[0; 3]));
+        let err = check(&data, WarningKind::ChunkOutOfOrder);
+        assert_matches!(err, PngDecodingError::ChunkOutOfOrder { kind, offset: 95 } if &kind == b"PLTE");
+
+        // Two cHRM chunks.
+        let mut data = sample_image();
+        let chrm = data[33..77].to_vec();
+        data.splice(33..33, chrm);
+        let err = check(&data, WarningKind::DuplicateChunk);
+        assert_matches!(err, PngDecodingError::DuplicateChunk { kind, offset: 77 } if &kind == b"cHRM");
+    }
+
+    #[test]
+    fn test_palette() {
+        let mut data = sample_image();
+        // Turn the sample image into a 2-bit indexed image, and give it a
+        // palette and a tRNS chunk after the bKGD chunk (which is now the
+        // wrong length, so ignored).
+        let ihdr = [&data[16..24], &[2, 3, 0, 0, 0]].concat();
+        data.splice(8..33, chunk(b"IHDR", &ihdr));
+        let chunks = [
+            chunk(b"PLTE", &[255, 0, 0, 0, 255, 0, 0, 0, 255]),
+            chunk(b"tRNS", &[0, 128]),
+        ]
+        .concat();
+        data.splice(77..77, chunks);
+
+        let png = read_png_data(&data).unwrap().png.unwrap();
+        assert_eq!(png.color_type, ColorType::Indexed);
+        assert_eq!(png.palette.as_ref().unwrap().len(), 3);
+        assert_eq!(
+            png.transparency,
+            Some(Transparency::PaletteAlpha(vec![0, 128]))
+        );
+        assert_eq!(
+            png.palette_rgba().unwrap(),
+            vec![[255, 0, 0, 0], [0, 255, 0, 128], [0, 0, 255, 255]]
+        );
+        assert_eq!(png.background, None);
+    }
+
+    #[test]
+    fn test_invalid_palette() {
+        let mut data = sample_image();
+        data.splice(77..77, chunk(b"PLTE", &[255, 0, 0, 0]));
+        // The default read carries on without a palette.
+        let metadata = read_png_data(&data).unwrap();
+        assert_eq!((metadata.width, metadata.height), (400, 224));
+        assert_eq!(metadata.png.unwrap().palette, None);
+        assert_eq!(metadata.warnings.len(), 1);
+        assert_eq!(metadata.warnings[0].kind, WarningKind::InvalidPalette);
+        assert_eq!(metadata.warnings[0].offset, 77);
+
+        let strict = ReadOptions::new().strictness(Strictness::Strict);
+        let err = read_png_data_ref(&data, &strict, &mut ()).unwrap_err();
+        assert_matches!(
+            err,
+            PngDecodingError::InvalidPaletteLength {
+                offset: 77,
+                length: 4
+            }
+        );
+    }
+
+    #[test]
+    fn test_read_options() {
+        let mut data = sample_image();
+        let mut iccp = b"Profile\0\0".to_vec();
+        iccp.extend(embedded::tests::deflate(b"profile"));
+        data.splice(
+            77..77,
+            [
+                chunk(b"iCCP", &iccp),
+                chunk(b"iTXt", b"XML:com.adobe.xmp\0\0\0\0\0<x:xmpmeta/>"),
+            ]
+            .concat(),
+        );
+
+        let metadata = read_png_data(&data).unwrap();
+        assert_eq!(
+            (metadata.exif, metadata.xmp, metadata.icc_profile),
+            (None, None, None)
+        );
+
+        let options = ReadOptions::new().exif(true).xmp(true).icc_profile(true);
+        let metadata = read_png_data_ref(&data, &options, &mut ()).unwrap();
+        assert_eq!(metadata.exif.map(|exif| &exif[..4]), Some(&b"MM\0*"[..]));
+        assert_eq!(metadata.xmp, Some(Cow::Borrowed(&b"<x:xmpmeta/>"[..])));
+        assert_eq!(metadata.icc_profile.as_deref(), Some(&b"profile"[..]));
+
+        // Stopping at the dimensions skips the comments at the end.
+        let options = ReadOptions::dimensions_only();
+        let metadata = read_png_data_ref(&data, &options, &mut ()).unwrap();
+        assert_eq!((metadata.width, metadata.height), (400, 224));
+        assert!(metadata.comments.is_empty());
+
+        // A bad CRC is only noticed when checking CRCs.
+        let mut data = sample_image();
+        let last = data.len() - 13;
+        data[last] ^= 1;
+        let options = ReadOptions::new().verify_crc(false);
+        assert!(read_png_data_ref(&data, &options, &mut ()).is_ok());
+        let err = read_png_data(&data).unwrap_err();
+        assert_matches!(err, PngDecodingError::InvalidChunkCrc { .. });
+    }
+
+    #[test]
+    fn test_limits() {
+        let data = sample_image();
+        let read = |limits| read_png_data_ref(&data, &ReadOptions::new().limits(limits), &mut ());
+        let err = read(Limits::new().max_pixels(Some(400 * 224 - 1))).unwrap_err();
+        assert_matches!(
+            err,
+            PngDecodingError::LimitExceeded {
+                kind: LimitKind::Pixels,
+                value: 89600,
+                ..
+            }
+        );
+        let err = read(Limits::new().max_segments(Some(14))).unwrap_err();
+        assert_matches!(
+            err,
+            PngDecodingError::LimitExceeded {
+                kind: LimitKind::Segments,
+                value: 15,
+                limit: 14
+            }
+        );
+        assert!(read(Limits::new().max_segments(Some(15))).is_ok());
+    }
+
+    #[test]
+    fn test_strictness() {
+        // A tEXt chunk with no NUL after its keyword.
+        let mut data = sample_image();
+        data.splice(33..33, chunk(b"tEXt", b"comment"));
+        let metadata = read_png_data(&data).unwrap();
+        assert_eq!(
+            metadata.warnings,
+            vec![Warning {
+                offset: 33,
+                kind: WarningKind::MalformedChunk,
+                message: "Malformed tEXt chunk at offset 33".to_string(),
+            }]
+        );
+        let strict = ReadOptions::new().strictness(Strictness::Strict);
+        let err = read_png_data_ref(&data, &strict, &mut ()).unwrap_err();
+        assert_matches!(err, PngDecodingError::MalformedChunk { kind, offset: 33 } if &kind == b"tEXt");
+
+        // A bad CRC is only tolerated in lenient mode.
+        let mut data = sample_image();
+        data[32] ^= 1;
+        let err = read_png_data(&data).unwrap_err();
+        assert_matches!(err, PngDecodingError::InvalidChunkCrc { offset: 8, .. });
+        let lenient = ReadOptions::new().strictness(Strictness::Lenient);
+        let metadata = read_png_data_ref(&data, &lenient, &mut ()).unwrap();
+        assert_eq!((metadata.width, metadata.height), (400, 224));
+        assert_eq!(metadata.warnings.len(), 1);
+        assert_eq!(metadata.warnings[0].kind, WarningKind::InvalidCrc);
+        assert_eq!(metadata.warnings[0].offset, 8);
+
+        // Chunks in the wrong order are only an error in strict mode.
+        let mut data = sample_image();
+        data.splice(33..33, chunk(b"IDAT", b""));
+        assert_matches!(
+            read_png_data_ref(&data, &strict, &mut ()),
+            Err(PngDecodingError::ChunkOutOfOrder { kind, offset: 45 }) if &kind == b"cHRM"
+        );
+        let metadata = read_png_data(&data).unwrap();
+        assert_eq!(
+            metadata.warnings.iter().map(|w| w.kind).collect::<Vec<_>>(),
+            [WarningKind::ChunkOutOfOrder, WarningKind::ChunkOutOfOrder]
+        );
+    }
+
+    #[test]
+    fn test_truncation() {
+        let data = sample_image();
+        let metadata = read_png_data(&data).unwrap();
+        assert!(!metadata.truncated);
+        assert_eq!(metadata.trailing_bytes, None);
+
+        let mut appended = data.clone();
+        appended.extend_from_slice(b"PK\x03\x04");
+        let metadata = read_png_data(&appended).unwrap();
+        assert_eq!(metadata.trailing_bytes, Some(data.len()..data.len() + 4));
+
+        // Cut short in the middle of the image data.
+        let cut = &data[..data.len() / 2];
+        let metadata = read_png_data(cut).unwrap();
+        assert!(metadata.truncated);
+        assert_eq!(metadata.warnings[0].kind, WarningKind::Truncated);
+        let strict = ReadOptions::new().strictness(Strictness::Strict);
+        let err = read_png_data_ref(cut, &strict, &mut ()).unwrap_err();
+        assert_eq!(
+            err,
+            PngDecodingError::MissingIEND {
+                offset: data.len() / 2
+            }
+        );
+        assert_eq!(err.kind(), ErrorKind::Truncated);
+        assert_eq!(err.offset(), Some(data.len() / 2));
+
+        // We don't know, if we stop before the end.
+        let options = ReadOptions::new().stop_at(StopAt::Dimensions);
+        let metadata = read_png_data_ref(cut, &options, &mut ()).unwrap();
+        assert!(!metadata.truncated);
+    }
+
+    #[test]
+    fn test_visitor() {
+        struct Private(Vec<u8>);
+        impl MetadataVisitor for Private {
+            fn visit_png_chunk(&mut self, chunk: &Chunk) -> Flow {
+                if &chunk.kind == b"prIv" {
+                    self.0 = chunk.data.to_vec();
+                    return Flow::Stop;
+                }
+                Flow::Continue
+            }
+        }
+
+        let mut data = sample_image();
+        data.splice(77..77, chunk(b"prIv", b"secret"));
+        let mut visitor = Private(Vec::new());
+        let metadata = read_png_data_with_visitor(&data, &mut visitor).unwrap();
+        assert_eq!(visitor.0, b"secret");
+        // We stopped before reaching the tEXt chunks.
+        assert_eq!((metadata.width, metadata.height), (400, 224));
+        assert!(metadata.comments.is_empty());
+    }
+
+    /// Build a chunk with the given type and data, including its CRC.
+    pub(crate) fn chunk(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut chunk = Vec::new();
+        chunk.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        chunk.extend_from_slice(kind);
+        chunk.extend_from_slice(data);
+        let crc = crc32fast::hash(&chunk[4..]);
+        chunk.extend_from_slice(&crc.to_be_bytes());
+        chunk
+    }
+
     fn sample_image() -> Vec<u8> {
         std::fs::read("src/watercolors.png").unwrap()
     }
+
+    /// A chunk of a type that the reader looks at, with whatever data,
+    /// compressed or not.
+    fn arbitrary_chunk() -> impl Strategy<Value = Vec<u8>> {
+        let kind = prop::sample::select(vec![
+            *b"PLTE", *b"tRNS", *b"gAMA", *b"cHRM", *b"sRGB", *b"sBIT", *b"bKGD", *b"cICP",
+            *b"mDCv", *b"cLLI", *b"tEXt", *b"iTXt", *b"iCCP", *b"eXIf", *b"IDAT", *b"IEND",
+            *b"IHDR",
+        ]);
+        let data = prop::collection::vec(any::<u8>(), 0..64);
+        (kind, data, any::<bool>()).prop_map(|(kind, data, compressed)| {
+            let data = match &kind {
+                b"iCCP" if compressed => {
+                    [&b"ICC\0\0"[..], &embedded::tests::deflate(&data)].concat()
+                }
+                b"iTXt" if compressed => {
+                    let header = b"XML:com.adobe.xmp\0\x01\0\0\0";
+                    [&header[..], &embedded::tests::deflate(&data)].concat()
+                }
+                _ => data,
+            };
+            chunk(&kind, &data)
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn test_arbitrary_chunks_never_panic(
+            ihdr in prop::collection::vec(any::<u8>(), 13),
+            chunks in prop::collection::vec(arbitrary_chunk(), 0..12),
+        ) {
+            let mut data = b"\x89PNG\r\n\x1a\n".to_vec();
+            data.extend(chunk(b"IHDR", &ihdr));
+            data.extend(chunks.concat());
+
+            for strictness in [Strictness::Strict, Strictness::Lenient] {
+                let options = ReadOptions::new()
+                    .exif(true)
+                    .xmp(true)
+                    .icc_profile(true)
+                    .strictness(strictness);
+                if let Ok(metadata) = read_png_data_ref(&data, &options, &mut ()) {
+                    let png = metadata.png.unwrap();
+                    let _ = png.palette_rgba();
+                    let _ = png.gamma_value();
+                }
+            }
+        }
+    }
 }
@@ -0,0 +1,277 @@
+//! Iterating over the chunks in PNG data.
+
+use super::PngDecodingError;
+
+/// A chunk in PNG data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Chunk<'a> {
+    /// The chunk type, such as `*b"IHDR"`.
+    pub kind: [u8; 4],
+    /// The chunk's data, borrowed from the PNG data.
+    pub data: &'a [u8],
+    /// The chunk's CRC, as stored in the PNG data.
+    pub crc: u32,
+    /// The position of the start of the chunk (its length field) in the PNG
+    /// data.
+    pub offset: usize,
+}
+
+impl<'a> Chunk<'a> {
+    /// Returns true if this is a critical chunk, which a decoder must
+    /// understand to display the image. Otherwise, it is ancillary.
+    pub fn is_critical(&self) -> bool {
+        self.kind[0] & 0x20 == 0
+    }
+
+    /// Returns true if this is a public chunk, defined by the PNG
+    /// specification or registered. Otherwise, it is private.
+    pub fn is_public(&self) -> bool {
+        self.kind[1] & 0x20 == 0
+    }
+
+    /// Returns true if the reserved bit is set correctly (to 0). Chunks with
+    /// it set to 1 don't follow this version of the PNG specification.
+    pub fn is_reserved_bit_valid(&self) -> bool {
+        self.kind[2] & 0x20 == 0
+    }
+
+    /// Returns true if an editor that doesn't understand this chunk may copy
+    /// it to a modified image anyway.
+    pub fn is_safe_to_copy(&self) -> bool {
+        self.kind[3] & 0x20 != 0
+    }
+
+    /// Returns true if the chunk's CRC matches its type and data.
+    pub fn crc_matches(&self) -> bool {
+        self.calculate_crc() == self.crc
+    }
+
+    /// Calculates the CRC of the chunk's type and data.
+    pub fn calculate_crc(&self) -> u32 {
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&self.kind);
+        hasher.update(self.data);
+        hasher.finalize()
+    }
+
+    /// The error for a chunk whose CRC doesn't match.
+    pub(crate) fn crc_error(&self) -> PngDecodingError {
+        PngDecodingError::InvalidChunkCrc {
+            kind: self.kind,
+            offset: self.offset,
+            expected: self.crc,
+            actual: self.calculate_crc(),
+        }
+    }
+
+    /// The total length of the chunk in the PNG data, including its length,
+    /// type and CRC fields.
+    pub fn total_len(&self) -> usize {
+        12 + self.data.len()
+    }
+}
+
+/// An iterator over the chunks in PNG data.
+///
+/// The first 8 bytes of the data (the PNG signature) are skipped. Each
+/// chunk's length is checked against the data, and its CRC is verified
+/// (unless turned off with [`Chunks::verify_crc`]); if either check fails,
+/// the iterator returns the error and then stops. It also
+/// stops after the IEND chunk, or if there are too few bytes left for another
+/// chunk.
+///
+/// # Example
+///
+/// ```
+/// use pb_imgsize::png::Chunks;
+///
+/// let data = include_bytes!("../watercolors.png");
+/// let kinds = Chunks::new(data)
+///     .map(|chunk| chunk.map(|c| c.kind))
+///     .collect::<Result<Vec<_>, _>>()
+///     .unwrap();
+/// assert_eq!(&kinds[0], b"IHDR");
+/// assert_eq!(kinds.last(), Some(b"IEND"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Chunks<'a> {
+    buf: &'a [u8],
+    position: usize,
+    verify_crc: bool,
+    done: bool,
+}
+
+impl<'a> Chunks<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Chunks {
+            buf,
+            position: 8,
+            verify_crc: true,
+            done: false,
+        }
+    }
+
+    /// Whether to verify each chunk's CRC. Skipping this saves hashing every
+    /// chunk, including the image data.
+    pub fn verify_crc(mut self, verify_crc: bool) -> Self {
+        self.verify_crc = verify_crc;
+        self
+    }
+
+    /// The position in the data where the next chunk would start.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    fn read_chunk(&mut self) -> Result<Option<Chunk<'a>>, PngDecodingError> {
+        let buf = self.buf;
+        let offset = self.position;
+        if offset + 12 > buf.len() {
+            return Ok(None);
+        }
+        let header = &buf[offset..offset + 8];
+        let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let kind = [header[4], header[5], header[6], header[7]];
+
+        let data_start = offset + 8;
+        let Some(crc_bytes) = data_start
+            .checked_add(length)
+            .and_then(|end| buf.get(end..end.checked_add(4)?))
+        else {
+            return Err(PngDecodingError::TruncatedChunk {
+                kind,
+                offset,
+                length: length as u32,
+                available: buf.len() - data_start,
+            });
+        };
+        let data = &buf[data_start..data_start + length];
+        let crc = u32::from_be_bytes([crc_bytes[0], crc_bytes[1], crc_bytes[2], crc_bytes[3]]);
+
+        let chunk = Chunk {
+            kind,
+            data,
+            crc,
+            offset,
+        };
+        if self.verify_crc && !chunk.crc_matches() {
+            return Err(chunk.crc_error());
+        }
+        self.position = data_start + length + 4;
+        Ok(Some(chunk))
+    }
+}
+
+impl<'a> Iterator for Chunks<'a> {
+    type Item = Result<Chunk<'a>, PngDecodingError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let result = self.read_chunk().transpose();
+        match &result {
+            Some(Ok(chunk)) if &chunk.kind != b"IEND" => {}
+            _ => self.done = true,
+        }
+        result
+    }
+}
+
+impl<'a> std::iter::FusedIterator for Chunks<'a> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+
+    #[test]
+    fn test_chunks() {
+        let data = sample_image();
+        let chunks = Chunks::new(&data).collect::<Result<Vec<_>, _>>().unwrap();
+        let kinds: Vec<_> = chunks.iter().map(|c| &c.kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                b"IHDR", b"cHRM", b"bKGD", b"tIME", b"IDAT", b"IDAT", b"IDAT", b"IDAT", b"IDAT",
+                b"eXIf", b"tEXt", b"tEXt", b"tEXt", b"tEXt", b"IEND"
+            ]
+        );
+
+        let ihdr = &chunks[0];
+        assert_eq!(ihdr.offset, 8);
+        assert_eq!(ihdr.data.len(), 13);
+        assert_eq!(ihdr.total_len(), 25);
+        assert_eq!(chunks[1].offset, 33);
+        assert_eq!(chunks.last().unwrap().offset + 12, data.len());
+    }
+
+    #[test]
+    fn test_chunk_properties() {
+        let chunk = |kind: &[u8; 4]| Chunk {
+            kind: *kind,
+            data: &[],
+            crc: 0,
+            offset: 0,
+        };
+        let ihdr = chunk(b"IHDR");
+        assert!(ihdr.is_critical() && ihdr.is_public() && !ihdr.is_safe_to_copy());
+        let text = chunk(b"tEXt");
+        assert!(!text.is_critical() && text.is_public() && text.is_safe_to_copy());
+        let private = chunk(b"prIv");
+        assert!(!private.is_critical() && !private.is_public() && private.is_safe_to_copy());
+        assert!(private.is_reserved_bit_valid());
+        assert!(!chunk(b"tExt").is_reserved_bit_valid());
+    }
+
+    #[test]
+    fn test_stops_after_error() {
+        let mut data = sample_image();
+        data[31] ^= 1;
+        let mut chunks = Chunks::new(&data);
+        let error = chunks.next().unwrap().unwrap_err();
+        assert_matches!(
+            error,
+            PngDecodingError::InvalidChunkCrc {
+                kind: [b'I', b'H', b'D', b'R'],
+                offset: 8,
+                expected,
+                actual,
+            } if expected != actual
+        );
+        assert_eq!(error.kind(), crate::ErrorKind::ChecksumMismatch);
+        assert!(error
+            .to_string()
+            .starts_with("Invalid CRC in IHDR chunk at offset 8: expected 0x"));
+        assert_matches!(chunks.next(), None);
+    }
+
+    #[test]
+    fn test_without_crc() {
+        let mut data = sample_image();
+        data[31] ^= 1;
+        let chunks = Chunks::new(&data).verify_crc(false);
+        assert_eq!(chunks.map(Result::unwrap).count(), 15);
+    }
+
+    #[test]
+    fn test_stops_after_iend() {
+        let mut data = sample_image();
+        data.extend_from_within(8..33);
+        assert_eq!(Chunks::new(&data).count(), 15);
+    }
+
+    #[test]
+    fn test_length_overrun() {
+        let data = include_bytes!("../invalid01.png");
+        let result = Chunks::new(data).collect::<Result<Vec<_>, _>>();
+        assert_matches!(
+            result,
+            Err(PngDecodingError::TruncatedChunk { offset: 8, .. })
+        );
+    }
+
+    fn sample_image() -> Vec<u8> {
+        std::fs::read("src/watercolors.png").unwrap()
+    }
+}
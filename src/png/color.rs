@@ -0,0 +1,184 @@
+//! Decoders for the PNG colorimetry chunks: gAMA, cHRM, sRGB, sBIT and bKGD.
+//!
+//! A chunk with the wrong length is ignored, the same as a missing one.
+
+/// The color type from the IHDR chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorType {
+    Grayscale,
+    Rgb,
+    Indexed,
+    GrayscaleAlpha,
+    Rgba,
+    Unknown(u8),
+}
+
+impl From<u8> for ColorType {
+    fn from(n: u8) -> Self {
+        match n {
+            0 => ColorType::Grayscale,
+            2 => ColorType::Rgb,
+            3 => ColorType::Indexed,
+            4 => ColorType::GrayscaleAlpha,
+            6 => ColorType::Rgba,
+            n => ColorType::Unknown(n),
+        }
+    }
+}
+
+/// The contents of a cHRM chunk.
+///
+/// Each (x, y) chromaticity is stored as in the chunk, multiplied by 100000.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Chromaticities {
+    pub white_point: (u32, u32),
+    pub red: (u32, u32),
+    pub green: (u32, u32),
+    pub blue: (u32, u32),
+}
+
+/// The rendering intent from an sRGB chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderingIntent {
+    Perceptual,
+    RelativeColorimetric,
+    Saturation,
+    AbsoluteColorimetric,
+    Unknown(u8),
+}
+
+/// The contents of an sBIT chunk: the number of significant bits in each
+/// channel of the original image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignificantBits {
+    Gray(u8),
+    GrayAlpha(u8, u8),
+    /// Used for both truecolor and indexed images.
+    Rgb(u8, u8, u8),
+    Rgba(u8, u8, u8, u8),
+}
+
+/// The contents of a bKGD chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Background {
+    PaletteIndex(u8),
+    Gray(u16),
+    Rgb(u16, u16, u16),
+}
+
+pub(crate) fn read_gama(data: &[u8]) -> Option<u32> {
+    match *data {
+        [a, b, c, d] => Some(u32::from_be_bytes([a, b, c, d])),
+        _ => None,
+    }
+}
+
+pub(crate) fn read_chrm(data: &[u8]) -> Option<Chromaticities> {
+    if data.len() != 32 {
+        return None;
+    }
+    let u32_at = |i: usize| u32::from_be_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+    let xy = |i: usize| (u32_at(i), u32_at(i + 4));
+    Some(Chromaticities {
+        white_point: xy(0),
+        red: xy(8),
+        green: xy(16),
+        blue: xy(24),
+    })
+}
+
+pub(crate) fn read_srgb(data: &[u8]) -> Option<RenderingIntent> {
+    match *data {
+        [0] => Some(RenderingIntent::Perceptual),
+        [1] => Some(RenderingIntent::RelativeColorimetric),
+        [2] => Some(RenderingIntent::Saturation),
+        [3] => Some(RenderingIntent::AbsoluteColorimetric),
+        [n] => Some(RenderingIntent::Unknown(n)),
+        _ => None,
+    }
+}
+
+pub(crate) fn read_sbit(data: &[u8], color_type: ColorType) -> Option<SignificantBits> {
+    match (color_type, data) {
+        (ColorType::Grayscale, &[g]) => Some(SignificantBits::Gray(g)),
+        (ColorType::GrayscaleAlpha, &[g, a]) => Some(SignificantBits::GrayAlpha(g, a)),
+        (ColorType::Rgb | ColorType::Indexed, &[r, g, b]) => Some(SignificantBits::Rgb(r, g, b)),
+        (ColorType::Rgba, &[r, g, b, a]) => Some(SignificantBits::Rgba(r, g, b, a)),
+        _ => None,
+    }
+}
+
+pub(crate) fn read_bkgd(data: &[u8], color_type: ColorType) -> Option<Background> {
+    match (color_type, data) {
+        (ColorType::Indexed, &[i]) => Some(Background::PaletteIndex(i)),
+        (ColorType::Grayscale | ColorType::GrayscaleAlpha, &[g0, g1]) => {
+            Some(Background::Gray(u16::from_be_bytes([g0, g1])))
+        }
+        (ColorType::Rgb | ColorType::Rgba, &[r0, r1, g0, g1, b0, b1]) => Some(Background::Rgb(
+            u16::from_be_bytes([r0, r1]),
+            u16::from_be_bytes([g0, g1]),
+            u16::from_be_bytes([b0, b1]),
+        )),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_gama() {
+        assert_eq!(read_gama(&[0x00, 0x00, 0xb1, 0x8f]), Some(45455));
+        assert_eq!(read_gama(&[0x00, 0xb1, 0x8f]), None);
+    }
+
+    #[test]
+    fn test_read_chrm() {
+        let data = std::fs::read("src/watercolors.png").unwrap();
+        let chrm = read_chrm(&data[41..73]).unwrap();
+        assert_eq!(chrm.white_point, (31270, 32900));
+        assert_eq!(chrm.red, (64000, 33000));
+        assert_eq!(chrm.green, (30000, 60000));
+        assert_eq!(chrm.blue, (15000, 6000));
+        assert_eq!(read_chrm(&data[41..72]), None);
+    }
+
+    #[test]
+    fn test_read_srgb() {
+        assert_eq!(read_srgb(&[0]), Some(RenderingIntent::Perceptual));
+        assert_eq!(read_srgb(&[3]), Some(RenderingIntent::AbsoluteColorimetric));
+        assert_eq!(read_srgb(&[4]), Some(RenderingIntent::Unknown(4)));
+        assert_eq!(read_srgb(&[]), None);
+    }
+
+    #[test]
+    fn test_read_sbit() {
+        assert_eq!(
+            read_sbit(&[5, 6, 5], ColorType::Indexed),
+            Some(SignificantBits::Rgb(5, 6, 5))
+        );
+        assert_eq!(
+            read_sbit(&[4, 1], ColorType::GrayscaleAlpha),
+            Some(SignificantBits::GrayAlpha(4, 1))
+        );
+        assert_eq!(read_sbit(&[5, 6, 5], ColorType::Rgba), None);
+    }
+
+    #[test]
+    fn test_read_bkgd() {
+        assert_eq!(
+            read_bkgd(&[0, 255, 0, 255, 0, 255], ColorType::Rgb),
+            Some(Background::Rgb(255, 255, 255))
+        );
+        assert_eq!(
+            read_bkgd(&[7], ColorType::Indexed),
+            Some(Background::PaletteIndex(7))
+        );
+        assert_eq!(
+            read_bkgd(&[1, 0], ColorType::Grayscale),
+            Some(Background::Gray(256))
+        );
+        assert_eq!(read_bkgd(&[1, 0], ColorType::Rgb), None);
+    }
+}
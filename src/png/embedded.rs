@@ -0,0 +1,169 @@
+//! Extracting the ICC profile from an iCCP chunk, and the XMP packet from an
+//! iTXt chunk, and editing the XMP packet.
+//!
+//! A chunk that can't be decoded is ignored, the same as a missing one.
+
+use std::borrow::Cow;
+
+use miniz_oxide::inflate::TINFLStatus;
+
+use crate::limits::LimitExceeded;
+use crate::{LimitKind, Limits};
+
+/// The iTXt keyword for an XMP packet.
+const XMP_KEYWORD: &[u8] = b"XML:com.adobe.xmp";
+
+/// Decode an iCCP chunk, returning the decompressed ICC profile.
+pub(crate) fn read_iccp(data: &[u8], limits: &Limits) -> Result<Option<Vec<u8>>, LimitExceeded> {
+    let Some((_name, rest)) = split_at_nul(data) else {
+        return Ok(None);
+    };
+    match *rest {
+        // Compression method 0 is zlib, the only one defined.
+        [0, ref compressed @ ..] => inflate(compressed, limits),
+        _ => Ok(None),
+    }
+}
+
+/// Decode an iTXt chunk, returning its text if it holds an XMP packet. The
+/// text is only copied if it is compressed.
+pub(crate) fn read_xmp<'a>(
+    data: &'a [u8],
+    limits: &Limits,
+) -> Result<Option<Cow<'a, [u8]>>, LimitExceeded> {
+    let Some(text) = xmp_text(data) else {
+        return Ok(None);
+    };
+    match text {
+        (0, _, text) => Ok(Some(Cow::Borrowed(text))),
+        (1, 0, text) => Ok(inflate(text, limits)?.map(Cow::Owned)),
+        _ => Ok(None),
+    }
+}
+
+/// Returns true if an iTXt chunk holds an XMP packet.
+pub(crate) fn is_xmp(data: &[u8]) -> bool {
+    matches!(split_at_nul(data), Some((keyword, _)) if keyword == XMP_KEYWORD)
+}
+
+/// Remove the GPS properties and serial numbers from an iTXt chunk holding an
+/// XMP packet, returning the chunk's new data, or `None` if the packet can't
+/// be decoded. A compressed packet is compressed again.
+pub(crate) fn remove_private_xmp(data: &[u8]) -> Option<Vec<u8>> {
+    let (compressed, _method, text) = xmp_text(data)?;
+    let header = &data[..data.len() - text.len()];
+    let mut xmp = read_xmp(data, &Limits::default()).ok()??.into_owned();
+    crate::xmp::remove_private_properties(&mut xmp);
+    if compressed != 0 {
+        xmp = deflate(&xmp);
+    }
+    Some([header, &xmp].concat())
+}
+
+/// Split an iTXt chunk holding an XMP packet into its compression flag,
+/// compression method and text.
+fn xmp_text(data: &[u8]) -> Option<(u8, u8, &[u8])> {
+    let (keyword, rest) = split_at_nul(data)?;
+    if keyword != XMP_KEYWORD {
+        return None;
+    }
+    let [compressed, method, ref rest @ ..] = *rest else {
+        return None;
+    };
+    let (_language, rest) = split_at_nul(rest)?;
+    let (_translated_keyword, text) = split_at_nul(rest)?;
+    Some((compressed, method, text))
+}
+
+/// Split the data at the first NUL byte, dropping the NUL.
+fn split_at_nul(data: &[u8]) -> Option<(&[u8], &[u8])> {
+    let nul = memchr::memchr(0, data)?;
+    Some((&data[..nul], &data[nul + 1..]))
+}
+
+/// Compress data the way PNG does.
+pub(crate) fn deflate(data: &[u8]) -> Vec<u8> {
+    miniz_oxide::deflate::compress_to_vec_zlib(data, 6)
+}
+
+/// Inflate zlib data, returning `None` if it is corrupt, or an error if it
+/// inflates to more than the limit.
+fn inflate(data: &[u8], limits: &Limits) -> Result<Option<Vec<u8>>, LimitExceeded> {
+    let max_size = limits.max_inflated_size.unwrap_or(usize::MAX);
+    match miniz_oxide::inflate::decompress_to_vec_zlib_with_limit(data, max_size) {
+        Ok(data) => Ok(Some(data)),
+        Err(e) if e.status == TINFLStatus::HasMoreOutput => {
+            limits.check(LimitKind::InflatedSize, max_size as u64 + 1)?;
+            Ok(None)
+        }
+        Err(_) => Ok(None),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) use super::deflate;
+
+    #[test]
+    fn test_read_iccp() {
+        let mut data = b"sRGB\0\0".to_vec();
+        data.extend(deflate(b"profile"));
+        let limits = Limits::default();
+        assert_eq!(read_iccp(&data, &limits), Ok(Some(b"profile".to_vec())));
+
+        let small = Limits::new().max_inflated_size(Some(4));
+        let err = read_iccp(&data, &small).unwrap_err();
+        assert_eq!(
+            (err.kind, err.value, err.limit),
+            (LimitKind::InflatedSize, 5, 4)
+        );
+
+        // Unknown compression method.
+        data[5] = 1;
+        assert_eq!(read_iccp(&data, &limits), Ok(None));
+        assert_eq!(read_iccp(b"sRGB", &limits), Ok(None));
+    }
+
+    #[test]
+    fn test_read_xmp() {
+        let limits = Limits::default();
+        let data = b"XML:com.adobe.xmp\0\0\0\0\0<x:xmpmeta/>";
+        assert_eq!(
+            read_xmp(data, &limits),
+            Ok(Some(Cow::Borrowed(&b"<x:xmpmeta/>"[..])))
+        );
+
+        let mut data = b"XML:com.adobe.xmp\0\x01\0en\0\0".to_vec();
+        data.extend(deflate(b"<x:xmpmeta/>"));
+        assert_eq!(
+            read_xmp(&data, &limits).unwrap().as_deref(),
+            Some(&b"<x:xmpmeta/>"[..])
+        );
+
+        assert_eq!(read_xmp(b"Title\0\0\0\0\0Watercolors", &limits), Ok(None));
+        assert_eq!(read_xmp(b"XML:com.adobe.xmp\0\0\0", &limits), Ok(None));
+    }
+
+    #[test]
+    fn test_remove_private_xmp() {
+        let xmp = crate::xmp::tests::XMP;
+        let mut expected = xmp.to_vec();
+        crate::xmp::remove_private_properties(&mut expected);
+        let header = b"XML:com.adobe.xmp\0\0\0\0\0";
+        let data = remove_private_xmp(&[header, xmp].concat()).unwrap();
+        assert_eq!(data, [header, &expected[..]].concat());
+
+        let header = b"XML:com.adobe.xmp\0\x01\0en\0\0";
+        let data = remove_private_xmp(&[&header[..], &deflate(xmp)].concat()).unwrap();
+        assert_eq!(&data[..header.len()], header);
+        let limits = Limits::default();
+        assert_eq!(read_xmp(&data, &limits).unwrap().unwrap(), expected);
+
+        assert_eq!(
+            remove_private_xmp(b"XML:com.adobe.xmp\0\x01\0\0\0junk"),
+            None
+        );
+    }
+}
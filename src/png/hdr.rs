@@ -0,0 +1,175 @@
+//! Decoders for the HDR chunks added in the third edition of the PNG
+//! specification: cICP, mDCv and cLLI.
+//!
+//! A chunk with the wrong length is ignored, the same as a missing one.
+
+/// The contents of a cICP (coding-independent code points) chunk.
+///
+/// The values are code points from ITU-T H.273.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cicp {
+    pub color_primaries: u8,
+    pub transfer_function: u8,
+    pub matrix_coefficients: u8,
+    /// True if the image uses the full range of sample values, rather than
+    /// the narrow ("video") range.
+    pub full_range: bool,
+}
+
+impl Cicp {
+    /// Returns true if the transfer function is SMPTE ST 2084 (PQ).
+    pub fn is_pq(&self) -> bool {
+        self.transfer_function == 16
+    }
+
+    /// Returns true if the transfer function is ARIB STD-B67 (HLG).
+    pub fn is_hlg(&self) -> bool {
+        self.transfer_function == 18
+    }
+}
+
+/// The contents of an mDCv (mastering display color volume) chunk.
+///
+/// The fields hold the raw values from the chunk. Chromaticities are in units
+/// of 0.00002, and luminances in units of 0.0001 cd/m².
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MasteringDisplay {
+    /// The (x, y) chromaticities of the red, green and blue primaries.
+    pub primaries: [(u16, u16); 3],
+    /// The (x, y) chromaticity of the white point.
+    pub white_point: (u16, u16),
+    pub max_luminance: u32,
+    pub min_luminance: u32,
+}
+
+impl MasteringDisplay {
+    /// The (x, y) chromaticities of the red, green and blue primaries.
+    pub fn primaries_xy(&self) -> [(f64, f64); 3] {
+        self.primaries.map(chromaticity)
+    }
+
+    /// The (x, y) chromaticity of the white point.
+    pub fn white_point_xy(&self) -> (f64, f64) {
+        chromaticity(self.white_point)
+    }
+
+    /// The maximum luminance of the mastering display, in cd/m².
+    pub fn max_luminance_nits(&self) -> f64 {
+        f64::from(self.max_luminance) * 0.0001
+    }
+
+    /// The minimum luminance of the mastering display, in cd/m².
+    pub fn min_luminance_nits(&self) -> f64 {
+        f64::from(self.min_luminance) * 0.0001
+    }
+}
+
+/// The contents of a cLLI (content light level information) chunk.
+///
+/// The fields hold the raw values from the chunk, in units of 0.0001 cd/m².
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContentLightLevel {
+    /// The maximum content light level (MaxCLL).
+    pub max_content: u32,
+    /// The maximum frame-average light level (MaxFALL).
+    pub max_frame_average: u32,
+}
+
+impl ContentLightLevel {
+    /// The maximum content light level (MaxCLL), in cd/m².
+    pub fn max_content_nits(&self) -> f64 {
+        f64::from(self.max_content) * 0.0001
+    }
+
+    /// The maximum frame-average light level (MaxFALL), in cd/m².
+    pub fn max_frame_average_nits(&self) -> f64 {
+        f64::from(self.max_frame_average) * 0.0001
+    }
+}
+
+fn chromaticity((x, y): (u16, u16)) -> (f64, f64) {
+    (f64::from(x) * 0.00002, f64::from(y) * 0.00002)
+}
+
+pub(crate) fn read_cicp(data: &[u8]) -> Option<Cicp> {
+    match *data {
+        [color_primaries, transfer_function, matrix_coefficients, full_range] => Some(Cicp {
+            color_primaries,
+            transfer_function,
+            matrix_coefficients,
+            full_range: full_range != 0,
+        }),
+        _ => None,
+    }
+}
+
+pub(crate) fn read_mdcv(data: &[u8]) -> Option<MasteringDisplay> {
+    if data.len() != 24 {
+        return None;
+    }
+    let u16_at = |i: usize| u16::from_be_bytes([data[i], data[i + 1]]);
+    let u32_at = |i: usize| u32::from_be_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+    Some(MasteringDisplay {
+        primaries: [
+            (u16_at(0), u16_at(2)),
+            (u16_at(4), u16_at(6)),
+            (u16_at(8), u16_at(10)),
+        ],
+        white_point: (u16_at(12), u16_at(14)),
+        max_luminance: u32_at(16),
+        min_luminance: u32_at(20),
+    })
+}
+
+pub(crate) fn read_clli(data: &[u8]) -> Option<ContentLightLevel> {
+    if data.len() != 8 {
+        return None;
+    }
+    let u32_at = |i: usize| u32::from_be_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+    Some(ContentLightLevel {
+        max_content: u32_at(0),
+        max_frame_average: u32_at(4),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_cicp() {
+        // BT.2100 PQ, full range.
+        let cicp = read_cicp(&[9, 16, 0, 1]).unwrap();
+        assert_eq!(cicp.color_primaries, 9);
+        assert_eq!(cicp.matrix_coefficients, 0);
+        assert!(cicp.full_range);
+        assert!(cicp.is_pq());
+        assert!(!cicp.is_hlg());
+        assert_eq!(read_cicp(&[9, 18, 0]), None);
+    }
+
+    #[test]
+    fn test_read_mdcv() {
+        // The BT.2020 primaries and D65 white point, 1000 to 0.005 cd/m².
+        let data = [
+            0x8a, 0x48, 0x39, 0x08, 0x21, 0x34, 0x9b, 0xaa, 0x19, 0x96, 0x08, 0xfc, 0x3d, 0x13,
+            0x40, 0x42, 0x00, 0x98, 0x96, 0x80, 0x00, 0x00, 0x00, 0x32,
+        ];
+        let mdcv = read_mdcv(&data).unwrap();
+        assert_eq!(mdcv.primaries[0], (35400, 14600));
+        assert_eq!(mdcv.white_point, (15635, 16450));
+        let (x, y) = mdcv.white_point_xy();
+        assert!((x - 0.3127).abs() < 1e-9 && (y - 0.3290).abs() < 1e-9);
+        assert!((mdcv.max_luminance_nits() - 1000.0).abs() < 1e-9);
+        assert!((mdcv.min_luminance_nits() - 0.005).abs() < 1e-9);
+        assert_eq!(read_mdcv(&data[1..]), None);
+    }
+
+    #[test]
+    fn test_read_clli() {
+        let clli = read_clli(&[0x00, 0x98, 0x96, 0x80, 0x00, 0x3d, 0x09, 0x00]).unwrap();
+        assert!((clli.max_content_nits() - 1000.0).abs() < 1e-9);
+        assert!((clli.max_frame_average_nits() - 400.0).abs() < 1e-9);
+        assert_eq!(read_clli(&[0; 9]), None);
+    }
+}
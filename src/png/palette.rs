@@ -0,0 +1,126 @@
+//! Decoders for the PLTE and tRNS chunks.
+
+use super::{ColorType, PngDecodingError};
+
+/// The contents of a tRNS chunk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Transparency {
+    /// Alpha values for the first entries of the palette. Entries past the
+    /// end of the list are opaque.
+    PaletteAlpha(Vec<u8>),
+    /// The gray level that is fully transparent.
+    Gray(u16),
+    /// The RGB color that is fully transparent.
+    Rgb(u16, u16, u16),
+}
+
+/// Decode a PLTE chunk, checking that its length is a multiple of 3 and that
+/// it has no more entries than the bit depth allows.
+pub(crate) fn read_plte(
+    data: &[u8],
+    offset: usize,
+    bit_depth: u8,
+    color_type: ColorType,
+) -> Result<Vec<[u8; 3]>, PngDecodingError> {
+    let len = data.len() as u32;
+    if len == 0 || !len.is_multiple_of(3) {
+        return Err(PngDecodingError::InvalidPaletteLength {
+            offset,
+            length: len,
+        });
+    }
+    let entries = len / 3;
+    // Non-indexed images can carry a suggested palette of up to 256 entries.
+    let max_entries = match color_type {
+        ColorType::Indexed => 1u32.checked_shl(bit_depth.into()).unwrap_or(u32::MAX),
+        _ => 256,
+    };
+    if entries > max_entries.min(256) {
+        return Err(PngDecodingError::PaletteTooLarge {
+            offset,
+            entries,
+            bit_depth,
+        });
+    }
+    Ok(data
+        .chunks_exact(3)
+        .map(|rgb| [rgb[0], rgb[1], rgb[2]])
+        .collect())
+}
+
+/// Decode a tRNS chunk, returning `None` if its length doesn't suit the
+/// color type.
+pub(crate) fn read_trns(data: &[u8], color_type: ColorType) -> Option<Transparency> {
+    match (color_type, data) {
+        (ColorType::Indexed, _) => Some(Transparency::PaletteAlpha(data.to_vec())),
+        (ColorType::Grayscale, &[g0, g1]) => Some(Transparency::Gray(u16::from_be_bytes([g0, g1]))),
+        (ColorType::Rgb, &[r0, r1, g0, g1, b0, b1]) => Some(Transparency::Rgb(
+            u16::from_be_bytes([r0, r1]),
+            u16::from_be_bytes([g0, g1]),
+            u16::from_be_bytes([b0, b1]),
+        )),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+
+    #[test]
+    fn test_read_plte() {
+        let data = [255, 0, 0, 0, 255, 0, 0, 0, 255];
+        let palette = read_plte(&data, 0, 2, ColorType::Indexed).unwrap();
+        assert_eq!(palette, vec![[255, 0, 0], [0, 255, 0], [0, 0, 255]]);
+    }
+
+    #[test]
+    fn test_read_plte_invalid_length() {
+        let err = read_plte(&[255, 0, 0, 0], 0, 8, ColorType::Indexed).unwrap_err();
+        assert_matches!(
+            err,
+            PngDecodingError::InvalidPaletteLength { length: 4, .. }
+        );
+        let err = read_plte(&[], 0, 8, ColorType::Indexed).unwrap_err();
+        assert_matches!(
+            err,
+            PngDecodingError::InvalidPaletteLength { length: 0, .. }
+        );
+    }
+
+    #[test]
+    fn test_read_plte_too_large() {
+        // A 1-bit image can only use 2 palette entries.
+        let err = read_plte(&[0; 9], 0, 1, ColorType::Indexed).unwrap_err();
+        assert_matches!(
+            err,
+            PngDecodingError::PaletteTooLarge {
+                entries: 3,
+                bit_depth: 1,
+                ..
+            }
+        );
+        // No palette can have more than 256 entries.
+        let err = read_plte(&[0; 771], 0, 8, ColorType::Rgb).unwrap_err();
+        assert_matches!(err, PngDecodingError::PaletteTooLarge { entries: 257, .. });
+        assert!(read_plte(&[0; 9], 0, 1, ColorType::Rgb).is_ok());
+    }
+
+    #[test]
+    fn test_read_trns() {
+        assert_eq!(
+            read_trns(&[0, 128], ColorType::Indexed),
+            Some(Transparency::PaletteAlpha(vec![0, 128]))
+        );
+        assert_eq!(
+            read_trns(&[0, 1], ColorType::Grayscale),
+            Some(Transparency::Gray(1))
+        );
+        assert_eq!(
+            read_trns(&[0, 1, 0, 2, 0, 3], ColorType::Rgb),
+            Some(Transparency::Rgb(1, 2, 3))
+        );
+        assert_eq!(read_trns(&[0, 1], ColorType::Rgba), None);
+    }
+}
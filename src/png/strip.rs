@@ -0,0 +1,182 @@
+//! Removing metadata chunks from a PNG image.
+
+use super::chunks::{Chunk, Chunks};
+use super::embedded;
+use super::write::write_chunk;
+use super::PngDecodingError;
+use crate::exif::remove_private_tags;
+use crate::{ExifPolicy, StripPolicy};
+
+/// Ancillary chunks that are needed to show the image properly: color,
+/// transparency, physical size and animation.
+const DISPLAY_CHUNKS: &[&[u8; 4]] = &[
+    b"acTL", b"bKGD", b"cHRM", b"cICP", b"cLLI", b"fcTL", b"fdAT", b"gAMA", b"hIST", b"mDCv",
+    b"pHYs", b"sBIT", b"sPLT", b"sRGB", b"tRNS",
+];
+
+/// The types of ImageMagick's "Raw profile type" text chunks that hold, in
+/// hex, a copy of EXIF, XMP, IPTC or Photoshop data.
+const RAW_PROFILE_TYPES: &[&[u8]] = &[b"exif", b"app1", b"xmp", b"iptc", b"8bim"];
+
+/// Copy the PNG data, leaving out the metadata chunks that the policy doesn't
+/// keep. Critical chunks, and anything after the IEND chunk, are copied as
+/// they are.
+pub(crate) fn strip_png(buf: &[u8], policy: &StripPolicy) -> Result<Vec<u8>, PngDecodingError> {
+    let mut out = Vec::with_capacity(buf.len());
+    // Where we've copied up to.
+    let mut copied = 0;
+    for chunk in Chunks::new(buf) {
+        let chunk = chunk?;
+        let end = chunk.offset + chunk.total_len();
+        if &chunk.kind == b"eXIf" && policy.exif == ExifPolicy::RemovePrivate {
+            let mut data = chunk.data.to_vec();
+            remove_private_tags(&mut data);
+            out.extend_from_slice(&buf[copied..chunk.offset]);
+            write_chunk(&mut out, b"eXIf", &data);
+            copied = end;
+        } else if !keep(&chunk, policy) {
+            out.extend_from_slice(&buf[copied..chunk.offset]);
+            copied = end;
+        } else if &chunk.kind == b"iTXt"
+            && embedded::is_xmp(chunk.data)
+            && policy.exif == ExifPolicy::RemovePrivate
+        {
+            // An XMP packet that can't be decoded can't be cleaned up either,
+            // so it is left out.
+            out.extend_from_slice(&buf[copied..chunk.offset]);
+            if let Some(data) = embedded::remove_private_xmp(chunk.data) {
+                write_chunk(&mut out, b"iTXt", &data);
+            }
+            copied = end;
+        }
+    }
+    out.extend_from_slice(&buf[copied..]);
+    Ok(out)
+}
+
+/// Whether to keep a chunk.
+fn keep(chunk: &Chunk, policy: &StripPolicy) -> bool {
+    match &chunk.kind {
+        b"eXIf" => policy.exif != ExifPolicy::Drop,
+        b"iTXt" if embedded::is_xmp(chunk.data) => policy.xmp,
+        b"iCCP" => policy.icc_profile,
+        b"tEXt" | b"zTXt" | b"iTXt" if is_raw_profile(chunk.data) => {
+            policy.comments && policy.exif != ExifPolicy::RemovePrivate
+        }
+        b"tEXt" | b"zTXt" | b"iTXt" => policy.comments,
+        kind if chunk.is_critical() || DISPLAY_CHUNKS.contains(&kind) => true,
+        _ => policy.other,
+    }
+}
+
+/// Whether a text chunk is one of ImageMagick's raw profiles holding a copy
+/// of the metadata, which can't be edited like the original.
+fn is_raw_profile(data: &[u8]) -> bool {
+    let keyword = &data[..memchr::memchr(0, data).unwrap_or(data.len())];
+    keyword
+        .strip_prefix(b"Raw profile type ")
+        .is_some_and(|kind| {
+            RAW_PROFILE_TYPES
+                .iter()
+                .any(|t| kind.eq_ignore_ascii_case(t))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::tests::chunk;
+    use crate::png::{read_png_data, read_png_data_ref};
+    use crate::ReadOptions;
+    use crate::{exif, xmp};
+
+    /// The sample image, with an ICC profile, an XMP packet and a private
+    /// chunk added, and its eXIf chunk replaced by one with a GPS IFD.
+    fn sample_image() -> Vec<u8> {
+        let mut data = std::fs::read("src/watercolors.png").unwrap();
+        let exif = Chunks::new(&data)
+            .map(Result::unwrap)
+            .find(|c| &c.kind == b"eXIf")
+            .unwrap();
+        let range = exif.offset..exif.offset + exif.total_len();
+        data.splice(range, chunk(b"eXIf", &exif::tests::exif(false)));
+        let mut iccp = b"Profile\0\0".to_vec();
+        iccp.extend(embedded::tests::deflate(b"profile"));
+        let chunks = [
+            chunk(b"iCCP", &iccp),
+            chunk(b"iTXt", b"XML:com.adobe.xmp\0\0\0\0\0<x:xmpmeta/>"),
+            chunk(b"prIv", b"secret"),
+        ];
+        data.splice(33..33, chunks.concat());
+        data
+    }
+
+    fn kinds(data: &[u8]) -> Vec<String> {
+        Chunks::new(data)
+            .map(|c| String::from_utf8_lossy(&c.unwrap().kind).into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn test_strip_all() {
+        let data = sample_image();
+        let stripped = strip_png(&data, &StripPolicy::new()).unwrap();
+        assert_eq!(
+            kinds(&stripped),
+            ["IHDR", "iCCP", "cHRM", "bKGD", "IDAT", "IDAT", "IDAT", "IDAT", "IDAT", "IEND"]
+        );
+        let all = ReadOptions::new().exif(true).xmp(true).icc_profile(true);
+        let metadata = read_png_data_ref(&stripped, &all, &mut ()).unwrap();
+        assert_eq!((metadata.width, metadata.height), (400, 224));
+        assert_eq!(metadata.icc_profile.as_deref(), Some(&b"profile"[..]));
+        assert_eq!((metadata.exif, metadata.xmp), (None, None));
+
+        let policy = StripPolicy::new().comments(true).other(true);
+        let stripped = strip_png(&data, &policy).unwrap();
+        let metadata = read_png_data(&stripped).unwrap();
+        assert_eq!(metadata.comments, vec![b"Abstract watercolors".to_vec()]);
+        assert!(kinds(&stripped).contains(&"prIv".to_string()));
+        assert!(kinds(&stripped).contains(&"tIME".to_string()));
+        assert!(!kinds(&stripped).contains(&"iTXt".to_string()));
+    }
+
+    #[test]
+    fn test_strip_private_tags() {
+        let data = sample_image();
+        let stripped = strip_png(&data, &StripPolicy::private_tags_only()).unwrap();
+        assert_eq!(stripped.len(), data.len());
+        assert_eq!(kinds(&stripped), kinds(&data));
+        let mut expected = exif::tests::exif(false);
+        exif::remove_private_tags(&mut expected);
+        let options = ReadOptions::new().exif(true);
+        // The chunk's CRC has been worked out afresh.
+        let metadata = read_png_data_ref(&stripped, &options, &mut ()).unwrap();
+        assert_eq!(metadata.exif, Some(&expected[..]));
+    }
+
+    #[test]
+    fn test_strip_private_xmp() {
+        // A compressed XMP packet with GPS properties, and a copy of the EXIF
+        // data in an ImageMagick raw profile.
+        let mut data = std::fs::read("src/watercolors.png").unwrap();
+        let mut itxt = b"XML:com.adobe.xmp\0\x01\0\0\0".to_vec();
+        itxt.extend(embedded::tests::deflate(xmp::tests::XMP));
+        let raw = b"Raw profile type APP1\0\nAPP1\n      4\n45786966\n";
+        let chunks = [chunk(b"iTXt", &itxt), chunk(b"tEXt", raw)];
+        data.splice(33..33, chunks.concat());
+
+        let stripped = strip_png(&data, &StripPolicy::private_tags_only()).unwrap();
+        let options = ReadOptions::new().xmp(true);
+        let metadata = read_png_data_ref(&stripped, &options, &mut ()).unwrap();
+        let mut expected = xmp::tests::XMP.to_vec();
+        xmp::remove_private_properties(&mut expected);
+        assert_eq!(metadata.xmp.as_deref(), Some(&expected[..]));
+        // The raw profile is gone, but the comment is still there.
+        assert_eq!(metadata.comments, vec![b"Abstract watercolors"]);
+        assert_eq!(kinds(&stripped).len(), kinds(&data).len() - 1);
+
+        // Keeping the EXIF data as it is keeps the XMP packet as it is too.
+        let policy = StripPolicy::private_tags_only().exif(ExifPolicy::Keep);
+        assert_eq!(strip_png(&data, &policy).unwrap(), data);
+    }
+}
@@ -0,0 +1,280 @@
+//! Checking a whole PNG file, rather than just its header: every chunk's
+//! CRC, the IDAT chunks, and the size of the image data once inflated.
+
+use miniz_oxide::inflate::stream::{inflate, InflateState};
+use miniz_oxide::{DataFormat, MZFlush, MZStatus};
+
+use super::chunks::Chunks;
+use super::PngDecodingError;
+
+/// The Adam7 passes, as (x start, y start, x step, y step).
+const ADAM7: [(u64, u64, u64, u64); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+/// Where we are in the run of IDAT chunks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Idat {
+    NotSeen,
+    Reading,
+    Done,
+}
+
+/// Check that the PNG data is complete and uncorrupted: every chunk's CRC
+/// matches, the IDAT chunks are together, there is an IEND chunk, and the
+/// image data inflates to the size the IHDR chunk calls for.
+pub(crate) fn validate_png(buf: &[u8]) -> Result<(), PngDecodingError> {
+    let mut chunks = Chunks::new(buf);
+    let ihdr = match chunks.next().transpose()? {
+        Some(chunk) if &chunk.kind == b"IHDR" => chunk,
+        _ => return Err(PngDecodingError::MissingIHDR),
+    };
+    let expected = image_data_size(ihdr.data, ihdr.offset)?;
+
+    let mut inflater = Inflater::new(expected);
+    let mut idat = Idat::NotSeen;
+    for chunk in chunks {
+        let chunk = chunk?;
+        let offset = chunk.offset;
+        match &chunk.kind {
+            b"IDAT" if idat == Idat::Done => {
+                return Err(PngDecodingError::NonContiguousImageData { offset });
+            }
+            b"IDAT" => {
+                idat = Idat::Reading;
+                inflater.feed(chunk.data, offset)?;
+            }
+            b"IEND" if idat == Idat::NotSeen => {
+                return Err(PngDecodingError::MissingImageData { offset });
+            }
+            b"IEND" => return inflater.finish(offset),
+            _ if idat == Idat::Reading => idat = Idat::Done,
+            _ => {}
+        }
+    }
+    Err(PngDecodingError::MissingIEND { offset: buf.len() })
+}
+
+/// The size of the image data once inflated, from the IHDR chunk: each row of
+/// each pass has a filter type byte and then its pixels, padded to a byte.
+fn image_data_size(ihdr: &[u8], offset: usize) -> Result<u64, PngDecodingError> {
+    let &[w0, w1, w2, w3, h0, h1, h2, h3, bit_depth, color_type, _, _, interlace] = ihdr else {
+        return Err(PngDecodingError::InvalidIHDRLength {
+            offset,
+            length: ihdr.len() as u32,
+        });
+    };
+    let width = u64::from(u32::from_be_bytes([w0, w1, w2, w3]));
+    let height = u64::from(u32::from_be_bytes([h0, h1, h2, h3]));
+    let channels = match (color_type, bit_depth) {
+        (0, 1 | 2 | 4 | 8 | 16) => 1,
+        (3, 1 | 2 | 4 | 8) => 1,
+        (4, 8 | 16) => 2,
+        (2, 8 | 16) => 3,
+        (6, 8 | 16) => 4,
+        _ => {
+            return Err(PngDecodingError::InvalidBitDepth {
+                color_type,
+                bit_depth,
+            })
+        }
+    };
+    let bits_per_pixel = channels * u64::from(bit_depth);
+    let size = |width: u64, height: u64| {
+        if width == 0 || height == 0 {
+            0
+        } else {
+            height * (1 + (width * bits_per_pixel).div_ceil(8))
+        }
+    };
+    Ok(if interlace == 0 {
+        size(width, height)
+    } else {
+        ADAM7
+            .iter()
+            .map(|&(x0, y0, dx, dy)| {
+                let pass_width = width.saturating_sub(x0).div_ceil(dx);
+                let pass_height = height.saturating_sub(y0).div_ceil(dy);
+                size(pass_width, pass_height)
+            })
+            .sum()
+    })
+}
+
+/// Inflates the image data a chunk at a time, counting the bytes rather than
+/// keeping them.
+struct Inflater {
+    state: Box<InflateState>,
+    expected: u64,
+    actual: u64,
+    done: bool,
+}
+
+impl Inflater {
+    fn new(expected: u64) -> Self {
+        Inflater {
+            state: InflateState::new_boxed(DataFormat::Zlib),
+            expected,
+            actual: 0,
+            done: false,
+        }
+    }
+
+    /// Inflate the data of the IDAT chunk at `offset`.
+    fn feed(&mut self, mut data: &[u8], offset: usize) -> Result<(), PngDecodingError> {
+        while !data.is_empty() {
+            if self.done {
+                // There is more data after the end of the zlib stream.
+                return Err(PngDecodingError::InvalidImageData { offset });
+            }
+            let (consumed, written) = self.inflate(data, offset)?;
+            if consumed == 0 && written == 0 {
+                return Err(PngDecodingError::InvalidImageData { offset });
+            }
+            data = &data[consumed..];
+        }
+        Ok(())
+    }
+
+    /// Finish inflating, on reaching the IEND chunk at `offset`.
+    fn finish(mut self, offset: usize) -> Result<(), PngDecodingError> {
+        // Flush out what the inflater still has buffered.
+        while !self.done {
+            if self.inflate(&[], offset)?.1 == 0 {
+                // The zlib stream was cut short.
+                return Err(PngDecodingError::InvalidImageData { offset });
+            }
+        }
+        if self.actual != self.expected {
+            Err(self.size_mismatch())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Inflate some of the data, returning how many bytes it consumed and
+    /// how many it wrote.
+    fn inflate(&mut self, data: &[u8], offset: usize) -> Result<(usize, usize), PngDecodingError> {
+        let mut output = [0; 32 * 1024];
+        let result = inflate(&mut self.state, data, &mut output, MZFlush::None);
+        self.actual += result.bytes_written as u64;
+        if self.actual > self.expected {
+            // Stop now, rather than inflating a decompression bomb.
+            return Err(self.size_mismatch());
+        }
+        match result.status {
+            Ok(MZStatus::StreamEnd) => self.done = true,
+            Ok(MZStatus::Ok) => {}
+            _ => return Err(PngDecodingError::InvalidImageData { offset }),
+        }
+        Ok((result.bytes_consumed, result.bytes_written))
+    }
+
+    fn size_mismatch(&self) -> PngDecodingError {
+        PngDecodingError::ImageDataSizeMismatch {
+            expected: self.expected,
+            actual: self.actual,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::embedded::tests::deflate;
+    use crate::png::tests::chunk;
+    use assert_matches::assert_matches;
+
+    /// A 3x2 8-bit grayscale image, with its IDAT split in two.
+    fn image(idat: &[u8], between: &[u8], end: &[u8]) -> Vec<u8> {
+        let mut buf = b"\x89PNG\r\n\x1a\n".to_vec();
+        buf.extend(chunk(b"IHDR", b"\0\0\0\x03\0\0\0\x02\x08\0\0\0\0"));
+        let (first, second) = idat.split_at(idat.len() / 2);
+        buf.extend(chunk(b"IDAT", first));
+        buf.extend_from_slice(between);
+        buf.extend(chunk(b"IDAT", second));
+        buf.extend_from_slice(end);
+        buf
+    }
+
+    #[test]
+    fn test_validate_png() {
+        let data = std::fs::read("src/watercolors.png").unwrap();
+        assert_eq!(validate_png(&data), Ok(()));
+
+        let idat = deflate(&[0; 8]);
+        let iend = chunk(b"IEND", b"");
+        assert_eq!(validate_png(&image(&idat, b"", &iend)), Ok(()));
+        assert_eq!(
+            validate_png(&image(&idat, b"", b"")),
+            Err(PngDecodingError::MissingIEND { offset: 68 })
+        );
+        assert_eq!(
+            validate_png(&image(&idat, &chunk(b"tEXt", b"a\0b"), &iend)),
+            Err(PngDecodingError::NonContiguousImageData { offset: 65 })
+        );
+        assert_eq!(
+            validate_png(&image(&deflate(&[0; 9]), b"", &iend)),
+            Err(PngDecodingError::ImageDataSizeMismatch {
+                expected: 8,
+                actual: 9
+            })
+        );
+        assert_eq!(
+            validate_png(&image(&deflate(&[0; 7]), b"", &iend)),
+            Err(PngDecodingError::ImageDataSizeMismatch {
+                expected: 8,
+                actual: 7
+            })
+        );
+        assert_eq!(
+            validate_png(&image(&idat[..idat.len() - 1], b"", &iend)),
+            Err(PngDecodingError::InvalidImageData { offset: 67 })
+        );
+
+        // A bad CRC anywhere in the file.
+        let mut data = data;
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        let iend = data.len() - 12;
+        assert_matches!(
+            validate_png(&data),
+            Err(PngDecodingError::InvalidChunkCrc { kind, offset, .. })
+                if &kind == b"IEND" && offset == iend
+        );
+    }
+
+    #[test]
+    fn test_image_data_size() {
+        let ihdr = |width: u32, height: u32, bit_depth: u8, color_type: u8, interlace: u8| {
+            let mut ihdr = width.to_be_bytes().to_vec();
+            ihdr.extend(height.to_be_bytes());
+            ihdr.extend([bit_depth, color_type, 0, 0, interlace]);
+            image_data_size(&ihdr, 8)
+        };
+        assert_eq!(ihdr(3, 2, 8, 0, 0), Ok(8));
+        assert_eq!(ihdr(3, 2, 16, 6, 0), Ok(2 * (1 + 24)));
+        assert_eq!(ihdr(9, 1, 1, 3, 0), Ok(3));
+        // Adam7 passes of a 1x1 image: only the first has any pixels.
+        assert_eq!(ihdr(1, 1, 8, 2, 1), Ok(4));
+        // The passes of an 8x8 image are 1x1, 1x1, 2x1, 2x2, 4x2, 4x4 and
+        // 8x4 pixels.
+        assert_eq!(
+            ihdr(8, 8, 8, 0, 1),
+            Ok(2 + 2 + 3 + (2 * 3) + (2 * 5) + (4 * 5) + (4 * 9))
+        );
+        assert_eq!(
+            ihdr(1, 1, 4, 2, 0),
+            Err(PngDecodingError::InvalidBitDepth {
+                color_type: 2,
+                bit_depth: 4
+            })
+        );
+    }
+}
@@ -0,0 +1,224 @@
+//! Writing a copy of a PNG image with its comment chunks changed.
+
+use super::chunks::Chunks;
+use super::PngDecodingError;
+use crate::exif;
+
+/// The keyword of the tEXt chunks that hold comments.
+const COMMENT_KEYWORD: &[u8] = b"comment";
+
+/// The most data a chunk can hold.
+const MAX_CHUNK_DATA: usize = (1 << 31) - 1;
+
+/// Copy the PNG data, replacing its `comment` tEXt chunks with one for each
+/// of the comments. Everything else, including the image data, is copied as
+/// it is.
+///
+/// The new chunks go where the first old one was or, if there wasn't one,
+/// before the image data, so that readers that stop there still see them.
+/// Data without an IEND chunk has been cut short, so there's nowhere safe to
+/// put them, and it is an error.
+pub(crate) fn write_png_comments(
+    buf: &[u8],
+    comments: &[impl AsRef<[u8]>],
+) -> Result<Vec<u8>, PngDecodingError> {
+    let mut chunks = Vec::new();
+    for comment in comments {
+        let comment = comment.as_ref();
+        let data = [COMMENT_KEYWORD, b"\0", comment].concat();
+        if data.len() > MAX_CHUNK_DATA {
+            return Err(PngDecodingError::CommentTooLong {
+                length: comment.len(),
+            });
+        }
+        write_chunk(&mut chunks, b"tEXt", &data);
+    }
+
+    // Find the old comments, and where the image data starts.
+    let mut old = Vec::new();
+    let mut image_data = None;
+    let mut ended = false;
+    for chunk in Chunks::new(buf) {
+        let chunk = chunk?;
+        ended = &chunk.kind == b"IEND";
+        let is_comment = &chunk.kind == b"tEXt"
+            && chunk.data.starts_with(COMMENT_KEYWORD)
+            && chunk.data.get(COMMENT_KEYWORD.len()) == Some(&0);
+        if is_comment {
+            old.push(chunk.offset..chunk.offset + chunk.total_len());
+        } else if matches!(&chunk.kind, b"IDAT" | b"IEND") {
+            image_data.get_or_insert(chunk.offset);
+        }
+    }
+    let insert_at = match (old.first(), image_data) {
+        (Some(first), _) if ended => first.start,
+        (None, Some(offset)) if ended => offset,
+        _ => return Err(PngDecodingError::MissingIEND { offset: buf.len() }),
+    };
+
+    let mut out = Vec::with_capacity(buf.len() + chunks.len());
+    out.extend_from_slice(&buf[..insert_at]);
+    out.extend_from_slice(&chunks);
+    // Leave out the old comments, but keep everything between them.
+    let mut copied = insert_at;
+    for range in old {
+        out.extend_from_slice(&buf[copied..range.start]);
+        copied = range.end;
+    }
+    out.extend_from_slice(&buf[copied..]);
+    Ok(out)
+}
+
+/// Copy the PNG data with the orientation in its eXIf chunk changed, and the
+/// chunk's CRC worked out afresh. Nothing moves, so everything else is copied
+/// as it is. Returns `None` if there is no eXIf chunk, or it has no
+/// orientation.
+pub(crate) fn set_png_orientation(
+    buf: &[u8],
+    orientation: u16,
+) -> Result<Option<Vec<u8>>, PngDecodingError> {
+    for chunk in Chunks::new(buf) {
+        let chunk = chunk?;
+        if &chunk.kind != b"eXIf" {
+            continue;
+        }
+        let mut out = buf.to_vec();
+        // The chunk's type and data, which the CRC covers.
+        let start = chunk.offset + 4;
+        let end = start + 4 + chunk.data.len();
+        if !exif::set_orientation(&mut out[start + 4..end], orientation) {
+            return Ok(None);
+        }
+        let crc = crc32fast::hash(&out[start..end]);
+        out[end..end + 4].copy_from_slice(&crc.to_be_bytes());
+        return Ok(Some(out));
+    }
+    Ok(None)
+}
+
+/// Append a chunk to `out`, with its CRC.
+pub(super) fn write_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(kind);
+    hasher.update(data);
+    out.extend_from_slice(&hasher.finalize().to_be_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::chunks::Chunk;
+    use crate::png::read_png_data;
+    use crate::png::tests::chunk;
+
+    #[test]
+    fn test_write_comments() {
+        let data = std::fs::read("src/watercolors.png").unwrap();
+        let written = write_png_comments(&data, &["Sunset", "Harbour"]).unwrap();
+        let metadata = read_png_data(&written).unwrap();
+        assert_eq!(
+            metadata.comments,
+            vec![b"Sunset".to_vec(), b"Harbour".to_vec()]
+        );
+        // The other tEXt chunks, and everything else, are still there.
+        let chunks = Chunks::new(&written)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(chunks.iter().filter(|c| &c.kind == b"tEXt").count(), 5);
+        assert_eq!(&written[..33], &data[..33]);
+
+        // The new comments are where the old one was, after the image data.
+        let kinds = |chunks: &[Chunk]| chunks.iter().map(|c| c.kind).collect::<Vec<_>>();
+        let original = Chunks::new(&data).collect::<Result<Vec<_>, _>>().unwrap();
+        let position = original
+            .iter()
+            .position(|c| c.data.starts_with(b"comment\0"))
+            .unwrap();
+        assert!(original[..position].iter().any(|c| &c.kind == b"IDAT"));
+        assert_eq!(chunks[position].data, b"comment\0Sunset");
+        assert_eq!(chunks[position + 1].data, b"comment\0Harbour");
+        assert_eq!(kinds(&chunks[..position]), kinds(&original[..position]));
+        assert_eq!(
+            kinds(&chunks[position + 2..]),
+            kinds(&original[position + 1..])
+        );
+
+        let removed = write_png_comments(&data, &[] as &[&[u8]]).unwrap();
+        assert!(read_png_data(&removed).unwrap().comments.is_empty());
+        let old = chunk(b"tEXt", b"comment\0Abstract watercolors");
+        assert_eq!(removed.len(), data.len() - old.len());
+
+        // Adding a comment to an image that had none puts it before the
+        // image data.
+        let rewritten = write_png_comments(&removed, &["Abstract watercolors"]).unwrap();
+        let chunks = Chunks::new(&rewritten)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(&chunks[4].kind, b"tEXt");
+        assert_eq!(&chunks[5].kind, b"IDAT");
+        assert_eq!(rewritten.len(), data.len());
+    }
+
+    #[test]
+    fn test_set_orientation() {
+        let mut data = std::fs::read("src/watercolors.png").unwrap();
+        let exif = Chunks::new(&data)
+            .map(Result::unwrap)
+            .find(|c| &c.kind == b"eXIf")
+            .unwrap();
+        let (offset, len) = (exif.offset, exif.total_len());
+        // The sample image's EXIF data has no orientation.
+        assert_eq!(set_png_orientation(&data, 1), Ok(None));
+
+        data.splice(
+            offset..offset + len,
+            chunk(b"eXIf", &exif::tests::exif(false)),
+        );
+        let written = set_png_orientation(&data, 1).unwrap().unwrap();
+        assert_eq!(written.len(), data.len());
+        let mut expected = exif::tests::exif(false);
+        expected[18] = 1;
+        let chunks = Chunks::new(&written)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let exif = chunks.iter().find(|c| &c.kind == b"eXIf").unwrap();
+        assert_eq!(exif.data, expected);
+        assert_eq!(exif.crc, exif.calculate_crc());
+    }
+
+    #[test]
+    fn test_trailing_bytes() {
+        let mut data = std::fs::read("src/watercolors.png").unwrap();
+        data.extend_from_slice(b"PK\x03\x04");
+        let written = write_png_comments(&data, &["Sunset"]).unwrap();
+        assert!(written.ends_with(b"IEND\xae\x42\x60\x82PK\x03\x04"));
+    }
+
+    #[test]
+    fn test_missing_iend() {
+        let data = std::fs::read("src/watercolors.png").unwrap();
+        // Cut off the IEND chunk, and then the last IDAT chunk too.
+        let without_iend = &data[..data.len() - 12];
+        assert_eq!(
+            write_png_comments(without_iend, &["Sunset"]),
+            Err(PngDecodingError::MissingIEND {
+                offset: without_iend.len()
+            })
+        );
+        let last_idat = Chunks::new(&data)
+            .map(Result::unwrap)
+            .filter(|c| &c.kind == b"IDAT")
+            .last()
+            .unwrap();
+        let truncated = &data[..last_idat.offset];
+        assert_eq!(
+            write_png_comments(truncated, &["Sunset"]),
+            Err(PngDecodingError::MissingIEND {
+                offset: truncated.len()
+            })
+        );
+    }
+}
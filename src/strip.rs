@@ -0,0 +1,115 @@
+//! The policy for [`crate::strip`]: which kinds of metadata to keep.
+
+/// What to do with an image's EXIF data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum ExifPolicy {
+    Keep,
+    /// Remove the GPS IFD and the camera and lens serial numbers, keeping
+    /// the rest, such as the orientation and the camera model. Serial
+    /// numbers inside the maker note, whose format is up to the camera maker,
+    /// are not found.
+    ///
+    /// XMP repeats the EXIF data, so the GPS properties and serial numbers
+    /// are removed from a kept XMP packet too. Copies of the metadata that
+    /// can't be edited like that are left out, even if the rest of the policy
+    /// keeps them: the extended XMP segments and Photoshop's APP13 segment
+    /// (which holds IPTC data) of a JPEG, and ImageMagick's "Raw profile
+    /// type" text chunks in a PNG.
+    RemovePrivate,
+    #[default]
+    Drop,
+}
+
+/// Which kinds of metadata [`crate::strip`] keeps.
+///
+/// Whatever is needed to display the image is always kept: the JFIF and
+/// Adobe segments and MPF index of a JPEG, and the critical, color and
+/// animation chunks of a PNG.
+///
+/// # Examples
+///
+/// ```
+/// use pb_imgsize::{ExifPolicy, StripPolicy};
+///
+/// // Remove everything but the ICC profile and the EXIF data, and take the
+/// // location and serial numbers out of that.
+/// let policy = StripPolicy::new().exif(ExifPolicy::RemovePrivate);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StripPolicy {
+    pub(crate) exif: ExifPolicy,
+    pub(crate) xmp: bool,
+    pub(crate) icc_profile: bool,
+    pub(crate) comments: bool,
+    pub(crate) other: bool,
+}
+
+impl Default for StripPolicy {
+    fn default() -> Self {
+        StripPolicy {
+            exif: ExifPolicy::Drop,
+            xmp: false,
+            icc_profile: true,
+            comments: false,
+            other: false,
+        }
+    }
+}
+
+impl StripPolicy {
+    /// A policy that removes all the metadata except the ICC profile, which
+    /// the colors depend on.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A policy that only removes the location and serial numbers, from the
+    /// EXIF data and XMP packet, and keeps everything else that doesn't
+    /// carry another copy of them. See [`ExifPolicy::RemovePrivate`].
+    pub fn private_tags_only() -> Self {
+        StripPolicy {
+            exif: ExifPolicy::RemovePrivate,
+            xmp: true,
+            icc_profile: true,
+            comments: true,
+            other: true,
+        }
+    }
+
+    /// What to do with the EXIF data, in a JPEG APP1 segment or a PNG eXIf
+    /// chunk.
+    pub fn exif(mut self, exif: ExifPolicy) -> Self {
+        self.exif = exif;
+        self
+    }
+
+    /// Whether to keep the XMP packet, in JPEG APP1 segments or a PNG iTXt
+    /// chunk.
+    pub fn xmp(mut self, xmp: bool) -> Self {
+        self.xmp = xmp;
+        self
+    }
+
+    /// Whether to keep the ICC profile, in JPEG APP2 segments or a PNG iCCP
+    /// chunk.
+    pub fn icc_profile(mut self, icc_profile: bool) -> Self {
+        self.icc_profile = icc_profile;
+        self
+    }
+
+    /// Whether to keep comments and text: JPEG COM segments, and PNG tEXt,
+    /// zTXt and iTXt chunks other than XMP.
+    pub fn comments(mut self, comments: bool) -> Self {
+        self.comments = comments;
+        self
+    }
+
+    /// Whether to keep everything else: other JPEG APPn segments, such as
+    /// IPTC data in APP13, and other PNG ancillary chunks, such as tIME and
+    /// private chunks.
+    pub fn other(mut self, other: bool) -> Self {
+        self.other = other;
+        self
+    }
+}
@@ -0,0 +1,294 @@
+//! A minimal reader for TIFF-structured data, as used by EXIF and MPF.
+//!
+//! Offsets are relative to the start of the TIFF header, and every read is
+//! bounds-checked: anything that points outside the data reads as `None`.
+
+/// TIFF-structured data, starting with its header.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Tiff<'a> {
+    pub data: &'a [u8],
+    pub big_endian: bool,
+}
+
+/// An IFD (Image File Directory) within TIFF data.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Ifd<'a> {
+    tiff: Tiff<'a>,
+    offset: usize,
+    count: u16,
+    depth: usize,
+}
+
+/// A single 12-byte entry in an IFD.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Entry {
+    pub tag: u16,
+    pub kind: u16,
+    pub count: u32,
+    /// The offset of the entry itself, relative to the TIFF header.
+    pub offset: usize,
+}
+
+/// TIFF field type for SHORT (u16) values.
+pub(crate) const SHORT: u16 = 3;
+/// TIFF field type for LONG (u32) values.
+pub(crate) const LONG: u16 = 4;
+/// TIFF field type for UNDEFINED (raw byte) values.
+pub(crate) const UNDEFINED: u16 = 7;
+
+/// The size of one element of a TIFF field type, or `None` for an unknown
+/// type.
+pub(crate) fn type_size(kind: u16) -> Option<usize> {
+    match kind {
+        // BYTE, ASCII, SBYTE, UNDEFINED
+        1 | 2 | 6 | 7 => Some(1),
+        // SHORT, SSHORT
+        3 | 8 => Some(2),
+        // LONG, SLONG, FLOAT, IFD
+        4 | 9 | 11 | 13 => Some(4),
+        // RATIONAL, SRATIONAL, DOUBLE
+        5 | 10 | 12 => Some(8),
+        _ => None,
+    }
+}
+
+impl<'a> Tiff<'a> {
+    /// Read the TIFF header ("II*\0" or "MM\0*") at the start of the data.
+    pub fn new(data: &'a [u8]) -> Option<Self> {
+        let big_endian = match data.get(..4)? {
+            b"II*\0" => false,
+            b"MM\0*" => true,
+            _ => return None,
+        };
+        Some(Tiff { data, big_endian })
+    }
+
+    pub fn u16_at(&self, offset: usize) -> Option<u16> {
+        let bytes = self.data.get(offset..offset.checked_add(2)?)?;
+        let bytes = [bytes[0], bytes[1]];
+        Some(if self.big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    }
+
+    pub fn u32_at(&self, offset: usize) -> Option<u32> {
+        let bytes = self.data.get(offset..offset.checked_add(4)?)?;
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        Some(if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    }
+
+    /// The first IFD, whose offset is given in the header.
+    pub fn first_ifd(&self) -> Option<Ifd<'a>> {
+        self.ifd(self.u32_at(4)? as usize)
+    }
+
+    /// Encode a u16 in the data's byte order.
+    pub fn u16_bytes(&self, value: u16) -> [u8; 2] {
+        if self.big_endian {
+            value.to_be_bytes()
+        } else {
+            value.to_le_bytes()
+        }
+    }
+
+    /// Encode a u32 in the data's byte order.
+    pub fn u32_bytes(&self, value: u32) -> [u8; 4] {
+        if self.big_endian {
+            value.to_be_bytes()
+        } else {
+            value.to_le_bytes()
+        }
+    }
+
+    /// The IFD at the given offset, at depth 1.
+    pub fn ifd(&self, offset: usize) -> Option<Ifd<'a>> {
+        let count = self.u16_at(offset)?;
+        // The entries are followed by the 4-byte offset of the next IFD.
+        let end = offset.checked_add(2 + 12 * usize::from(count) + 4)?;
+        if end > self.data.len() {
+            return None;
+        }
+        Some(Ifd {
+            tiff: *self,
+            offset,
+            count,
+            depth: 1,
+        })
+    }
+}
+
+impl<'a> Ifd<'a> {
+    /// The offset of the IFD, relative to the TIFF header.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// How many IFDs were followed to get to this one, counting itself: 1 for
+    /// IFD0, 2 for IFD1 or the Exif IFD, and so on. Walkers check this against
+    /// [`crate::Limits::max_ifd_depth`].
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// The range the IFD takes up: its entry count, entries and the offset of
+    /// the next IFD.
+    pub fn range(&self) -> std::ops::Range<usize> {
+        self.offset..self.offset + 2 + 12 * usize::from(self.count) + 4
+    }
+
+    /// The entries in this IFD.
+    pub fn entries(&self) -> impl Iterator<Item = Entry> + 'a {
+        let tiff = self.tiff;
+        let start = self.offset + 2;
+        (0..usize::from(self.count)).map(move |i| {
+            let offset = start + 12 * i;
+            // These reads can't fail: `Tiff::ifd` checked that the entries
+            // are all inside the data.
+            Entry {
+                tag: tiff.u16_at(offset).unwrap_or_default(),
+                kind: tiff.u16_at(offset + 2).unwrap_or_default(),
+                count: tiff.u32_at(offset + 4).unwrap_or_default(),
+                offset,
+            }
+        })
+    }
+
+    /// The entry with the given tag, if there is one.
+    pub fn find(&self, tag: u16) -> Option<Entry> {
+        self.entries().find(|entry| entry.tag == tag)
+    }
+
+    /// The value of the entry with the given tag, if there is one and it is a
+    /// single SHORT or LONG.
+    pub fn find_u32(&self, tag: u16) -> Option<u32> {
+        self.find(tag)?.read_u32(&self.tiff)
+    }
+
+    /// The next IFD in the chain, if there is one.
+    pub fn next(&self) -> Option<Ifd<'a>> {
+        let offset = self
+            .tiff
+            .u32_at(self.offset + 2 + 12 * usize::from(self.count))?;
+        if offset == 0 {
+            None
+        } else {
+            self.follow(offset)
+        }
+    }
+
+    /// The IFD that the entry with the given tag points to, such as the Exif
+    /// or GPS IFD.
+    pub fn sub_ifd(&self, tag: u16) -> Option<Ifd<'a>> {
+        self.follow(self.find_u32(tag)?)
+    }
+
+    /// The IFD at the given offset, one deeper than this one.
+    fn follow(&self, offset: u32) -> Option<Ifd<'a>> {
+        let ifd = self.tiff.ifd(offset as usize)?;
+        Some(Ifd {
+            depth: self.depth + 1,
+            ..ifd
+        })
+    }
+}
+
+impl Entry {
+    /// The offset of the entry's 4-byte value field, which holds either the
+    /// value itself, or the offset of the value if it doesn't fit.
+    pub fn value_offset(&self) -> usize {
+        self.offset + 8
+    }
+
+    /// Read the entry's value as a u32, if it is a single SHORT or LONG.
+    pub fn read_u32(&self, tiff: &Tiff) -> Option<u32> {
+        match (self.kind, self.count) {
+            (SHORT, 1) => tiff.u16_at(self.value_offset()).map(u32::from),
+            (LONG, 1) => tiff.u32_at(self.value_offset()),
+            _ => None,
+        }
+    }
+
+    /// The entry's value as raw bytes, given the size of one element of its
+    /// type.
+    pub fn read_bytes<'a>(&self, tiff: &Tiff<'a>, element_size: usize) -> Option<&'a [u8]> {
+        tiff.data.get(self.value_range(tiff, element_size)?)
+    }
+
+    /// Where the entry's value is, given the size of one element of its type.
+    /// This is inside the entry itself if the value fits in 4 bytes.
+    pub fn value_range(&self, tiff: &Tiff, element_size: usize) -> Option<std::ops::Range<usize>> {
+        let len = element_size.checked_mul(self.count as usize)?;
+        let start = if len <= 4 {
+            self.value_offset()
+        } else {
+            tiff.u32_at(self.value_offset())? as usize
+        };
+        Some(start..start.checked_add(len)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_ifd_chain() {
+        // Little-endian header, then IFD0 at offset 8 with one entry, then
+        // IFD1 at offset 26 with two entries.
+        let data = [
+            b"II*\0\x08\0\0\0".as_slice(),
+            b"\x01\0\x12\x01\x03\0\x01\0\0\0\x06\0\0\0\x1a\0\0\0",
+            b"\x02\0\x01\x02\x04\0\x01\0\0\0\x44\x33\x22\x11",
+            b"\x02\x02\x03\0\x02\0\0\0\x07\0\x09\0\0\0\0\0",
+        ]
+        .concat();
+        let tiff = Tiff::new(&data).unwrap();
+        let ifd0 = tiff.first_ifd().unwrap();
+        assert_eq!(ifd0.find_u32(0x0112), Some(6));
+        assert_eq!(ifd0.find_u32(0x0201), None);
+
+        let ifd1 = ifd0.next().unwrap();
+        assert_eq!(ifd1.find_u32(0x0201), Some(0x11223344));
+        // Two SHORTs fit in the value field, but aren't a single u32.
+        assert_eq!(ifd1.find_u32(0x0202), None);
+        let entry = ifd1.find(0x0202).unwrap();
+        assert_eq!(entry.read_bytes(&tiff, 2), Some(b"\x07\0\x09\0".as_slice()));
+        assert!(ifd1.next().is_none());
+        assert_eq!((ifd0.depth(), ifd1.depth()), (1, 2));
+    }
+
+    #[test]
+    fn test_big_endian() {
+        let data = b"MM\0*\0\0\0\x08\0\x01\x01\x12\0\x03\0\0\0\x01\0\x03\0\0\0\0\0\0\0\0";
+        let tiff = Tiff::new(data).unwrap();
+        assert_eq!(tiff.first_ifd().unwrap().find_u32(0x0112), Some(3));
+    }
+
+    #[test]
+    fn test_out_of_bounds() {
+        assert!(Tiff::new(b"II*").is_none());
+        assert!(Tiff::new(b"XX*\0\x08\0\0\0").is_none());
+
+        // IFD0 claims 2 entries, but there is only room for one.
+        let data = b"II*\0\x08\0\0\0\x02\0\x12\x01\x03\0\x01\0\0\0\x06\0\0\0\0\0\0\0";
+        let tiff = Tiff::new(data).unwrap();
+        assert!(tiff.first_ifd().is_none());
+
+        // The next IFD is past the end of the data.
+        let data = b"II*\0\x08\0\0\0\x01\0\x12\x01\x03\0\x01\0\0\0\x06\0\0\0\xff\0\0\0";
+        let tiff = Tiff::new(data).unwrap();
+        assert!(tiff.first_ifd().unwrap().next().is_none());
+
+        // An entry whose value points past the end of the data.
+        let data = b"II*\0\x08\0\0\0\x01\0\x0e\x01\x02\0\x10\0\0\0\xff\0\0\0\0\0\0\0";
+        let tiff = Tiff::new(data).unwrap();
+        let entry = tiff.first_ifd().unwrap().find(0x010e).unwrap();
+        assert_eq!(entry.read_bytes(&tiff, 1), None);
+    }
+}
@@ -0,0 +1,68 @@
+//! Callbacks for the segments and chunks seen while reading an image.
+
+use crate::jpeg::Segment;
+use crate::png::Chunk;
+
+/// Whether the reader should carry on after a visitor has been called.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Flow {
+    Continue,
+    /// Stop reading. If the dimensions have been found, the metadata read so
+    /// far is returned, which includes the segment or chunk just visited.
+    /// Otherwise the read fails, as it would for an image with no dimensions:
+    /// with [`JpegDecodingError::NoSofMarker`] for a JPEG stopped before its
+    /// SOF segment, or [`PngDecodingError::MissingIHDR`] for a PNG stopped
+    /// before its IHDR chunk.
+    ///
+    /// [`JpegDecodingError::NoSofMarker`]: crate::JpegDecodingError::NoSofMarker
+    /// [`PngDecodingError::MissingIHDR`]: crate::PngDecodingError::MissingIHDR
+    Stop,
+}
+
+/// A visitor that is called for each JPEG segment or PNG chunk as the image
+/// is read, so that metadata this crate doesn't decode can be extracted in
+/// the same pass.
+///
+/// Both methods do nothing by default, so only the one for the format of
+/// interest needs to be implemented.
+///
+/// # Example
+///
+/// ```
+/// use pb_imgsize::jpeg::{Marker, Segment};
+/// use pb_imgsize::{Flow, MetadataVisitor};
+///
+/// #[derive(Default)]
+/// struct App1Finder(Option<usize>);
+///
+/// impl MetadataVisitor for App1Finder {
+///     fn visit_jpeg_segment(&mut self, segment: &Segment) -> Flow {
+///         if segment.marker == Marker::App(1) {
+///             self.0 = Some(segment.offset);
+///         }
+///         Flow::Continue
+///     }
+/// }
+///
+/// let data = include_bytes!("buttercups.jpg");
+/// let mut finder = App1Finder::default();
+/// pb_imgsize::read_bytes_with_visitor(data, &mut finder).unwrap();
+/// assert_eq!(finder.0, Some(0x22));
+/// ```
+pub trait MetadataVisitor {
+    /// Called for each segment up to the first SOS segment, before the reader
+    /// handles it.
+    fn visit_jpeg_segment(&mut self, _segment: &Segment) -> Flow {
+        Flow::Continue
+    }
+
+    /// Called for each chunk up to and including IEND, after its CRC has been
+    /// checked but before the reader handles it.
+    fn visit_png_chunk(&mut self, _chunk: &Chunk) -> Flow {
+        Flow::Continue
+    }
+}
+
+/// A visitor that does nothing.
+impl MetadataVisitor for () {}
@@ -0,0 +1,128 @@
+//! How strictly to read images, and the warnings for problems that reading
+//! carried on past.
+
+use std::fmt::Display;
+
+/// How strictly to follow the JPEG and PNG specifications when reading.
+///
+/// The levels are ordered from least to most forgiving.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+#[non_exhaustive]
+pub enum Strictness {
+    /// Every violation of the specification that the reader notices is an
+    /// error.
+    Strict,
+    /// Carry on past minor problems, such as garbage between JPEG segments, a
+    /// malformed or misplaced ancillary PNG chunk or an invalid palette, but
+    /// treat damage such as a bad CRC as an error.
+    #[default]
+    Normal,
+    /// Carry on past anything that leaves the data readable, including bad
+    /// CRCs.
+    Lenient,
+}
+
+/// What kind of problem a [`Warning`] is about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum WarningKind {
+    /// Bytes between JPEG segments that aren't part of any segment.
+    ExtraneousBytes,
+    /// A PNG chunk whose CRC doesn't match its contents.
+    InvalidCrc,
+    /// A PNG chunk in the wrong place, including before IHDR.
+    ChunkOutOfOrder,
+    /// A PNG chunk that may only appear once, appearing again.
+    DuplicateChunk,
+    /// A PNG chunk that can't be decoded, such as a tEXt chunk without a
+    /// keyword or a gAMA chunk of the wrong length. It is ignored.
+    MalformedChunk,
+    /// A PLTE chunk with the wrong length or too many entries. It is ignored.
+    InvalidPalette,
+    /// The data ends before the image does.
+    Truncated,
+}
+
+/// A problem found while reading an image, which reading carried on past.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Warning {
+    /// Where the problem is in the image data.
+    pub offset: usize,
+    pub kind: WarningKind,
+    /// A description of the problem, the same as the error it would be under
+    /// a stricter [`Strictness`].
+    pub message: String,
+}
+
+/// Collects warnings, or turns problems into errors, depending on the
+/// strictness.
+#[derive(Debug)]
+pub(crate) struct Diagnostics {
+    strictness: Strictness,
+    pub warnings: Vec<Warning>,
+}
+
+impl Diagnostics {
+    pub fn new(strictness: Strictness) -> Self {
+        Diagnostics {
+            strictness,
+            warnings: Vec::new(),
+        }
+    }
+
+    /// Report a problem, which reading can carry on past at the `tolerated`
+    /// strictness level or any more forgiving one. Returns the error if the
+    /// current strictness doesn't tolerate it, or records a warning if it
+    /// does.
+    pub fn report<E: Display>(
+        &mut self,
+        error: E,
+        kind: WarningKind,
+        offset: usize,
+        tolerated: Strictness,
+    ) -> Result<(), E> {
+        if self.strictness < tolerated {
+            return Err(error);
+        }
+        self.warnings.push(Warning {
+            offset,
+            kind,
+            message: error.to_string(),
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        let mut diagnostics = Diagnostics::new(Strictness::Normal);
+        let kind = WarningKind::ExtraneousBytes;
+        assert_eq!(
+            diagnostics.report("minor", kind, 1, Strictness::Normal),
+            Ok(())
+        );
+        assert_eq!(
+            diagnostics.report("major", kind, 2, Strictness::Lenient),
+            Err("major")
+        );
+        assert_eq!(
+            diagnostics.warnings,
+            vec![Warning {
+                offset: 1,
+                kind,
+                message: "minor".to_string()
+            }]
+        );
+
+        let mut diagnostics = Diagnostics::new(Strictness::Strict);
+        assert_eq!(
+            diagnostics.report("minor", kind, 1, Strictness::Normal),
+            Err("minor")
+        );
+        assert!(diagnostics.warnings.is_empty());
+    }
+}
@@ -0,0 +1,299 @@
+//! Editing XMP packets in place.
+//!
+//! XMP repeats much of the EXIF data as properties, so the location and
+//! serial numbers that [`crate::exif::remove_private_tags`] takes out of the
+//! EXIF data have to come out of the XMP packet too. As with EXIF data,
+//! nothing is moved: removed properties are overwritten with spaces, which
+//! XML ignores between tags and attributes, so the packet stays the same
+//! length.
+
+use std::ops::Range;
+
+use memchr::memmem;
+
+/// The properties to remove, by namespace: the GPS location, and the camera
+/// and lens serial numbers. A name ending in `*` stands for every property
+/// starting with the rest of it.
+const PRIVATE_PROPERTIES: &[(&[u8], &[&[u8]])] = &[
+    (b"http://ns.adobe.com/exif/1.0/", &[b"GPS*"]),
+    (
+        b"http://ns.adobe.com/exif/1.0/aux/",
+        &[b"SerialNumber", b"LensSerialNumber"],
+    ),
+    (
+        b"http://cipa.jp/exif/1.0/",
+        &[b"BodySerialNumber", b"LensSerialNumber"],
+    ),
+];
+
+/// A tag in the XML, which is as much of its structure as we need.
+#[derive(Debug)]
+struct Tag {
+    range: Range<usize>,
+    name: Range<usize>,
+    kind: TagKind,
+    attributes: Vec<Attribute>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TagKind {
+    Start,
+    /// A tag that closes itself, like `<exif:GPSLatitude/>`.
+    Empty,
+    End,
+}
+
+#[derive(Debug)]
+struct Attribute {
+    /// The attribute, from the whitespace before it to its closing quote.
+    range: Range<usize>,
+    name: Range<usize>,
+    value: Range<usize>,
+}
+
+/// Remove the GPS properties and the serial numbers from an XMP packet,
+/// whether they are written as attributes or as elements. The namespace
+/// prefixes are looked up, so they don't have to be the usual ones.
+pub(crate) fn remove_private_properties(xmp: &mut [u8]) {
+    let tags = read_tags(xmp);
+    let namespaces: Vec<(&[u8], &[u8])> = tags
+        .iter()
+        .flat_map(|tag| &tag.attributes)
+        .filter_map(|a| {
+            let prefix = xmp[a.name.clone()].strip_prefix(b"xmlns:")?;
+            Some((prefix, &xmp[a.value.clone()]))
+        })
+        .collect();
+    let is_private = |name: &[u8]| {
+        let Some(colon) = memchr::memchr(b':', name) else {
+            return false;
+        };
+        let (prefix, local) = (&name[..colon], &name[colon + 1..]);
+        namespaces
+            .iter()
+            .filter(|(p, _)| *p == prefix)
+            .filter_map(|(_, uri)| PRIVATE_PROPERTIES.iter().find(|(ns, _)| ns == uri))
+            .flat_map(|(_, names)| names.iter())
+            .any(|n| match n.strip_suffix(b"*") {
+                Some(start) => local.starts_with(start),
+                None => local == *n,
+            })
+    };
+
+    let mut blank = Vec::new();
+    let mut tags = tags.iter();
+    while let Some(tag) = tags.next() {
+        let name = &xmp[tag.name.clone()];
+        match tag.kind {
+            TagKind::End => {}
+            TagKind::Empty if is_private(name) => blank.push(tag.range.clone()),
+            TagKind::Start if is_private(name) => {
+                let end = element_end(xmp, name, &mut tags);
+                blank.push(tag.range.start..end);
+            }
+            _ => blank.extend(
+                tag.attributes
+                    .iter()
+                    .filter(|a| is_private(&xmp[a.name.clone()]))
+                    .map(|a| a.range.clone()),
+            ),
+        }
+    }
+    for range in blank {
+        xmp[range].fill(b' ');
+    }
+}
+
+/// Find where the element with the given name ends, taking the tags up to
+/// its end tag. An element that is never closed runs to the end of the data.
+fn element_end<'a>(xmp: &[u8], name: &[u8], tags: &mut impl Iterator<Item = &'a Tag>) -> usize {
+    let mut depth = 1;
+    for tag in tags {
+        if &xmp[tag.name.clone()] != name {
+            continue;
+        }
+        match tag.kind {
+            TagKind::Start => depth += 1,
+            TagKind::Empty => {}
+            TagKind::End => {
+                depth -= 1;
+                if depth == 0 {
+                    return tag.range.end;
+                }
+            }
+        }
+    }
+    xmp.len()
+}
+
+/// Find the start and end tags in the XML, skipping comments, processing
+/// instructions, CDATA sections and declarations.
+fn read_tags(xmp: &[u8]) -> Vec<Tag> {
+    let mut tags = Vec::new();
+    let mut position = 0;
+    while let Some(found) = memchr::memchr(b'<', &xmp[position..]) {
+        let start = position + found;
+        let rest = &xmp[start..];
+        let skip_to = |end: &[u8]| memmem::find(rest, end).map(|i| start + i + end.len());
+        let next = if rest.starts_with(b"<!--") {
+            skip_to(b"-->")
+        } else if rest.starts_with(b"<![CDATA[") {
+            skip_to(b"]]>")
+        } else if rest.starts_with(b"<?") {
+            skip_to(b"?>")
+        } else if rest.starts_with(b"<!") {
+            skip_to(b">")
+        } else if let Some(tag) = read_tag(xmp, start) {
+            let end = tag.range.end;
+            tags.push(tag);
+            Some(end)
+        } else {
+            // Not a tag after all.
+            Some(start + 1)
+        };
+        let Some(next) = next else {
+            break;
+        };
+        position = next;
+    }
+    tags
+}
+
+/// Read the tag starting at `start`, or return `None` if it isn't one.
+fn read_tag(xmp: &[u8], start: usize) -> Option<Tag> {
+    let is_end = xmp.get(start + 1) == Some(&b'/');
+    let name = read_name(xmp, start + 1 + usize::from(is_end))?;
+    let mut i = name.end;
+    let mut attributes = Vec::new();
+    loop {
+        let space = i;
+        i = skip_space(xmp, i);
+        let kind = match (xmp.get(i)?, xmp.get(i + 1)) {
+            (b'>', _) => TagKind::Start,
+            (b'/', Some(b'>')) => TagKind::Empty,
+            _ => {
+                let name = read_name(xmp, i)?;
+                i = skip_space(xmp, name.end);
+                if xmp.get(i) != Some(&b'=') {
+                    return None;
+                }
+                i = skip_space(xmp, i + 1);
+                let quote = *xmp.get(i).filter(|&&q| q == b'"' || q == b'\'')?;
+                let value_start = i + 1;
+                let value_end = value_start + memchr::memchr(quote, &xmp[value_start..])?;
+                i = value_end + 1;
+                attributes.push(Attribute {
+                    range: space..i,
+                    name,
+                    value: value_start..value_end,
+                });
+                continue;
+            }
+        };
+        let (kind, end) = match kind {
+            TagKind::Start if is_end => (TagKind::End, i + 1),
+            TagKind::Start => (TagKind::Start, i + 1),
+            _ => (TagKind::Empty, i + 2),
+        };
+        return Some(Tag {
+            range: start..end,
+            name,
+            kind,
+            attributes,
+        });
+    }
+}
+
+/// Read a tag or attribute name starting at `start`.
+fn read_name(xmp: &[u8], start: usize) -> Option<Range<usize>> {
+    let len = xmp[start.min(xmp.len())..]
+        .iter()
+        .take_while(|&&b| !b.is_ascii_whitespace() && !b"/>=<'\"".contains(&b))
+        .count();
+    (len > 0).then_some(start..start + len)
+}
+
+/// Skip over any whitespace starting at `start`.
+fn skip_space(xmp: &[u8], start: usize) -> usize {
+    let len = xmp[start.min(xmp.len())..]
+        .iter()
+        .take_while(|b| b.is_ascii_whitespace())
+        .count();
+    start + len
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// An XMP packet with GPS properties and serial numbers, both as
+    /// attributes and as elements, alongside ones to keep.
+    pub(crate) const XMP: &[u8] = br#"<?xpacket begin="" id="W5M0MpCehiHzreSzNTczkc9d"?>
+<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about=""
+    xmlns:exif="http://ns.adobe.com/exif/1.0/"
+    xmlns:aux="http://ns.adobe.com/exif/1.0/aux/"
+    xmlns:tiff="http://ns.adobe.com/tiff/1.0/"
+    exif:GPSLatitude="51,30.4N" exif:ExposureTime="1/125"
+    aux:SerialNumber='0123456789' tiff:Orientation="1">
+   <exif:GPSLongitude>0,7.6W</exif:GPSLongitude>
+   <exif:GPSVersionID/>
+   <aux:LensSerialNumber>
+    <rdf:Alt><rdf:li xml:lang="x-default">AB-1234</rdf:li></rdf:Alt>
+   </aux:LensSerialNumber>
+   <exif:Flash rdf:parseType="Resource"><exif:Fired>False</exif:Fired></exif:Flash>
+  </rdf:Description>
+  <rdf:Description rdf:about="" xmlns:ex="http://cipa.jp/exif/1.0/">
+   <ex:BodySerialNumber>9876</ex:BodySerialNumber>
+   <!-- <exif:GPSAltitude>is not a property in a comment</exif:GPSAltitude> -->
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>
+<?xpacket end="w"?>"#;
+
+    #[test]
+    fn test_remove_private_properties() {
+        let mut xmp = XMP.to_vec();
+        remove_private_properties(&mut xmp);
+        assert_eq!(xmp.len(), XMP.len());
+        let xmp = String::from_utf8(xmp).unwrap();
+        for removed in [
+            "51,30.4N",
+            "0123456789",
+            "0,7.6W",
+            "GPSVersionID",
+            "AB-1234",
+            "9876",
+        ] {
+            assert!(!xmp.contains(removed), "{} is still there", removed);
+        }
+        for kept in [
+            r#"exif:ExposureTime="1/125""#,
+            r#"tiff:Orientation="1">"#,
+            "<exif:Fired>False</exif:Fired>",
+            "is not a property in a comment",
+        ] {
+            assert!(xmp.contains(kept), "{} was removed", kept);
+        }
+        // What's left is still well-formed, with the same tags.
+        let tags = read_tags(xmp.as_bytes());
+        assert!(tags.iter().all(|tag| tag.kind != TagKind::Empty));
+        assert_eq!(tags.len(), 12);
+    }
+
+    #[test]
+    fn test_unreadable_xmp() {
+        for xmp in [&b""[..], b"<", b"<a b", b"<a b='c", b"< >", b"<exif:GPS"] {
+            let mut copy = xmp.to_vec();
+            remove_private_properties(&mut copy);
+            assert_eq!(copy, xmp);
+        }
+        // An unclosed private element runs to the end.
+        let mut xmp = br#"<a xmlns:e="http://ns.adobe.com/exif/1.0/"><e:GPSAltitude>1"#.to_vec();
+        remove_private_properties(&mut xmp);
+        let (kept, removed) = xmp.split_at(xmp.len() - 16);
+        assert!(kept.ends_with(b"/\">"));
+        assert_eq!(removed, [b' '; 16]);
+    }
+}